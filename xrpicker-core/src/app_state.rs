@@ -6,12 +6,18 @@ use std::{iter, path::PathBuf};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
-use crate::{platform::PlatformRuntime, Error, ManifestError, Platform};
+use crate::{
+    history::ActivationRecord, layer::ApiLayer, platform::PlatformRuntime, ActivationStrategy,
+    Error, ManifestError, Platform,
+};
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct PersistentAppState {
-    /// The extra paths provided by the user: the only thing we really want to serialize
+    /// The extra paths provided by the user
     pub extra_paths: Vec<PathBuf>,
+    /// How the user last chose to make runtimes active
+    #[serde(default)]
+    pub activation_strategy: ActivationStrategy,
 }
 
 impl PersistentAppState {
@@ -62,18 +68,28 @@ pub struct AppState<T: Platform> {
     pub runtimes: Vec<T::PlatformRuntimeType>,
     pub nonfatal_errors: Vec<ManifestError>,
     pub active_data: T::PlatformActiveData,
+    pub api_layers: Vec<ApiLayer>,
+    /// Changes made to the active runtime, oldest first
+    pub activation_history: Vec<ActivationRecord>,
+    /// What the last change made did, for the user to see
+    pub status: Option<String>,
 }
 
 impl<T: Platform> AppState<T> {
     /// Try creating state from scratch
     pub fn new(platform: &T) -> Result<Self, Error> {
-        let (runtimes, nonfatal_errors) =
+        let (runtimes, mut nonfatal_errors) =
             platform.find_available_runtimes(Box::new(iter::empty()))?;
         let active_data = platform.get_active_data();
+        let (api_layers, mut layer_errors) = platform.find_api_layers()?;
+        nonfatal_errors.append(&mut layer_errors);
         Ok(Self {
             runtimes,
             nonfatal_errors,
             active_data,
+            api_layers,
+            activation_history: platform.get_activation_history()?,
+            status: None,
         })
     }
 
@@ -81,13 +97,18 @@ impl<T: Platform> AppState<T> {
         platform: &T,
         persistent_state: &PersistentAppState,
     ) -> Result<Self, Error> {
-        let (runtimes, nonfatal_errors) =
+        let (runtimes, mut nonfatal_errors) =
             platform.find_available_runtimes(persistent_state.iterate_extra_paths())?;
         let active_data = platform.get_active_data();
+        let (api_layers, mut layer_errors) = platform.find_api_layers()?;
+        nonfatal_errors.append(&mut layer_errors);
         Ok(Self {
             runtimes,
             nonfatal_errors,
             active_data,
+            api_layers,
+            activation_history: platform.get_activation_history()?,
+            status: None,
         })
     }
 
@@ -98,11 +119,15 @@ impl<T: Platform> AppState<T> {
         platform: &T,
         persistent_state: Option<&PersistentAppState>,
     ) -> Result<Self, Error> {
-        let (new_runtimes, new_nonfatal_errors) =
+        let (new_runtimes, mut new_nonfatal_errors) =
             platform.find_available_runtimes(persistent_state.iterate_extra_paths())?;
 
         let active_data = platform.get_active_data();
 
+        // Layers have no user-visible identity to preserve, so just replace them
+        let (api_layers, mut layer_errors) = platform.find_api_layers()?;
+        new_nonfatal_errors.append(&mut layer_errors);
+
         // start with existing runtimes
         let runtimes = self
             .runtimes
@@ -122,6 +147,9 @@ impl<T: Platform> AppState<T> {
             runtimes,
             nonfatal_errors: new_nonfatal_errors,
             active_data,
+            api_layers,
+            activation_history: platform.get_activation_history()?,
+            status: self.status,
         })
     }
 }
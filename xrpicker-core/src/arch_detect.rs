@@ -1,31 +1,520 @@
 // Copyright 2022-2023, Collabora, Ltd.
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-//! Not actually windows specific, but not yet used on Linux.
+//! Identify the CPU architecture (and version) of runtime binaries, whatever platform we are
+//! running on.
 
+use crate::ld_so::{read_u32, ElfDynamicInfo};
+#[cfg(windows)]
 use crate::{runtime::BaseRuntime, Error, ManifestError};
-use object::{self, read::Object};
+use object::{
+    self, pe,
+    read::{
+        pe::{ImageNtHeaders, PeFile, ResourceNameOrId},
+        Object, ObjectSection,
+    },
+    FileKind, LittleEndian,
+};
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+#[cfg(windows)]
 use std::{fs, path::Path};
 
-/// A single manifest may only be one of these values.
-pub(crate) enum RuntimeBitness {
-    /// Uses shared library search path to find the right binary per arch
-    Universal,
-    /// Points to a 32-bit runtime
-    BitWidth32,
-    /// Points to a 64-bit runtime
-    BitWidth64,
+/// The CPU architecture a binary is built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Architecture {
+    /// 32-bit x86, aka i386 or i686
+    X86,
+    /// 64-bit x86, aka AMD64 or x64
+    X86_64,
+    /// 64-bit ARM, aka ARM64
+    Aarch64,
+    /// 32-bit ARM
+    Armv7,
+    /// Windows "emulation compatible" ARM64 code, which can interoperate with x64 code
+    Arm64EC,
+    Riscv32,
+    Riscv64,
+    PowerPc,
+    PowerPc64,
+    Mips,
+    Mips64,
+    LoongArch64,
+    S390x,
+    Sparc64,
+    /// Some other or unrecognized architecture
+    Unknown,
 }
 
-/// Investigate a manifest and the runtime binary to which it refers, to identify whether it is
-/// 32-bit, 64-bit, or universal (using shared library search path)
-pub(crate) fn get_runtime_bitness(manifest_path: &Path) -> Result<RuntimeBitness, ManifestError> {
+/// All the architectures we know, which is all but `Unknown`
+const KNOWN_ARCHITECTURES: [Architecture; 14] = [
+    Architecture::X86,
+    Architecture::X86_64,
+    Architecture::Aarch64,
+    Architecture::Armv7,
+    Architecture::Arm64EC,
+    Architecture::Riscv32,
+    Architecture::Riscv64,
+    Architecture::PowerPc,
+    Architecture::PowerPc64,
+    Architecture::Mips,
+    Architecture::Mips64,
+    Architecture::LoongArch64,
+    Architecture::S390x,
+    Architecture::Sparc64,
+];
+
+impl Display for Architecture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Matching `std::env::consts::ARCH` where there is an equivalent
+        let name = match self {
+            Architecture::X86 => "x86",
+            Architecture::X86_64 => "x86_64",
+            Architecture::Aarch64 => "aarch64",
+            Architecture::Armv7 => "armv7",
+            Architecture::Arm64EC => "arm64ec",
+            Architecture::Riscv32 => "riscv32",
+            Architecture::Riscv64 => "riscv64",
+            Architecture::PowerPc => "powerpc",
+            Architecture::PowerPc64 => "powerpc64",
+            Architecture::Mips => "mips",
+            Architecture::Mips64 => "mips64",
+            Architecture::LoongArch64 => "loongarch64",
+            Architecture::S390x => "s390x",
+            Architecture::Sparc64 => "sparc64",
+            Architecture::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Architecture {
+    type Err = String;
+
+    /// Parse either our name for an architecture, or the one the OpenXR loader uses
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KNOWN_ARCHITECTURES
+            .into_iter()
+            .find(|arch| arch.to_string() == s || arch.openxr_abi_name() == Some(s))
+            .ok_or_else(|| format!("Unknown architecture '{}'", s))
+    }
+}
+
+impl Architecture {
+    /// The architecture this program was built for
+    pub fn host() -> Self {
+        if cfg!(target_arch = "x86") {
+            Architecture::X86
+        } else if cfg!(target_arch = "x86_64") {
+            Architecture::X86_64
+        } else if cfg!(target_arch = "aarch64") {
+            Architecture::Aarch64
+        } else if cfg!(target_arch = "arm") {
+            Architecture::Armv7
+        } else if cfg!(target_arch = "riscv32") {
+            Architecture::Riscv32
+        } else if cfg!(target_arch = "riscv64") {
+            Architecture::Riscv64
+        } else if cfg!(target_arch = "powerpc") {
+            Architecture::PowerPc
+        } else if cfg!(target_arch = "powerpc64") {
+            Architecture::PowerPc64
+        } else if cfg!(target_arch = "mips") {
+            Architecture::Mips
+        } else if cfg!(target_arch = "mips64") {
+            Architecture::Mips64
+        } else if cfg!(target_arch = "loongarch64") {
+            Architecture::LoongArch64
+        } else if cfg!(target_arch = "s390x") {
+            Architecture::S390x
+        } else if cfg!(target_arch = "sparc64") {
+            Architecture::Sparc64
+        } else {
+            Architecture::Unknown
+        }
+    }
+
+    /// Is this a 64-bit architecture?
+    pub fn is_64(&self) -> bool {
+        matches!(
+            self,
+            Architecture::X86_64
+                | Architecture::Aarch64
+                | Architecture::Arm64EC
+                | Architecture::Riscv64
+                | Architecture::PowerPc64
+                | Architecture::Mips64
+                | Architecture::LoongArch64
+                | Architecture::S390x
+                | Architecture::Sparc64
+        )
+    }
+
+    /// Can applications on a system with this (host) architecture load a library built for `other`?
+    ///
+    /// This includes libraries that only 32-bit applications can load, like x86 on x86_64.
+    pub fn can_load(&self, other: Architecture) -> bool {
+        if *self == other {
+            return true;
+        }
+        match self {
+            Architecture::X86_64 => other == Architecture::X86,
+            // Windows on ARM can emulate x86 and x64, and ARM64EC is made to mix with x64
+            Architecture::Aarch64 if cfg!(windows) => matches!(
+                other,
+                Architecture::Arm64EC
+                    | Architecture::X86_64
+                    | Architecture::X86
+                    | Architecture::Armv7
+            ),
+            Architecture::Aarch64 => other == Architecture::Armv7,
+            Architecture::PowerPc64 => other == Architecture::PowerPc,
+            Architecture::Mips64 => other == Architecture::Mips,
+            _ => false,
+        }
+    }
+
+    /// The name the OpenXR loader uses for this architecture, as in `active_runtime.<name>.json`
+    pub fn openxr_abi_name(&self) -> Option<&'static str> {
+        match self {
+            Architecture::X86 => Some("i686"),
+            Architecture::X86_64 => Some("x86_64"),
+            Architecture::Aarch64 => Some("aarch64"),
+            Architecture::Armv7 => Some("armv7a-vfp"),
+            Architecture::Riscv32 => Some("riscv32"),
+            Architecture::Riscv64 => Some("riscv64"),
+            Architecture::PowerPc => Some("ppc"),
+            Architecture::PowerPc64 if cfg!(target_endian = "little") => Some("ppc64el"),
+            Architecture::PowerPc64 => Some("ppc64"),
+            Architecture::Mips => Some("mips"),
+            Architecture::Mips64 => Some("mips64"),
+            Architecture::LoongArch64 => Some("loongarch64"),
+            Architecture::S390x => Some("s390x"),
+            Architecture::Sparc64 => Some("sparc64"),
+            Architecture::Arm64EC | Architecture::Unknown => None,
+        }
+    }
+
+    /// The 32-bit architectures that applications on a system with this (host) architecture
+    /// might also use, like x86 on x86_64
+    pub fn companion_architectures(&self) -> Vec<Architecture> {
+        KNOWN_ARCHITECTURES
+            .into_iter()
+            .filter(|other| !other.is_64() && other != self && self.can_load(*other))
+            .collect()
+    }
+
+    fn from_object(architecture: object::Architecture) -> Self {
+        match architecture {
+            object::Architecture::I386 => Architecture::X86,
+            object::Architecture::X86_64 => Architecture::X86_64,
+            object::Architecture::Aarch64 => Architecture::Aarch64,
+            object::Architecture::Arm => Architecture::Armv7,
+            object::Architecture::Riscv32 => Architecture::Riscv32,
+            object::Architecture::Riscv64 => Architecture::Riscv64,
+            object::Architecture::PowerPc => Architecture::PowerPc,
+            object::Architecture::PowerPc64 => Architecture::PowerPc64,
+            object::Architecture::Mips => Architecture::Mips,
+            object::Architecture::Mips64 => Architecture::Mips64,
+            object::Architecture::LoongArch64 => Architecture::LoongArch64,
+            object::Architecture::S390x => Architecture::S390x,
+            object::Architecture::Sparc64 => Architecture::Sparc64,
+            _ => Architecture::Unknown,
+        }
+    }
+
+    fn from_pe_machine(machine: u16) -> Self {
+        match machine {
+            pe::IMAGE_FILE_MACHINE_I386 => Architecture::X86,
+            pe::IMAGE_FILE_MACHINE_AMD64 => Architecture::X86_64,
+            pe::IMAGE_FILE_MACHINE_ARM64 => Architecture::Aarch64,
+            pe::IMAGE_FILE_MACHINE_ARMNT => Architecture::Armv7,
+            IMAGE_FILE_MACHINE_ARM64EC => Architecture::Arm64EC,
+            pe::IMAGE_FILE_MACHINE_RISCV32 => Architecture::Riscv32,
+            pe::IMAGE_FILE_MACHINE_RISCV64 => Architecture::Riscv64,
+            _ => Architecture::Unknown,
+        }
+    }
+}
+
+/// Not (yet) known to the `object` crate
+const IMAGE_FILE_MACHINE_ARM64EC: u16 = 0xA641;
+
+/// Read the machine field of a PE file directly, since `object` does not know all the values
+fn pe_machine(data: &[u8]) -> Option<u16> {
+    let nt_headers_offset = pe::ImageDosHeader::parse(data).ok()?.nt_headers_offset() as usize;
+    // The machine is the first field of the file header, right after the 4-byte signature
+    let machine_offset = nt_headers_offset.checked_add(4)?;
+    let bytes = data.get(machine_offset..machine_offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+/// Identify the architecture of a binary file's contents, if it is a binary at all
+pub(crate) fn binary_architecture(data: &[u8]) -> Option<Architecture> {
+    match FileKind::parse(data).ok()? {
+        FileKind::Pe32 | FileKind::Pe64 => pe_machine(data).map(Architecture::from_pe_machine),
+        _ => object::File::parse(data)
+            .ok()
+            .map(|f| Architecture::from_object(f.architecture())),
+    }
+}
+
+/// Version information from the metadata of a runtime library
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LibraryVersion {
+    /// The version of the library file
+    pub version: Option<String>,
+    /// The product the library is part of: from Windows version resources, and the package
+    /// notes some Linux distributions add
+    pub product_name: Option<String>,
+    /// Who made it: only from Windows version resources
+    pub company_name: Option<String>,
+}
+
+impl Display for LibraryVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.version.as_deref().unwrap_or("unknown"))?;
+        let details: Vec<&str> = [&self.product_name, &self.company_name]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the version information of a binary file's contents, if it has any.
+///
+/// For PE files, this is the `VS_VERSION_INFO` resource. For ELF files, it is the package
+/// note (`.note.package`) if any, and the version suffix of the file name (if given) or soname.
+pub(crate) fn binary_version(data: &[u8], file_name: Option<&str>) -> Option<LibraryVersion> {
+    match FileKind::parse(data).ok()? {
+        FileKind::Pe32 => pe_version::<pe::ImageNtHeaders32>(data),
+        FileKind::Pe64 => pe_version::<pe::ImageNtHeaders64>(data),
+        FileKind::Elf32 | FileKind::Elf64 => elf_version(data, file_name),
+        _ => None,
+    }
+}
+
+fn le_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+/// Round up to a multiple of 4, as the blocks of a version resource are aligned
+fn align4(offset: usize) -> usize {
+    offset.saturating_add(3) & !3
+}
+
+/// Signature at the start of a `VS_FIXEDFILEINFO`
+const VS_FFI_SIGNATURE: u32 = 0xFEEF04BD;
+
+/// A block of a `VS_VERSIONINFO` resource: a key, a value, and child blocks of the same form
+struct VersionBlock<'a> {
+    key: String,
+    value: &'a [u8],
+    children: &'a [u8],
+}
+
+impl<'a> VersionBlock<'a> {
+    /// Parse the block at the start of `data`, returning it and its length.
+    fn parse(data: &'a [u8]) -> Option<(Self, usize)> {
+        let length = le_u16(data, 0)? as usize;
+        let value_length = le_u16(data, 2)? as usize;
+        // The value length of text is in UTF-16 code units
+        let value_length = if le_u16(data, 4)? == 1 {
+            value_length * 2
+        } else {
+            value_length
+        };
+        let data = data.get(..length)?;
+        let mut key = vec![];
+        let mut offset = 6;
+        loop {
+            let c = le_u16(data, offset)?;
+            offset += 2;
+            if c == 0 {
+                break;
+            }
+            key.push(c);
+        }
+        let value_start = align4(offset).min(length);
+        let value_end = (value_start + value_length).min(length);
+        let children_start = align4(value_end).min(length);
+        Some((
+            Self {
+                key: String::from_utf16_lossy(&key),
+                value: &data[value_start..value_end],
+                children: &data[children_start..],
+            },
+            length,
+        ))
+    }
+
+    fn children(&self) -> impl Iterator<Item = VersionBlock<'a>> {
+        let mut rest = self.children;
+        std::iter::from_fn(move || {
+            let (block, length) = VersionBlock::parse(rest)?;
+            rest = rest.get(align4(length)..).unwrap_or_default();
+            Some(block)
+        })
+    }
+
+    /// The value as text, which some tools terminate with NUL (or several)
+    fn text(&self) -> String {
+        let units: Vec<u16> = self
+            .value
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|c| *c != 0)
+            .collect();
+        String::from_utf16_lossy(&units).trim().to_owned()
+    }
+
+    /// The file version from the value of the root block, a `VS_FIXEDFILEINFO`
+    fn fixed_file_version(&self) -> Option<String> {
+        if read_u32(self.value, 0, false)? != VS_FFI_SIGNATURE {
+            return None;
+        }
+        let (high, low) = (
+            read_u32(self.value, 8, false)?,
+            read_u32(self.value, 12, false)?,
+        );
+        Some(format!(
+            "{}.{}.{}.{}",
+            high >> 16,
+            high & 0xffff,
+            low >> 16,
+            low & 0xffff
+        ))
+    }
+}
+
+/// The data of the first version resource in a PE file
+fn pe_version_resource<Pe: ImageNtHeaders>(data: &[u8]) -> Option<&[u8]> {
+    let file = PeFile::<Pe>::parse(data).ok()?;
+    let sections = file.section_table();
+    let directory = file
+        .data_directories()
+        .resource_directory(data, &sections)
+        .ok()??;
+    // Resources are in a tree by type, then name (or ID), then language
+    let by_type = directory.root().ok()?;
+    let version = by_type
+        .entries
+        .iter()
+        .find(|e| matches!(e.name_or_id(), ResourceNameOrId::Id(pe::RT_VERSION)))?;
+    let by_name = version.data(directory).ok()?.table()?;
+    let by_language = by_name.entries.first()?.data(directory).ok()?.table()?;
+    let entry = by_language.entries.first()?.data(directory).ok()?.data()?;
+    let resource = sections.pe_data_at(data, entry.offset_to_data.get(LittleEndian))?;
+    resource.get(..entry.size.get(LittleEndian) as usize)
+}
+
+fn pe_version<Pe: ImageNtHeaders>(data: &[u8]) -> Option<LibraryVersion> {
+    resource_version(pe_version_resource::<Pe>(data)?)
+}
+
+/// The version information in the data of a `VS_VERSIONINFO` resource
+fn resource_version(resource: &[u8]) -> Option<LibraryVersion> {
+    let (root, _) = VersionBlock::parse(resource)?;
+    if root.key != "VS_VERSION_INFO" {
+        return None;
+    }
+    // The strings are in a table per language and code page: prefer the first
+    let mut strings: HashMap<String, String> = HashMap::new();
+    for string in root
+        .children()
+        .filter(|block| block.key == "StringFileInfo")
+        .flat_map(|block| block.children())
+        .flat_map(|table| table.children())
+    {
+        let text = string.text();
+        if !text.is_empty() {
+            strings.entry(string.key).or_insert(text);
+        }
+    }
+    Some(LibraryVersion {
+        version: strings
+            .remove("FileVersion")
+            .or_else(|| root.fixed_file_version()),
+        product_name: strings.remove("ProductName"),
+        company_name: strings.remove("CompanyName"),
+    })
+}
+
+/// Note type of the JSON package description many Linux distributions add to binaries,
+/// in `.note.package`: see <https://systemd.io/ELF_PACKAGE_METADATA/>
+const NT_FDO_PACKAGING_METADATA: u32 = 0xcafe1a7e;
+
+/// The name and version of the package an ELF file is from, if it says
+fn elf_package(file: &object::File) -> Option<(Option<String>, Option<String>)> {
+    let data = file.section_by_name(".note.package")?.data().ok()?;
+    package_note(data, !file.is_little_endian())
+}
+
+/// The name and version in the package note among the notes in `data`
+fn package_note(data: &[u8], big_endian: bool) -> Option<(Option<String>, Option<String>)> {
+    // Each note is the sizes of its name and description, its type, then the name and the
+    // description, each padded to 4 bytes
+    let mut offset = 0;
+    while offset < data.len() {
+        let name_size = read_u32(data, offset, big_endian)? as usize;
+        let desc_size = read_u32(data, offset + 4, big_endian)? as usize;
+        let note_type = read_u32(data, offset + 8, big_endian)?;
+        let name_start = offset + 12;
+        let desc_start = align4(name_start.checked_add(name_size)?);
+        let desc = data.get(desc_start..desc_start.checked_add(desc_size)?)?;
+        if note_type == NT_FDO_PACKAGING_METADATA
+            && data.get(name_start..name_start + name_size)? == b"FDO\0"
+        {
+            let desc = std::str::from_utf8(desc).ok()?.trim_end_matches('\0');
+            let package: serde_json::Value = serde_json::from_str(desc).ok()?;
+            let field = |name| package.get(name)?.as_str().map(str::to_owned);
+            return Some((field("name"), field("version")));
+        }
+        offset = align4(desc_start + desc_size);
+    }
+    None
+}
+
+/// The version in a shared library name like `libfoo.so.1.2`
+fn so_version(name: &str) -> Option<String> {
+    name.split_once(".so.")
+        .map(|(_, version)| version.to_owned())
+        .filter(|version| !version.is_empty())
+}
+
+fn elf_version(data: &[u8], file_name: Option<&str>) -> Option<LibraryVersion> {
+    let file = object::File::parse(data).ok()?;
+    let (product_name, package_version) = elf_package(&file).unwrap_or_default();
+    let version = package_version
+        .or_else(|| file_name.and_then(so_version))
+        .or_else(|| so_version(ElfDynamicInfo::parse(data)?.soname.as_deref()?));
+    if version.is_none() && product_name.is_none() {
+        return None;
+    }
+    Some(LibraryVersion {
+        version,
+        product_name,
+        company_name: None,
+    })
+}
+
+/// Investigate a manifest and the runtime binary to which it refers, to identify its architecture,
+/// or `None` if it is universal (using shared library search path to find the right binary per arch)
+#[cfg(windows)]
+pub(crate) fn get_runtime_architecture(
+    manifest_path: &Path,
+) -> Result<Option<Architecture>, ManifestError> {
     let runtime =
         BaseRuntime::new(manifest_path).map_err(|e| ManifestError(manifest_path.to_owned(), e))?;
     let library_path = runtime.resolve_library_path();
     if !library_path.is_absolute() {
         // If we can't resolve it, it must be universal
-        return Ok(RuntimeBitness::Universal);
+        return Ok(None);
     }
 
     let make_err = || {
@@ -36,18 +525,17 @@ pub(crate) fn get_runtime_bitness(manifest_path: &Path) -> Result<RuntimeBitness
     };
 
     let bin_data = fs::read(&library_path).map_err(|_| make_err())?;
-    let obj_file = object::File::parse(&*bin_data).map_err(|_| make_err())?;
-    if obj_file.is_64() {
-        Ok(RuntimeBitness::BitWidth64)
-    } else {
-        Ok(RuntimeBitness::BitWidth32)
-    }
+    binary_architecture(&bin_data)
+        .map(Some)
+        .ok_or_else(make_err)
 }
 
+#[cfg(windows)]
 pub(crate) trait PushUnique<T> {
     fn push_unique(&mut self, val: T);
 }
 
+#[cfg(windows)]
 impl<T> PushUnique<T> for Vec<T>
 where
     T: Eq,
@@ -59,3 +547,298 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_binaries::{elf_library, pe_dll};
+
+    fn pad4(data: &mut Vec<u8>) {
+        data.resize(align4(data.len()), 0);
+    }
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    /// A version block, with a text value if `text` is true
+    fn block(key: &str, value: &[u8], text: bool, children: &[Vec<u8>]) -> Vec<u8> {
+        let value_length = if text { value.len() / 2 } else { value.len() };
+        let mut data = vec![0, 0];
+        data.extend((value_length as u16).to_le_bytes());
+        data.extend(u16::from(text).to_le_bytes());
+        data.extend(utf16(key));
+        pad4(&mut data);
+        data.extend(value);
+        for child in children {
+            pad4(&mut data);
+            data.extend(child);
+        }
+        let length = (data.len() as u16).to_le_bytes();
+        data[..2].copy_from_slice(&length);
+        data
+    }
+
+    fn version_info(file_version: bool) -> Vec<u8> {
+        let mut fixed = vec![];
+        for value in [VS_FFI_SIGNATURE, 0x10000, 0x0002_0003, 0x0004_0005] {
+            fixed.extend(value.to_le_bytes());
+        }
+        fixed.resize(52, 0);
+        let mut strings = vec![
+            block("CompanyName", &utf16("Vendor"), true, &[]),
+            block("ProductName", &utf16("Runtime\0\0"), true, &[]),
+        ];
+        if file_version {
+            strings.push(block("FileVersion", &utf16("1.2.3"), true, &[]));
+        }
+        let table = block("040904b0", &[], true, &strings);
+        block(
+            "VS_VERSION_INFO",
+            &fixed,
+            false,
+            &[block("StringFileInfo", &[], true, &[table])],
+        )
+    }
+
+    #[test]
+    fn version_resource() {
+        assert_eq!(
+            resource_version(&version_info(true)),
+            Some(LibraryVersion {
+                version: Some("1.2.3".to_owned()),
+                product_name: Some("Runtime".to_owned()),
+                company_name: Some("Vendor".to_owned()),
+            })
+        );
+        assert_eq!(
+            resource_version(&version_info(false)).unwrap().version,
+            Some("2.3.4.5".to_owned())
+        );
+    }
+
+    #[test]
+    fn broken_version_resources() {
+        let data = version_info(true);
+        // Truncated, so the lengths run past the end
+        for end in 0..data.len() {
+            resource_version(&data[..end]);
+        }
+        // Misaligned, so everything is read from the wrong place
+        for start in 1..8 {
+            resource_version(&data[start..]);
+        }
+        // Lengths and other fields that are too short, too long, or nonsense
+        for offset in (0..data.len() - 1).step_by(2) {
+            for value in [0u16, 1, 3, 7, 0xffff] {
+                let mut data = data.clone();
+                data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+                resource_version(&data);
+            }
+        }
+    }
+
+    fn note(name: &[u8], note_type: u32, desc: &[u8], big_endian: bool) -> Vec<u8> {
+        let word = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut data = vec![];
+        data.extend(word(name.len() as u32));
+        data.extend(word(desc.len() as u32));
+        data.extend(word(note_type));
+        data.extend(name);
+        pad4(&mut data);
+        data.extend(desc);
+        pad4(&mut data);
+        data
+    }
+
+    fn package_notes(big_endian: bool) -> Vec<u8> {
+        let mut data = note(b"GNU\0", 3, &[1, 2, 3, 4, 5], big_endian);
+        data.extend(note(
+            b"FDO\0",
+            NT_FDO_PACKAGING_METADATA,
+            br#"{"type":"rpm","name":"monado","version":"21.0.0"}"#,
+            big_endian,
+        ));
+        data
+    }
+
+    #[test]
+    fn package_notes_in_either_byte_order() {
+        for big_endian in [false, true] {
+            assert_eq!(
+                package_note(&package_notes(big_endian), big_endian),
+                Some((Some("monado".to_owned()), Some("21.0.0".to_owned())))
+            );
+        }
+        assert_eq!(package_note(&note(b"GNU\0", 3, &[], false), false), None);
+    }
+
+    #[test]
+    fn broken_package_notes() {
+        let data = package_notes(false);
+        for end in 0..data.len() {
+            package_note(&data[..end], false);
+        }
+        for start in 1..8 {
+            package_note(&data[start..], false);
+        }
+        for offset in (0..data.len() - 3).step_by(4) {
+            for value in [0u32, 1, 3, 0x7fff_ffff, 0xffff_ffff] {
+                let mut data = data.clone();
+                data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                package_note(&data, false);
+            }
+        }
+    }
+
+    #[test]
+    fn pe_machines() {
+        for (machine, architecture) in [
+            (pe::IMAGE_FILE_MACHINE_I386, Architecture::X86),
+            (pe::IMAGE_FILE_MACHINE_AMD64, Architecture::X86_64),
+            (pe::IMAGE_FILE_MACHINE_ARM64, Architecture::Aarch64),
+            (pe::IMAGE_FILE_MACHINE_ARMNT, Architecture::Armv7),
+            (IMAGE_FILE_MACHINE_ARM64EC, Architecture::Arm64EC),
+            (pe::IMAGE_FILE_MACHINE_RISCV32, Architecture::Riscv32),
+            (pe::IMAGE_FILE_MACHINE_RISCV64, Architecture::Riscv64),
+            (pe::IMAGE_FILE_MACHINE_ARM, Architecture::Unknown),
+            (pe::IMAGE_FILE_MACHINE_IA64, Architecture::Unknown),
+        ] {
+            assert_eq!(Architecture::from_pe_machine(machine), architecture);
+        }
+    }
+
+    #[test]
+    fn object_architectures() {
+        for (object_arch, architecture) in [
+            (object::Architecture::I386, Architecture::X86),
+            (object::Architecture::X86_64, Architecture::X86_64),
+            (object::Architecture::Aarch64, Architecture::Aarch64),
+            (object::Architecture::Arm, Architecture::Armv7),
+            (object::Architecture::Riscv32, Architecture::Riscv32),
+            (object::Architecture::Riscv64, Architecture::Riscv64),
+            (object::Architecture::PowerPc, Architecture::PowerPc),
+            (object::Architecture::PowerPc64, Architecture::PowerPc64),
+            (object::Architecture::Mips, Architecture::Mips),
+            (object::Architecture::Mips64, Architecture::Mips64),
+            (object::Architecture::LoongArch64, Architecture::LoongArch64),
+            (object::Architecture::S390x, Architecture::S390x),
+            (object::Architecture::Sparc64, Architecture::Sparc64),
+            (object::Architecture::X86_64_X32, Architecture::Unknown),
+            (object::Architecture::Unknown, Architecture::Unknown),
+        ] {
+            assert_eq!(Architecture::from_object(object_arch), architecture);
+        }
+    }
+
+    #[test]
+    fn binary_architectures() {
+        assert_eq!(
+            binary_architecture(&elf_library(&[], &[])),
+            Some(Architecture::X86_64)
+        );
+        assert_eq!(
+            binary_architecture(&pe_dll(false, &[], &[])),
+            Some(Architecture::X86)
+        );
+        // ARM64EC DLLs are PE32+ files with their own machine type
+        let mut arm64ec = pe_dll(true, &[], &[]);
+        arm64ec[0x44..0x46].copy_from_slice(&IMAGE_FILE_MACHINE_ARM64EC.to_le_bytes());
+        assert_eq!(binary_architecture(&arm64ec), Some(Architecture::Arm64EC));
+        assert_eq!(binary_architecture(b"not a binary"), None);
+    }
+
+    #[test]
+    fn loadable_architectures() {
+        use Architecture::*;
+        for (host, other, loadable) in [
+            (X86_64, X86_64, true),
+            (X86_64, X86, true),
+            (X86, X86_64, false),
+            (Aarch64, Armv7, true),
+            (Armv7, Aarch64, false),
+            (Aarch64, X86_64, cfg!(windows)),
+            (Aarch64, X86, cfg!(windows)),
+            (Aarch64, Arm64EC, cfg!(windows)),
+            (Arm64EC, Arm64EC, true),
+            (X86_64, Arm64EC, false),
+            (PowerPc64, PowerPc, true),
+            (Mips64, Mips, true),
+            (Riscv64, Riscv32, false),
+            (X86_64, Aarch64, false),
+        ] {
+            assert_eq!(host.can_load(other), loadable, "{} loading {}", host, other);
+        }
+    }
+
+    #[test]
+    fn companions() {
+        use Architecture::*;
+        for (host, companions) in [
+            (X86_64, vec![X86]),
+            (X86, vec![]),
+            (
+                Aarch64,
+                if cfg!(windows) {
+                    vec![X86, Armv7]
+                } else {
+                    vec![Armv7]
+                },
+            ),
+            (Arm64EC, vec![]),
+            (PowerPc64, vec![PowerPc]),
+            (Mips64, vec![Mips]),
+            (Riscv64, vec![]),
+            (Unknown, vec![]),
+        ] {
+            assert_eq!(host.companion_architectures(), companions, "{}", host);
+        }
+    }
+
+    #[test]
+    fn names() {
+        use Architecture::*;
+        for (architecture, name, abi_name) in [
+            (X86, "x86", Some("i686")),
+            (X86_64, "x86_64", Some("x86_64")),
+            (Aarch64, "aarch64", Some("aarch64")),
+            (Armv7, "armv7", Some("armv7a-vfp")),
+            (Arm64EC, "arm64ec", None),
+            (PowerPc, "powerpc", Some("ppc")),
+            (Riscv64, "riscv64", Some("riscv64")),
+            (LoongArch64, "loongarch64", Some("loongarch64")),
+            (Unknown, "unknown", None),
+        ] {
+            assert_eq!(architecture.to_string(), name);
+            assert_eq!(architecture.openxr_abi_name(), abi_name);
+            if architecture != Unknown {
+                assert_eq!(name.parse(), Ok(architecture));
+            }
+            if let Some(abi_name) = abi_name {
+                assert_eq!(abi_name.parse(), Ok(architecture));
+            }
+        }
+        let ppc64_abi_name = if cfg!(target_endian = "little") {
+            "ppc64el"
+        } else {
+            "ppc64"
+        };
+        assert_eq!(PowerPc64.openxr_abi_name(), Some(ppc64_abi_name));
+        assert_eq!(ppc64_abi_name.parse(), Ok(PowerPc64));
+        assert_eq!("powerpc64".parse(), Ok(PowerPc64));
+        assert_eq!(
+            "unknown".parse::<Architecture>(),
+            Err("Unknown architecture 'unknown'".to_owned())
+        );
+        assert!("amd64".parse::<Architecture>().is_err());
+    }
+}
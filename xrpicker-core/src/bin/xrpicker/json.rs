@@ -0,0 +1,317 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Machine-readable output of the runtime listing, for `xrpicker list --format json`.
+//!
+//! The document is a single JSON object. Fields are only ever added within a given
+//! `schema_version`: scripts should ignore fields they do not know.
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "runtimes": [
+//!     {
+//!       "index": 1,
+//!       "name": "Monado",
+//!       "manifests": ["/usr/share/openxr/1/openxr_monado.json"],
+//!       "libraries": ["/usr/lib/x86_64-linux-gnu/libopenxr_monado.so"],
+//!       "architectures": ["x86_64"],
+//!       "version": { "version": "25", "product_name": null, "company_name": null },
+//!       "known_runtime": {
+//!         "id": "monado",
+//!         "name": "Monado",
+//!         "vendor": "Monado contributors",
+//!         "homepage": "https://monado.freedesktop.org/",
+//!         "out_of_process": true
+//!       },
+//!       "active_state": "active",
+//!       "is_active": true,
+//!       "description": "/usr/share/openxr/1/openxr_monado.json\n    ⮩ ...",
+//!       "problems": []
+//!     }
+//!   ],
+//!   "errors": [
+//!     {
+//!       "manifest": "/etc/xdg/openxr/1/broken.json",
+//!       "error": "JSON parsing error: expected value at line 1 column 1",
+//!       "source": "expected value at line 1 column 1"
+//!     }
+//!   ],
+//!   "active_manifests": ["/usr/share/openxr/1/openxr_monado.json"]
+//! }
+//! ```
+//!
+//! - `index` is the 1-based index accepted by `xrpicker set`.
+//! - `active_state` is one of `not_active`, `active`, `active_64`, `active_32`,
+//!   `active_64_and_32`, `active_by_environment`, `overridden_by_environment`, or `disabled`.
+//!   - `active_64`, `active_32`, and `active_64_and_32` are only used on platforms with
+//!     separate 32-bit and 64-bit active runtime settings (Windows).
+//!   - `active_by_environment` is the runtime selected by the `XR_RUNTIME_JSON` environment
+//!     variable, and `overridden_by_environment` is a runtime marked active but not used
+//!     because of that variable.
+//!   - `disabled` is a runtime that is not active, and is marked disabled in the list of
+//!     available runtimes (Windows).
+//! - `is_active` is true if the loader will use the runtime (given the environment of
+//!   `xrpicker`): it is false for `not_active`, `overridden_by_environment`, and `disabled`.
+//! - `active_manifests` are the manifest(s) the loader will use, so just the one from
+//!   `XR_RUNTIME_JSON` if it is set.
+//! - `libraries` are resolved as far as possible, but are not checked for existence.
+//! - `architectures` are those of the libraries that could be identified, like `x86_64`,
+//!   `x86`, `aarch64`, `armv7`, or `arm64ec`.
+//! - `version` is from the metadata of the (first) library that has any, or `null`: the
+//!   version resource of a Windows library, or the package note and the version in the file
+//!   name or soname of an ELF library. Any of its fields may be `null`.
+//! - `known_runtime` is what we know about the runtime, recognized by its library file name,
+//!   or `null`. `id` is stable, and `homepage` may be `null`.
+//! - `problems` are human-readable descriptions of problems found in the runtime libraries
+//!   that will keep applications from using the runtime. Empty if none were found.
+//! - `errors` lists manifests that were found but could not be loaded. `error` describes the
+//!   failure including its causes, and `source` is the underlying cause alone (like the JSON
+//!   syntax error or the OS error), or `null` if there is none.
+
+use std::{error::Error, path::Path};
+
+use serde::Serialize;
+use xrpicker::{
+    arch_detect::LibraryVersion, known_runtimes::KnownRuntime, platform::PlatformRuntime,
+    ActiveState, ManifestError,
+};
+
+/// Version of the document structure: incremented on incompatible changes only.
+const SCHEMA_VERSION: u32 = 1;
+
+/// Stable string for an active state: deliberately not tied to the enum variant names.
+fn active_state_str(state: ActiveState) -> &'static str {
+    match state {
+        ActiveState::NotActive => "not_active",
+        ActiveState::ActiveIndependentRuntime => "active",
+        ActiveState::Active64 => "active_64",
+        ActiveState::Active32 => "active_32",
+        ActiveState::Active64and32 => "active_64_and_32",
+        ActiveState::ActiveByEnvironment => "active_by_environment",
+        ActiveState::OverriddenByEnvironment => "overridden_by_environment",
+        ActiveState::Disabled => "disabled",
+    }
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+#[derive(Serialize)]
+struct JsonRuntime {
+    index: usize,
+    name: String,
+    manifests: Vec<String>,
+    libraries: Vec<String>,
+    architectures: Vec<String>,
+    version: Option<JsonVersion>,
+    known_runtime: Option<JsonKnownRuntime>,
+    active_state: &'static str,
+    is_active: bool,
+    description: String,
+    problems: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct JsonVersion {
+    version: Option<String>,
+    product_name: Option<String>,
+    company_name: Option<String>,
+}
+
+impl From<LibraryVersion> for JsonVersion {
+    fn from(version: LibraryVersion) -> Self {
+        Self {
+            version: version.version,
+            product_name: version.product_name,
+            company_name: version.company_name,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonKnownRuntime {
+    id: String,
+    name: String,
+    vendor: String,
+    homepage: Option<String>,
+    out_of_process: bool,
+}
+
+impl From<&KnownRuntime> for JsonKnownRuntime {
+    fn from(known: &KnownRuntime) -> Self {
+        Self {
+            id: known.id.clone(),
+            name: known.name.clone(),
+            vendor: known.vendor.clone(),
+            homepage: known.homepage.clone(),
+            out_of_process: known.out_of_process,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonManifestError {
+    manifest: String,
+    error: String,
+    source: Option<String>,
+}
+
+/// Describe an error including its causes, and get the innermost cause alone, if any
+pub(crate) fn error_messages(e: &dyn Error) -> (String, Option<String>) {
+    let mut error = e.to_string();
+    let mut source = e.source();
+    let mut innermost = None;
+    while let Some(cause) = source {
+        let message = cause.to_string();
+        // Some errors already include their cause in their own message
+        if !error.contains(&message) {
+            error = format!("{}: {}", error, message);
+        }
+        innermost = Some(message);
+        source = cause.source();
+    }
+    (error, innermost)
+}
+
+impl From<&ManifestError> for JsonManifestError {
+    fn from(e: &ManifestError) -> Self {
+        let (error, source) = error_messages(&e.1);
+        Self {
+            manifest: path_string(&e.0),
+            error,
+            source,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct JsonListing {
+    schema_version: u32,
+    runtimes: Vec<JsonRuntime>,
+    errors: Vec<JsonManifestError>,
+    active_manifests: Vec<String>,
+}
+
+impl JsonListing {
+    /// Create from runtimes (already paired with their active state), errors, and active manifest paths
+    pub(crate) fn new<'a, R: 'a + PlatformRuntime>(
+        runtimes: impl IntoIterator<Item = (&'a R, ActiveState)>,
+        errors: &[ManifestError],
+        active_manifests: &[impl AsRef<Path>],
+    ) -> Self {
+        let runtimes = runtimes
+            .into_iter()
+            .enumerate()
+            .map(|(i, (runtime, state))| JsonRuntime {
+                index: i + 1,
+                name: runtime.get_runtime_name(),
+                manifests: runtime
+                    .get_manifests()
+                    .into_iter()
+                    .map(path_string)
+                    .collect(),
+                libraries: runtime
+                    .get_libraries()
+                    .iter()
+                    .map(|p| path_string(p))
+                    .collect(),
+                architectures: runtime
+                    .get_architectures()
+                    .iter()
+                    .map(|a| a.to_string())
+                    .collect(),
+                version: runtime.get_version().map(JsonVersion::from),
+                known_runtime: runtime.get_known_runtime().map(JsonKnownRuntime::from),
+                active_state: active_state_str(state),
+                is_active: state.is_effectively_active(),
+                description: runtime.describe(),
+                problems: runtime
+                    .get_problems()
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect(),
+            })
+            .collect();
+        let errors = errors.iter().map(JsonManifestError::from).collect();
+        let active_manifests = active_manifests
+            .iter()
+            .map(|p| path_string(p.as_ref()))
+            .collect();
+        Self {
+            schema_version: SCHEMA_VERSION,
+            runtimes,
+            errors,
+            active_manifests,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::iter;
+
+    use serde_json::{json, Value};
+    use xrpicker::Platform;
+
+    use super::*;
+    use crate::tests::{fake_root, platform};
+
+    #[test]
+    fn listing() {
+        let root = fake_root(&[("openxr_a.json", "A"), ("openxr_b.json", "B")]);
+        let platform = platform(&root);
+        let active_data = platform.get_active_data();
+        let (runtimes, errors) = platform
+            .find_available_runtimes(Box::new(iter::empty()))
+            .unwrap();
+        let states = runtimes
+            .iter()
+            .map(|r| platform.get_runtime_active_state(r, &active_data));
+        let active_manifests = platform.get_active_runtime_manifests();
+        let listing: Value = serde_json::to_value(JsonListing::new(
+            runtimes.iter().zip(states),
+            &errors,
+            &active_manifests,
+        ))
+        .unwrap();
+
+        assert_eq!(listing["schema_version"], json!(1));
+        let dir = root.path().canonicalize().unwrap().join("etc/xdg/openxr/1");
+        let manifest_a = path_string(&dir.join("openxr_a.json"));
+        assert_eq!(listing["active_manifests"], json!([manifest_a]));
+
+        let mut listed = listing["runtimes"].as_array().unwrap().clone();
+        assert_eq!(listed.len(), 2);
+        for (i, runtime) in listed.iter().enumerate() {
+            assert_eq!(runtime["index"], json!(i + 1));
+        }
+        listed.sort_by_key(|r| r["name"].as_str().unwrap().to_owned());
+        assert_eq!(listed[0]["name"], json!("A"));
+        assert_eq!(listed[0]["manifests"], json!([manifest_a]));
+        assert_eq!(listed[0]["active_state"], json!("active"));
+        assert_eq!(listed[0]["is_active"], json!(true));
+        assert_eq!(listed[1]["name"], json!("B"));
+        assert_eq!(listed[1]["active_state"], json!("not_active"));
+        assert_eq!(listed[1]["is_active"], json!(false));
+        for runtime in &listed {
+            assert_eq!(runtime["architectures"], json!([]));
+            assert_eq!(runtime["version"], Value::Null);
+            assert_eq!(runtime["known_runtime"], Value::Null);
+            // The library does not exist
+            assert_eq!(runtime["problems"].as_array().unwrap().len(), 1);
+        }
+
+        let errors = listing["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0]["manifest"],
+            json!(path_string(&dir.join("openxr_broken.json")))
+        );
+        let error = errors[0]["error"].as_str().unwrap();
+        let source = errors[0]["source"].as_str().unwrap();
+        assert!(source.starts_with("expected value"), "{}", source);
+        assert!(error.ends_with(source), "{}", error);
+    }
+}
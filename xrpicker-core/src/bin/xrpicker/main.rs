@@ -0,0 +1,1171 @@
+// Copyright 2022-2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::{
+    env,
+    ffi::OsString,
+    fmt::Debug,
+    fs, io, iter,
+    path::{Path, PathBuf},
+    process::{self, ExitCode},
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use itertools::Itertools;
+use xrpicker::{
+    arch_detect::Architecture,
+    history::DEFAULT_HISTORY_LENGTH,
+    layer::{ApiLayer, ApiLayerKind},
+    library::RuntimeProblem,
+    make_platform,
+    platform::PlatformRuntime,
+    registry::active_runtime_reg_file,
+    validate::{validate_runtime_manifest, Severity},
+    ActivationStrategy, ActiveState, Platform, RUNTIME_JSON_ENV_VAR,
+};
+
+mod json;
+
+/// Exit code from `list` when no runtime is active, but there was no other error.
+const EXIT_NO_ACTIVE_RUNTIME: u8 = 3;
+
+/// Command-line tool to list and choose your active OpenXR runtime.
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    about,
+    after_help = "Exit status of `list`: 0 if any runtime is active, 3 if none is, 1 on other errors."
+)]
+struct Cli {
+    #[cfg(unix)]
+    #[command(flatten)]
+    root: RootArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Options to inspect a system other than the running one
+#[cfg(unix)]
+#[derive(clap::Args, Debug)]
+struct RootArgs {
+    /// Inspect the system in this directory (a mounted disk image, a chroot, or a container
+    /// rootfs) instead of the running one. Environment variables are then ignored.
+    #[arg(long, global = true, value_name = "DIR")]
+    root: Option<PathBuf>,
+
+    /// The per-user config directory, as seen inside the root. Defaults to none for `--root`.
+    #[arg(long, global = true, value_name = "DIR")]
+    xdg_config_home: Option<PathBuf>,
+
+    /// Colon-separated system config directories, as seen inside the root. Defaults to /etc/xdg.
+    #[arg(long, global = true, value_name = "DIRS", value_delimiter = ':')]
+    xdg_config_dirs: Option<Vec<PathBuf>>,
+
+    /// The per-user data directory, as seen inside the root. Defaults to none for `--root`.
+    #[arg(long, global = true, value_name = "DIR")]
+    xdg_data_home: Option<PathBuf>,
+
+    /// Colon-separated system data directories, as seen inside the root.
+    /// Defaults to /usr/local/share:/usr/share.
+    #[arg(long, global = true, value_name = "DIRS", value_delimiter = ':')]
+    xdg_data_dirs: Option<Vec<PathBuf>>,
+}
+
+#[cfg(unix)]
+impl RootArgs {
+    /// These options as arguments, to pass them along to the privileged helper
+    fn to_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![];
+        let dirs = [
+            ("--root", self.root.clone()),
+            ("--xdg-config-home", self.xdg_config_home.clone()),
+            ("--xdg-data-home", self.xdg_data_home.clone()),
+        ];
+        let dir_lists = [
+            ("--xdg-config-dirs", &self.xdg_config_dirs),
+            ("--xdg-data-dirs", &self.xdg_data_dirs),
+        ];
+        for (name, dir) in dirs {
+            if let Some(dir) = dir {
+                args.extend([name.into(), dir.into()]);
+            }
+        }
+        for (name, list) in dir_lists {
+            if let Some(list) = list {
+                let mut joined = OsString::new();
+                for (i, dir) in list.iter().enumerate() {
+                    if i > 0 {
+                        joined.push(":");
+                    }
+                    joined.push(dir);
+                }
+                args.extend([name.into(), joined]);
+            }
+        }
+        args
+    }
+
+    fn make_platform(self) -> xrpicker::LinuxPlatform {
+        if self.root.is_none()
+            && self.xdg_config_home.is_none()
+            && self.xdg_config_dirs.is_none()
+            && self.xdg_data_home.is_none()
+            && self.xdg_data_dirs.is_none()
+        {
+            return make_platform();
+        }
+        let root = self.root.unwrap_or_else(|| PathBuf::from("/"));
+        xrpicker::make_platform_with_root(
+            &root,
+            xrpicker::XdgConfigVars {
+                home: None,
+                config_home: self.xdg_config_home,
+                config_dirs: self.xdg_config_dirs,
+                data_home: self.xdg_data_home,
+                data_dirs: self.xdg_data_dirs,
+            },
+        )
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List available runtimes, non-fatal errors, and the active runtime manifest(s). (Default)
+    List {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// Print the path(s) of the active runtime manifest(s), one per line
+    Active,
+
+    /// Make a runtime active
+    Set {
+        /// The runtime to make active: an index as shown by `list`, a runtime name, or the path
+        /// to a runtime manifest
+        runtime: String,
+
+        /// Only make it active for applications of this architecture, like `i686` for 32-bit
+        /// games on an x86_64 system
+        #[arg(long, value_name = "ARCH")]
+        arch: Option<Architecture>,
+
+        /// How to make it active: `symlink` (the default), `relative-symlink`, `copy` (of the
+        /// manifest, with an absolute library path), or `environment` (an `environment.d` snippet
+        /// setting XR_RUNTIME_JSON, which takes effect at the next login). Only on Linux.
+        #[arg(long, value_name = "STRATEGY")]
+        strategy: Option<ActivationStrategy>,
+
+        /// Make it active for all users, in the system config directory. Asks for administrator
+        /// privileges (with pkexec or sudo) unless running as root or with `--root`.
+        #[arg(long, conflicts_with = "arch")]
+        system: bool,
+
+        /// With `--system`, only check and show what would change, without privileges
+        #[arg(long, requires = "system")]
+        dry_run: bool,
+    },
+
+    /// Make a runtime active for all users: what `set --system` runs with administrator
+    /// privileges
+    #[command(hide = true)]
+    SystemHelper {
+        /// The runtime manifest, as a path on this system
+        manifest: PathBuf,
+
+        #[arg(long, value_name = "STRATEGY")]
+        strategy: Option<ActivationStrategy>,
+
+        /// Only check and show what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Remove your own active runtime setting, so the system-wide default takes effect again
+    ///
+    /// The removed setting is backed up, and can be restored with `history undo`.
+    Reset,
+
+    /// Run a program using a specific runtime, without changing the active runtime
+    ///
+    /// Sets XR_RUNTIME_JSON for the program. If the runtime has separate 64-bit and 32-bit
+    /// manifests, the 64-bit one is used.
+    Run {
+        /// The runtime to use: an index as shown by `list`, a runtime name, or the path to a
+        /// runtime manifest
+        #[arg(long, short)]
+        runtime: String,
+
+        /// The program to run, and its arguments, after `--`
+        #[arg(last = true, required = true)]
+        command: Vec<OsString>,
+    },
+
+    /// Diagnose the OpenXR setup step by step, as the loader sees it, suggesting fixes
+    Doctor,
+
+    /// Check runtime manifest file(s) for problems, before shipping them
+    Validate {
+        /// Runtime manifest file(s) to check
+        #[arg(required = true)]
+        manifests: Vec<PathBuf>,
+    },
+
+    /// Show the shared libraries a runtime needs, and where the dynamic linker would find them
+    ///
+    /// Exits with failure if any are missing. ELF (Linux) and PE (Windows) runtime libraries
+    /// are inspected.
+    Deps {
+        /// The runtime: an index as shown by `list`, a runtime name, or the path to a runtime
+        /// manifest
+        runtime: String,
+    },
+
+    /// Enable a runtime in the list of available runtimes installers register in. Only on Windows.
+    Enable {
+        /// The runtime: an index as shown by `list`, a runtime name, or a manifest path
+        runtime: String,
+    },
+
+    /// Disable a runtime in the list of available runtimes, as some installers do for runtimes
+    /// they replace. It can still be made active. Only on Windows.
+    Disable {
+        /// The runtime: an index as shown by `list`, a runtime name, or a manifest path
+        runtime: String,
+    },
+
+    /// Add a runtime manifest to the list of available runtimes, as its installer would.
+    /// Only on Windows.
+    Register {
+        /// The runtime manifest file
+        manifest: PathBuf,
+    },
+
+    /// Remove a runtime from the list of available runtimes, for example one left behind by an
+    /// uninstaller. Only on Windows.
+    Unregister {
+        /// The runtime: an index as shown by `list`, a runtime name, or a manifest path
+        runtime: String,
+    },
+
+    /// Write a Windows `.reg` file that makes a runtime active (and lists it as available) on the
+    /// machines it is imported on, for deploying with group policy or similar tools
+    ///
+    /// Works on any platform: the manifest paths are as on the target machines, which are
+    /// assumed to be 64-bit Windows.
+    ExportReg {
+        /// The runtime's 64-bit manifest
+        #[arg(long, value_name = "PATH", required_unless_present = "manifest_32")]
+        manifest_64: Option<PathBuf>,
+
+        /// The runtime's 32-bit manifest, for 32-bit applications
+        #[arg(long, value_name = "PATH")]
+        manifest_32: Option<PathBuf>,
+
+        /// Write the file here, in the UTF-16 encoding regedit expects, instead of printing it
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// List API layers, or enable or disable implicit ones
+    Layers {
+        #[command(subcommand)]
+        command: Option<LayersCommand>,
+    },
+
+    /// Show the changes made to the active runtime, undo the last one, or restore an earlier one
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum LayersCommand {
+    /// List implicit and explicit API layers, and whether each is enabled. (Default)
+    List,
+
+    /// Enable an implicit API layer, so it is loaded for every application
+    Enable {
+        /// The layer: an index as shown by `layers list`, a layer name, or a manifest path
+        layer: String,
+    },
+
+    /// Disable an implicit API layer, so it is no longer loaded for every application.
+    ///
+    /// On Linux, system-wide layers are disabled for this user through their
+    /// `disable_environment` variable, starting at the next login.
+    Disable {
+        /// The layer: an index as shown by `layers list`, a layer name, or a manifest path
+        layer: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum HistoryCommand {
+    /// List the changes made to the active runtime, oldest first, with an index for each. (Default)
+    List,
+
+    /// Undo the most recent change, along with any made together with it. The undo is itself
+    /// recorded as a change.
+    Undo,
+
+    /// Set an active runtime file back to what an earlier change set it to
+    Restore {
+        /// The change: an index as shown by `history list`
+        index: usize,
+    },
+
+    /// Forget older changes, and remove backups of active runtime files that are no longer needed
+    Clean {
+        /// How many of the most recent changes to keep
+        #[arg(long, default_value_t = DEFAULT_HISTORY_LENGTH)]
+        keep: usize,
+
+        /// Also remove backups no remaining change needs, like those saved by older versions
+        /// without recording them in the history
+        #[arg(long)]
+        remove_unrecorded: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// A JSON document: see the `json` module for the structure
+    Json,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum CliError {
+    #[error("No runtime matches '{0}'. Run `xrpicker list` to see the available runtimes.")]
+    NoMatch(String),
+
+    #[error("Runtime index {0} is out of range: there are {1} runtime(s)")]
+    IndexOutOfRange(usize, usize),
+
+    #[error("No API layer matches '{0}'. Run `xrpicker layers` to see the available layers.")]
+    NoLayerMatch(String),
+
+    #[error("API layer index {0} is out of range: there are {1} layer(s)")]
+    LayerIndexOutOfRange(usize, usize),
+
+    #[error("History index {0} is out of range: there are {1} change(s)")]
+    HistoryIndexOutOfRange(usize, usize),
+
+    #[error(
+        "The name '{0}' is ambiguous, it matches:\n{1}\nSpecify an index or manifest path instead."
+    )]
+    Ambiguous(String, String),
+
+    #[error("Could not run {0}: {1}")]
+    Spawn(String, io::Error),
+
+    #[error("Administrator privileges are needed, but neither pkexec nor sudo was found. Run this as root instead.")]
+    NoPrivilegeHelper,
+
+    #[error("Making the runtime active for all users failed ({0})")]
+    HelperFailed(process::ExitStatus),
+
+    #[error(transparent)]
+    Core(#[from] xrpicker::Error),
+}
+
+/// Find the runtimes, making sure the selector's runtime is included if it is a path to a
+/// manifest not in a standard location.
+fn find_runtimes_for_selector<T: Platform>(
+    platform: &T,
+    selector: &str,
+) -> Result<Vec<T::PlatformRuntimeType>, xrpicker::Error> {
+    let extra_manifest = Some(Path::new(selector))
+        .filter(|p| p.is_file())
+        .map(|p| p.to_owned());
+    let (runtimes, _) = platform.find_available_runtimes(Box::new(extra_manifest.into_iter()))?;
+    Ok(runtimes)
+}
+
+/// Does this runtime use a manifest at the given path?
+fn uses_manifest<R: PlatformRuntime>(runtime: &R, manifest: &Path) -> bool {
+    runtime
+        .get_manifests()
+        .into_iter()
+        .any(|p| p == manifest || p.canonicalize().ok().as_deref() == Some(manifest))
+}
+
+/// Turn the user's description of a runtime into a single runtime, or a helpful error.
+///
+/// Tries, in order: a 1-based index as shown by `list`, a path to an existing manifest file,
+/// an exact runtime name, and a case-insensitive runtime name.
+fn select_runtime<'a, R: PlatformRuntime>(
+    runtimes: &'a [R],
+    selector: &str,
+) -> Result<&'a R, CliError> {
+    if let Ok(index) = selector.parse::<usize>() {
+        return index
+            .checked_sub(1)
+            .and_then(|i| runtimes.get(i))
+            .ok_or(CliError::IndexOutOfRange(index, runtimes.len()));
+    }
+
+    let path = Path::new(selector);
+    if path.is_file() {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        return runtimes
+            .iter()
+            .find(|r| uses_manifest(*r, &canonical))
+            .ok_or_else(|| CliError::NoMatch(selector.to_owned()));
+    }
+
+    let exact: Vec<(usize, &R)> = runtimes
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.get_runtime_name() == selector)
+        .collect();
+    let matches = if exact.is_empty() {
+        runtimes
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.get_runtime_name().eq_ignore_ascii_case(selector))
+            .collect()
+    } else {
+        exact
+    };
+
+    match matches.as_slice() {
+        [] => Err(CliError::NoMatch(selector.to_owned())),
+        [(_, runtime)] => Ok(runtime),
+        _ => Err(CliError::Ambiguous(
+            selector.to_owned(),
+            matches
+                .iter()
+                .map(|(i, r)| {
+                    format!(
+                        "  [{}] {}",
+                        i + 1,
+                        r.get_manifests().iter().map(|p| p.display()).join(", ")
+                    )
+                })
+                .join("\n"),
+        )),
+    }
+}
+
+/// Turn the user's description of an API layer into a single layer, or a helpful error.
+///
+/// Tries, in order: a 1-based index as shown by `layers list`, a path to a manifest file,
+/// and a layer name.
+fn select_layer<'a>(layers: &'a [ApiLayer], selector: &str) -> Result<&'a ApiLayer, CliError> {
+    if let Ok(index) = selector.parse::<usize>() {
+        return index
+            .checked_sub(1)
+            .and_then(|i| layers.get(i))
+            .ok_or(CliError::LayerIndexOutOfRange(index, layers.len()));
+    }
+
+    let path = Path::new(selector);
+    let canonical = path.canonicalize().ok();
+    let by_path = layers.iter().find(|layer| {
+        layer.manifest_path() == path
+            || canonical.is_some() && layer.manifest_path().canonicalize().ok() == canonical
+    });
+    if let Some(layer) = by_path {
+        return Ok(layer);
+    }
+
+    let matches: Vec<(usize, &ApiLayer)> = layers
+        .iter()
+        .enumerate()
+        .filter(|(_, layer)| layer.name() == selector)
+        .collect();
+    match matches.as_slice() {
+        [] => Err(CliError::NoLayerMatch(selector.to_owned())),
+        [(_, layer)] => Ok(layer),
+        _ => Err(CliError::Ambiguous(
+            selector.to_owned(),
+            matches
+                .iter()
+                .map(|(i, layer)| format!("  [{}] {}", i + 1, layer.manifest_path().display()))
+                .join("\n"),
+        )),
+    }
+}
+
+fn list<T: Platform>(platform: &T, format: OutputFormat) -> Result<ExitCode, CliError>
+where
+    T::PlatformRuntimeType: Debug,
+{
+    let active_data = platform.get_active_data();
+    let (runtimes, nonfatal_errors) = platform.find_available_runtimes(Box::new(iter::empty()))?;
+    let states: Vec<ActiveState> = runtimes
+        .iter()
+        .map(|r| platform.get_runtime_active_state(r, &active_data))
+        .collect();
+    let active_manifests = platform.get_active_runtime_manifests();
+    let any_active = states.iter().any(|state| state.is_effectively_active());
+
+    match format {
+        OutputFormat::Text => {
+            println!("\nRuntimes:");
+            for (i, (runtime, state)) in runtimes.iter().zip(&states).enumerate() {
+                let state = state.to_string();
+                if state.is_empty() {
+                    println!("- [{}] {}", i + 1, runtime.get_runtime_name());
+                } else {
+                    println!("- [{}] {}: {}", i + 1, runtime.get_runtime_name(), state);
+                }
+                for line in runtime.describe().lines() {
+                    println!("    {}", line);
+                }
+                let architectures = runtime.get_architectures();
+                if !architectures.is_empty() {
+                    println!("    Architecture: {}", architectures.iter().join(", "));
+                }
+                if let Some(version) = runtime.get_version() {
+                    println!("    Version: {}", version);
+                }
+                if let Some(known) = runtime.get_known_runtime() {
+                    println!("    Known runtime: {}", known);
+                }
+                for problem in runtime.get_problems() {
+                    println!("    Problem: {}", problem);
+                }
+            }
+
+            if !nonfatal_errors.is_empty() {
+                println!("\nNon-fatal errors:");
+                for e in &nonfatal_errors {
+                    println!(
+                        "- Manifest: {} - Error: {}",
+                        e.0.display(),
+                        json::error_messages(&e.1).0
+                    );
+                }
+            }
+
+            println!("\nActive runtime manifest path(s):");
+
+            for path in &active_manifests {
+                println!("- {}", path.display());
+            }
+        }
+        OutputFormat::Json => {
+            let listing = json::JsonListing::new(
+                runtimes.iter().zip(states.iter().copied()),
+                &nonfatal_errors,
+                &active_manifests,
+            );
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&listing).map_err(xrpicker::Error::from)?
+            );
+        }
+    }
+    if any_active {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::from(EXIT_NO_ACTIVE_RUNTIME))
+    }
+}
+
+fn active<T: Platform>(platform: &T) -> Result<ExitCode, CliError> {
+    for path in platform.get_active_runtime_manifests() {
+        println!("{}", path.display());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn set<T: Platform>(
+    platform: &T,
+    selector: &str,
+    arch: Option<Architecture>,
+    strategy: ActivationStrategy,
+) -> Result<ExitCode, CliError> {
+    let runtimes = find_runtimes_for_selector(platform, selector)?;
+    let runtime = select_runtime(&runtimes, selector)?;
+    match arch {
+        Some(arch) => {
+            runtime.make_active_for_architecture(arch, strategy)?;
+            println!(
+                "Active runtime for {} applications is now {}:\n{}",
+                arch,
+                runtime.get_runtime_name(),
+                runtime.describe()
+            );
+        }
+        None if strategy == ActivationStrategy::Environment => {
+            runtime.make_active_with_strategy(strategy)?;
+            println!(
+                "From the next login, {} selects {}:\n{}",
+                RUNTIME_JSON_ENV_VAR,
+                runtime.get_runtime_name(),
+                runtime.describe()
+            );
+        }
+        None => {
+            runtime.make_active_with_strategy(strategy)?;
+            println!(
+                "Active runtime is now {}:\n{}",
+                runtime.get_runtime_name(),
+                runtime.describe()
+            );
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Make a runtime active for all users, through the privileged helper mode of this program
+fn set_system<T: Platform>(
+    platform: &T,
+    selector: &str,
+    strategy: ActivationStrategy,
+    dry_run: bool,
+    alternate_root: bool,
+    forwarded_args: &[OsString],
+) -> Result<ExitCode, CliError> {
+    let runtimes = find_runtimes_for_selector(platform, selector)?;
+    let runtime = select_runtime(&runtimes, selector)?;
+    // Check before asking for a password, not after
+    runtime.make_active_system_wide(strategy, true)?;
+
+    let exe = env::current_exe().map_err(|e| CliError::Spawn("xrpicker".to_owned(), e))?;
+    let manifest = runtime
+        .get_manifests()
+        .into_iter()
+        .next()
+        .expect("every runtime has at least one manifest");
+    // The helper may run in another directory, so it must not depend on ours
+    let manifest = manifest.canonicalize().map_err(xrpicker::Error::from)?;
+    let mut helper_args: Vec<OsString> = forwarded_args.to_vec();
+    helper_args.extend([
+        "system-helper".into(),
+        manifest.into(),
+        "--strategy".into(),
+        strategy.name().into(),
+    ]);
+    if dry_run {
+        helper_args.push("--dry-run".into());
+    }
+    // Pretending needs no privileges, and we may well own a system in another directory
+    let mut command = if dry_run || alternate_root || is_root() {
+        process::Command::new(&exe)
+    } else {
+        let elevate = ["pkexec", "sudo"]
+            .into_iter()
+            .find_map(find_in_path)
+            .ok_or(CliError::NoPrivilegeHelper)?;
+        let mut command = process::Command::new(elevate);
+        command.arg(&exe);
+        command
+    };
+    println!(
+        "Making {} active for all users:\n{}",
+        runtime.get_runtime_name(),
+        runtime.describe()
+    );
+    let status = command
+        .args(&helper_args)
+        .status()
+        .map_err(|e| CliError::Spawn(exe.display().to_string(), e))?;
+    if !status.success() {
+        return Err(CliError::HelperFailed(status));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The privileged side of `set --system`
+fn system_helper<T: Platform>(
+    platform: &T,
+    manifest: &Path,
+    strategy: ActivationStrategy,
+    dry_run: bool,
+) -> Result<ExitCode, CliError> {
+    let selector = manifest.to_string_lossy();
+    let runtimes = find_runtimes_for_selector(platform, &selector)?;
+    let runtime = select_runtime(&runtimes, &selector)?;
+    let changes = runtime.make_active_system_wide(strategy, dry_run)?;
+    println!("{}", if dry_run { "Would change:" } else { "Changed:" });
+    for change in changes {
+        println!("- {}", change);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Are we running as root already?
+fn is_root() -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata("/proc/self").is_ok_and(|m| m.uid() == 0)
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Find a program in the directories in `PATH`
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|p| p.is_file())
+    })
+}
+
+fn reset<T: Platform>(platform: &T) -> Result<ExitCode, CliError> {
+    let had_setting = platform.has_user_active_runtime(&platform.get_active_data());
+    let now_active = platform.clear_user_active_runtime()?;
+    if had_setting {
+        println!("Removed your own active runtime setting");
+    } else {
+        println!("You had no active runtime setting of your own");
+    }
+    match now_active {
+        Some((file, manifest)) => println!(
+            "Now in effect: {}{}",
+            file.display(),
+            if file == manifest {
+                String::new()
+            } else {
+                format!(" -> {}", manifest.display())
+            }
+        ),
+        None => println!("There is no system-wide active runtime, so none is active now"),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run<T: Platform>(
+    platform: &T,
+    selector: &str,
+    command: &[OsString],
+) -> Result<ExitCode, CliError> {
+    let runtimes = find_runtimes_for_selector(platform, selector)?;
+    let runtime = select_runtime(&runtimes, selector)?;
+    let manifest = runtime
+        .get_manifests()
+        .into_iter()
+        .next()
+        .expect("every runtime has at least one manifest");
+    let (program, args) = command
+        .split_first()
+        .expect("a command is required by the argument parser");
+    eprintln!(
+        "Running {} with {}={}",
+        program.to_string_lossy(),
+        RUNTIME_JSON_ENV_VAR,
+        manifest.display()
+    );
+    let status = process::Command::new(program)
+        .args(args)
+        .env(RUNTIME_JSON_ENV_VAR, manifest)
+        .status()
+        .map_err(|e| CliError::Spawn(program.to_string_lossy().into_owned(), e))?;
+    // Pass along the exit code of the program, as far as we can
+    Ok(match status.code() {
+        Some(0) => ExitCode::SUCCESS,
+        Some(code) => u8::try_from(code).map_or(ExitCode::FAILURE, ExitCode::from),
+        None => ExitCode::FAILURE,
+    })
+}
+
+fn deps<T: Platform>(platform: &T, selector: &str) -> Result<ExitCode, CliError> {
+    let runtimes = find_runtimes_for_selector(platform, selector)?;
+    let runtime = select_runtime(&runtimes, selector)?;
+    println!("{}:\n{}", runtime.get_runtime_name(), runtime.describe());
+    let mut any_missing = false;
+    // Problems with the runtime library itself, which keep us from looking further
+    for problem in runtime.get_problems() {
+        if let RuntimeProblem::LibraryNotFound(_)
+        | RuntimeProblem::LibraryNotInSearchPath(_)
+        | RuntimeProblem::LibraryUnreadable { .. } = problem
+        {
+            any_missing = true;
+            println!("    Problem: {}", problem);
+        }
+    }
+    let dependencies = runtime.get_dependencies();
+    if dependencies.is_empty() && !any_missing {
+        println!("No shared library dependencies found");
+    }
+    for dependency in &dependencies {
+        match &dependency.path {
+            Some(path) => println!("    {} => {}", dependency.name, path.display()),
+            None if dependency.provided_by_system => {
+                println!("    {} => provided by Windows", dependency.name)
+            }
+            None if dependency.is_vc_runtime() => {
+                any_missing = true;
+                println!(
+                    "    {} => not found, requires the Visual C++ Redistributable (needed by {})",
+                    dependency.name,
+                    dependency.needed_by.display()
+                );
+            }
+            None => {
+                any_missing = true;
+                println!(
+                    "    {} => not found (needed by {})",
+                    dependency.name,
+                    dependency.needed_by.display()
+                );
+            }
+        }
+    }
+    if any_missing {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn doctor<T: Platform>(platform: &T) -> Result<ExitCode, CliError> {
+    let diagnosis = platform.diagnose();
+    print!("{}", diagnosis);
+    if diagnosis.has_failures() {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn validate(manifests: &[PathBuf]) -> Result<ExitCode, CliError> {
+    let mut any_errors = false;
+    for manifest in manifests {
+        let findings = validate_runtime_manifest(manifest)?;
+        if findings.is_empty() {
+            println!("{}: OK", manifest.display());
+        }
+        for finding in &findings {
+            println!("{}:{}", manifest.display(), finding);
+        }
+        any_errors |= findings.iter().any(|f| f.severity == Severity::Error);
+    }
+    if any_errors {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn set_enabled<T: Platform>(
+    platform: &T,
+    selector: &str,
+    enabled: bool,
+) -> Result<ExitCode, CliError> {
+    let runtimes = find_runtimes_for_selector(platform, selector)?;
+    let runtime = select_runtime(&runtimes, selector)?;
+    platform.set_runtime_enabled(runtime, enabled)?;
+    println!(
+        "{} runtime {}",
+        if enabled { "Enabled" } else { "Disabled" },
+        runtime.get_runtime_name()
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+fn register<T: Platform>(platform: &T, manifest: &Path) -> Result<ExitCode, CliError> {
+    // Not canonicalized: on Windows that adds a `\\?\` prefix the loader would not expect
+    let manifest = env::current_dir()
+        .map_err(xrpicker::Error::from)?
+        .join(manifest);
+    platform.register_runtime(&manifest)?;
+    println!("Registered {}", manifest.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn unregister<T: Platform>(platform: &T, selector: &str) -> Result<ExitCode, CliError> {
+    let runtimes = find_runtimes_for_selector(platform, selector)?;
+    let runtime = select_runtime(&runtimes, selector)?;
+    platform.unregister_runtime(runtime)?;
+    println!("Unregistered runtime {}", runtime.get_runtime_name());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn export_reg(
+    manifest_64: Option<&Path>,
+    manifest_32: Option<&Path>,
+    output: Option<&Path>,
+) -> Result<ExitCode, CliError> {
+    let contents = active_runtime_reg_file(manifest_64, manifest_32);
+    match output {
+        Some(output) => {
+            // UTF-16LE with a byte order mark, as regedit itself writes
+            let bytes: Vec<u8> = iter::once('\u{feff}' as u16)
+                .chain(contents.encode_utf16())
+                .flat_map(u16::to_le_bytes)
+                .collect();
+            fs::write(output, bytes).map_err(xrpicker::Error::from)?;
+            println!("Wrote {}", output.display());
+        }
+        None => print!("{}", contents),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn layers<T: Platform>(platform: &T, command: LayersCommand) -> Result<ExitCode, CliError> {
+    let (layers, nonfatal_errors) = platform.find_api_layers()?;
+    match command {
+        LayersCommand::List => {
+            println!("\nAPI layers:");
+            for (i, layer) in layers.iter().enumerate() {
+                let state = match (layer.kind(), layer.is_enabled()) {
+                    (ApiLayerKind::Explicit, _) => "explicit",
+                    (ApiLayerKind::Implicit, true) => "implicit, enabled",
+                    (ApiLayerKind::Implicit, false) => "implicit, disabled",
+                };
+                println!("- [{}] {} ({})", i + 1, layer.name(), state);
+                if let Some(description) = layer.description() {
+                    println!("    {}", description);
+                }
+                println!("    {}", layer.describe());
+            }
+
+            if !nonfatal_errors.is_empty() {
+                println!("\nNon-fatal errors:");
+                for e in &nonfatal_errors {
+                    println!(
+                        "- Manifest: {} - Error: {}",
+                        e.0.display(),
+                        json::error_messages(&e.1).0
+                    );
+                }
+            }
+        }
+        LayersCommand::Enable { layer } => {
+            let layer = select_layer(&layers, &layer)?;
+            platform.set_api_layer_enabled(layer, true)?;
+            println!("Enabled API layer {}", layer.name());
+        }
+        LayersCommand::Disable { layer } => {
+            let layer = select_layer(&layers, &layer)?;
+            platform.set_api_layer_enabled(layer, false)?;
+            println!("Disabled API layer {}", layer.name());
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn history<T: Platform>(platform: &T, command: HistoryCommand) -> Result<ExitCode, CliError> {
+    match command {
+        HistoryCommand::List => {
+            let history = platform.get_activation_history()?;
+            if history.is_empty() {
+                println!("No changes to the active runtime have been recorded");
+            }
+            for (i, record) in history.iter().enumerate() {
+                println!("- [{}] {}", i + 1, record);
+            }
+        }
+        HistoryCommand::Undo => {
+            platform.undo_last_activation()?;
+            println!("Undid the last change to the active runtime");
+        }
+        HistoryCommand::Restore { index } => {
+            let history = platform.get_activation_history()?;
+            if index == 0 || index > history.len() {
+                return Err(CliError::HistoryIndexOutOfRange(index, history.len()));
+            }
+            platform.restore_activation(index - 1)?;
+            println!("Restored: {}", history[index - 1].new);
+        }
+        HistoryCommand::Clean {
+            keep,
+            remove_unrecorded,
+        } => {
+            let removed = platform.clean_activation_history(keep, remove_unrecorded)?;
+            println!("Removed {} backup(s) that are no longer needed", removed);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    #[cfg(unix)]
+    let (alternate_root, forwarded_args) = (cli.root.root.is_some(), cli.root.to_args());
+    #[cfg(not(unix))]
+    let (alternate_root, forwarded_args) = (false, vec![]);
+    #[cfg(unix)]
+    let platform = cli.root.make_platform();
+    #[cfg(not(unix))]
+    let platform = make_platform();
+    let command = cli.command.unwrap_or(Command::List {
+        format: OutputFormat::Text,
+    });
+    let result = match command {
+        Command::List { format } => list(&platform, format),
+        Command::Active => active(&platform),
+        Command::Set {
+            runtime,
+            strategy,
+            system: true,
+            dry_run,
+            ..
+        } => set_system(
+            &platform,
+            &runtime,
+            strategy.unwrap_or_default(),
+            dry_run,
+            alternate_root,
+            &forwarded_args,
+        ),
+        Command::Set {
+            runtime,
+            arch,
+            strategy,
+            ..
+        } => set(&platform, &runtime, arch, strategy.unwrap_or_default()),
+        Command::SystemHelper {
+            manifest,
+            strategy,
+            dry_run,
+        } => system_helper(&platform, &manifest, strategy.unwrap_or_default(), dry_run),
+        Command::Reset => reset(&platform),
+        Command::Run { runtime, command } => run(&platform, &runtime, &command),
+        Command::Deps { runtime } => deps(&platform, &runtime),
+        Command::Doctor => doctor(&platform),
+        Command::Validate { manifests } => validate(&manifests),
+        Command::Enable { runtime } => set_enabled(&platform, &runtime, true),
+        Command::Disable { runtime } => set_enabled(&platform, &runtime, false),
+        Command::Register { manifest } => register(&platform, &manifest),
+        Command::Unregister { runtime } => unregister(&platform, &runtime),
+        Command::ExportReg {
+            manifest_64,
+            manifest_32,
+            output,
+        } => export_reg(
+            manifest_64.as_deref(),
+            manifest_32.as_deref(),
+            output.as_deref(),
+        ),
+        Command::Layers { command } => layers(&platform, command.unwrap_or(LayersCommand::List)),
+        Command::History { command } => history(&platform, command.unwrap_or(HistoryCommand::List)),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    /// A system in a temporary directory with runtimes in manifests with the given file names
+    /// and runtime names, the first of them active, and a broken manifest
+    pub(crate) fn fake_root(runtimes: &[(&str, &str)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("etc/xdg/openxr/1");
+        fs::create_dir_all(&dir).unwrap();
+        for (file_name, name) in runtimes {
+            fs::write(
+                dir.join(file_name),
+                format!(
+                    r#"{{"file_format_version": "1.0.0", "runtime": {{"name": "{}", "library_path": "/usr/lib/libtest.so"}}}}"#,
+                    name
+                ),
+            )
+            .unwrap();
+        }
+        fs::write(dir.join("openxr_broken.json"), "<not JSON>").unwrap();
+        symlink(runtimes[0].0, dir.join("active_runtime.json")).unwrap();
+        root
+    }
+
+    pub(crate) fn platform(root: &tempfile::TempDir) -> xrpicker::LinuxPlatform {
+        xrpicker::make_platform_with_root(
+            &root.path().canonicalize().unwrap(),
+            xrpicker::XdgConfigVars::default(),
+        )
+    }
+
+    fn manifest<R: PlatformRuntime>(runtime: &R) -> PathBuf {
+        runtime.get_manifests()[0].to_owned()
+    }
+
+    #[test]
+    fn runtime_selection() {
+        let root = fake_root(&[
+            ("openxr_monado.json", "Monado"),
+            ("openxr_a.json", "Test"),
+            ("openxr_b.json", "Test"),
+            ("openxr_c.json", "test"),
+        ]);
+        let (runtimes, _) = platform(&root)
+            .find_available_runtimes(Box::new(iter::empty()))
+            .unwrap();
+        assert_eq!(runtimes.len(), 4);
+        let index_of = |file_name: &str| {
+            runtimes
+                .iter()
+                .position(|r| manifest(r).ends_with(file_name))
+                .unwrap()
+        };
+        let selected = |selector: &str| {
+            select_runtime(&runtimes, selector).map(|r| {
+                manifest(r)
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_owned()
+            })
+        };
+
+        assert_eq!(selected("Monado").unwrap(), "openxr_monado.json");
+        assert_eq!(selected("MONADO").unwrap(), "openxr_monado.json");
+        // An exact match wins over case-insensitive ones
+        assert_eq!(selected("test").unwrap(), "openxr_c.json");
+        assert_eq!(
+            selected(&(index_of("openxr_b.json") + 1).to_string()).unwrap(),
+            "openxr_b.json"
+        );
+        let path = root.path().join("etc/xdg/openxr/1/openxr_a.json");
+        assert_eq!(selected(path.to_str().unwrap()).unwrap(), "openxr_a.json");
+
+        // Ambiguous names list the index and manifest of each match, and only those
+        for (selector, file_names) in [
+            ("Test", vec!["openxr_a.json", "openxr_b.json"]),
+            (
+                "TEST",
+                vec!["openxr_a.json", "openxr_b.json", "openxr_c.json"],
+            ),
+        ] {
+            let error = selected(selector).unwrap_err();
+            let mut expected: Vec<usize> = file_names.iter().map(|f| index_of(f)).collect();
+            expected.sort();
+            let expected = expected
+                .into_iter()
+                .map(|i| format!("  [{}] {}", i + 1, manifest(&runtimes[i]).display()))
+                .join("\n");
+            assert!(
+                matches!(&error, CliError::Ambiguous(s, matches) if s == selector && *matches == expected),
+                "{:?}",
+                error
+            );
+        }
+
+        assert!(matches!(
+            selected("Other"),
+            Err(CliError::NoMatch(s)) if s == "Other"
+        ));
+        assert!(matches!(
+            selected("0"),
+            Err(CliError::IndexOutOfRange(0, 4))
+        ));
+        assert!(matches!(
+            selected("5"),
+            Err(CliError::IndexOutOfRange(5, 4))
+        ));
+    }
+}
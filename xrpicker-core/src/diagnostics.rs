@@ -0,0 +1,534 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! End-to-end diagnosis of the OpenXR runtime setup, as a checklist meant for humans
+//! (and for pasting into support requests).
+
+use std::{env, fmt::Display, path::Path};
+
+use crate::{
+    arch_detect::Architecture,
+    library::{missing_dependency_problems, RuntimeProblem},
+    manifest::GenericManifest,
+    pe_deps::is_vc_runtime,
+    runtime::BaseRuntime,
+    sysroot::Sysroot,
+    validate::{validate_runtime_manifest_in, Severity},
+    RUNTIME_JSON_ENV_VAR,
+};
+
+/// Environment variables that change which runtime or layers the OpenXR loader uses,
+/// and what that means.
+const LOADER_ENV_VARS: [(&str, &str); 4] = [
+    (
+        "XR_RUNTIME_JSON",
+        "the loader will use this runtime manifest, ignoring the active runtime setting",
+    ),
+    (
+        "XR_API_LAYER_PATH",
+        "the loader will look for explicit API layers only in these directories",
+    ),
+    (
+        "XR_ENABLE_API_LAYERS",
+        "the loader will enable these API layers for every application",
+    ),
+    (
+        "XR_LOADER_DEBUG",
+        "the loader will print debugging information",
+    ),
+];
+
+/// The outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// Everything is fine
+    Ok,
+    /// Not a problem, just something to know
+    Info,
+    /// Might be a problem, or might cause confusion
+    Warning,
+    /// Will prevent OpenXR applications from working
+    Failure,
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckStatus::Ok => write!(f, "[ OK ]"),
+            CheckStatus::Info => write!(f, "[INFO]"),
+            CheckStatus::Warning => write!(f, "[WARN]"),
+            CheckStatus::Failure => write!(f, "[FAIL]"),
+        }
+    }
+}
+
+/// One line of the checklist, with optional details and suggested fix
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub status: CheckStatus,
+    pub summary: String,
+    pub details: Vec<String>,
+    pub suggestion: Option<String>,
+}
+
+impl Check {
+    pub(crate) fn new(status: CheckStatus, summary: impl Into<String>) -> Self {
+        Self {
+            status,
+            summary: summary.into(),
+            details: vec![],
+            suggestion: None,
+        }
+    }
+
+    pub(crate) fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.details.push(detail.into());
+        self
+    }
+
+    pub(crate) fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}", self.status, self.summary)?;
+        for detail in &self.details {
+            writeln!(f, "       {}", detail)?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            writeln!(f, "       Suggestion: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// The full checklist produced by `Platform::diagnose()`
+#[derive(Debug, Clone, Default)]
+pub struct Diagnosis {
+    pub checks: Vec<Check>,
+}
+
+impl Diagnosis {
+    pub(crate) fn push(&mut self, check: Check) {
+        self.checks.push(check);
+    }
+
+    /// Did any check fail outright?
+    pub fn has_failures(&self) -> bool {
+        self.checks.iter().any(|c| c.status == CheckStatus::Failure)
+    }
+}
+
+impl Display for Diagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "xrpicker {} on {} ({})",
+            env!("CARGO_PKG_VERSION"),
+            env::consts::OS,
+            env::consts::ARCH
+        )?;
+        for check in &self.checks {
+            write!(f, "{}", check)?;
+        }
+        if self.has_failures() {
+            writeln!(f, "\nProblems were found: see [FAIL] entries above.")
+        } else {
+            writeln!(f, "\nNo blocking problems were found.")
+        }
+    }
+}
+
+/// Report any environment variables that change what the loader does.
+pub(crate) fn check_environment() -> Vec<Check> {
+    let mut checks: Vec<Check> = LOADER_ENV_VARS
+        .iter()
+        .filter_map(|(name, meaning)| {
+            let value = env::var_os(name)?;
+            Some(
+                Check::new(
+                    CheckStatus::Warning,
+                    format!("{} is set: {}", name, meaning),
+                )
+                .with_detail(format!("{}={}", name, value.to_string_lossy())),
+            )
+        })
+        .collect();
+    if let Some(runtime_json) = env::var_os("XR_RUNTIME_JSON") {
+        let path = Path::new(&runtime_json);
+        if !path.is_file() {
+            checks.push(
+                Check::new(
+                    CheckStatus::Failure,
+                    "XR_RUNTIME_JSON points to a file that does not exist",
+                )
+                .with_detail(path.display().to_string())
+                .with_suggestion("Unset XR_RUNTIME_JSON or point it to an existing manifest"),
+            );
+        }
+    }
+    if checks.is_empty() {
+        checks.push(Check::new(
+            CheckStatus::Ok,
+            "No environment variables override the loader's behavior",
+        ));
+    }
+    checks
+}
+
+/// Check the manifest `XR_RUNTIME_JSON` points to, which the loader uses instead of the active
+/// runtime setting, like `check_active_manifest()`.
+///
+/// Empty if it does not exist, which `check_environment()` already reports.
+pub(crate) fn check_override_manifest(
+    manifest_path: &Path,
+    expected_arch: Option<Architecture>,
+) -> Vec<Check> {
+    if !manifest_path.is_file() {
+        return vec![];
+    }
+    let mut checks = vec![Check::new(
+        CheckStatus::Info,
+        format!(
+            "{} is used as the active runtime, since {} is set",
+            manifest_path.display(),
+            RUNTIME_JSON_ENV_VAR
+        ),
+    )];
+    checks.extend(check_active_manifest(
+        manifest_path,
+        &Sysroot::host(),
+        expected_arch,
+    ));
+    checks
+}
+
+/// Check the manifest that the loader would use, and the library it points to.
+///
+/// `manifest_path` is a path on the host, in the system described by `sysroot`.
+/// `expected_arch` is the architecture the library must have, or `None` to accept any.
+pub(crate) fn check_active_manifest(
+    manifest_path: &Path,
+    sysroot: &Sysroot,
+    expected_arch: Option<Architecture>,
+) -> Vec<Check> {
+    let mut checks = vec![];
+    let manifest_desc = manifest_path.display();
+
+    match validate_runtime_manifest_in(manifest_path, sysroot) {
+        Err(e) => {
+            checks.push(
+                Check::new(
+                    CheckStatus::Failure,
+                    format!(
+                        "Could not read the active runtime manifest {}",
+                        manifest_desc
+                    ),
+                )
+                .with_detail(e.to_string())
+                .with_suggestion("Check the file permissions, or choose another runtime"),
+            );
+            return checks;
+        }
+        Ok(findings) => {
+            let errors: Vec<String> = findings
+                .iter()
+                .filter(|f| f.severity == Severity::Error)
+                .map(|f| f.to_string())
+                .collect();
+            let warnings: Vec<String> = findings
+                .iter()
+                .filter(|f| f.severity == Severity::Warning)
+                .map(|f| f.to_string())
+                .collect();
+            if !errors.is_empty() {
+                let mut check = Check::new(
+                    CheckStatus::Failure,
+                    format!("The active runtime manifest {} has errors", manifest_desc),
+                )
+                .with_suggestion(
+                    "Reinstall the runtime, or choose another one with `xrpicker set`",
+                );
+                check.details = errors;
+                checks.push(check);
+            } else {
+                checks.push(Check::new(
+                    CheckStatus::Ok,
+                    format!("The active runtime manifest {} is valid", manifest_desc),
+                ));
+            }
+            if !warnings.is_empty() {
+                let mut check = Check::new(
+                    CheckStatus::Warning,
+                    "The active runtime manifest has warnings",
+                );
+                check.details = warnings;
+                checks.push(check);
+            }
+        }
+    }
+
+    let runtime = match BaseRuntime::new(manifest_path) {
+        Ok(r) => r,
+        // Already reported above
+        Err(_) => return checks,
+    };
+    let library_path = runtime.resolve_library_path_in(sysroot);
+    if runtime.uses_search_path() {
+        if !library_path.is_absolute() {
+            checks.push(
+                Check::new(
+                    CheckStatus::Failure,
+                    format!(
+                        "The runtime library {} was not found in the dynamic library search path",
+                        runtime.library_path()
+                    ),
+                )
+                .with_suggestion(
+                    "Install the runtime, or change the manifest to give the full path to the library",
+                ),
+            );
+            return checks;
+        }
+        checks.push(Check::new(
+            CheckStatus::Info,
+            format!(
+                "The runtime library {} is found using the dynamic library search path",
+                runtime.library_path()
+            ),
+        ));
+    }
+    if !library_path.is_file() {
+        // The validation above already reports this
+        return checks;
+    }
+    checks.push(Check::new(
+        CheckStatus::Ok,
+        format!("The runtime library {} exists", library_path.display()),
+    ));
+
+    let inspection = runtime.inspect_library_in(sysroot);
+    match (inspection.architecture, expected_arch) {
+        (None, _) => checks.push(
+            Check::new(
+                CheckStatus::Failure,
+                "Could not identify the runtime library as a shared library",
+            )
+            .with_detail(library_path.display().to_string())
+            .with_suggestion("Reinstall the runtime"),
+        ),
+        (Some(arch), Some(expected)) if arch != expected => checks.push(
+            Check::new(
+                CheckStatus::Failure,
+                format!(
+                    "The runtime library is built for {}, but {} is needed",
+                    arch, expected
+                ),
+            )
+            .with_detail(library_path.display().to_string())
+            .with_suggestion(format!(
+                "Install or register the {} build of the runtime",
+                expected
+            )),
+        ),
+        (Some(arch), _) => checks.push(Check::new(
+            CheckStatus::Ok,
+            format!("The runtime library is built for {}", arch),
+        )),
+    }
+
+    let dependencies = runtime.resolve_dependencies_in(sysroot);
+    let dependency_problems = missing_dependency_problems(&library_path, &dependencies);
+    let mut exports_ok = true;
+    let mut dependencies_ok = true;
+    for problem in inspection.problems.iter().chain(&dependency_problems) {
+        // Other problems with the library itself are reported above
+        match problem {
+            RuntimeProblem::MissingNegotiateExport { .. } => {
+                exports_ok = false;
+                checks.push(
+                    Check::new(CheckStatus::Failure, problem.to_string()).with_suggestion(
+                        "The manifest may point to the wrong library: reinstall the runtime",
+                    ),
+                );
+            }
+            RuntimeProblem::MissingDependency { dependency, .. } => {
+                dependencies_ok = false;
+                let suggestion = if is_vc_runtime(dependency) {
+                    "Install the latest Visual C++ Redistributable from Microsoft for the \
+                     runtime's architecture, or reinstall the runtime"
+                        .to_owned()
+                } else {
+                    format!(
+                        "Install the package that provides {}, or reinstall the runtime",
+                        dependency
+                    )
+                };
+                checks.push(
+                    Check::new(CheckStatus::Failure, problem.to_string())
+                        .with_suggestion(suggestion),
+                );
+            }
+            _ => {}
+        }
+    }
+    if exports_ok && inspection.architecture.is_some() {
+        checks.push(Check::new(
+            CheckStatus::Ok,
+            format!(
+                "The runtime library exports {}",
+                runtime.negotiate_function_name()
+            ),
+        ));
+    }
+    if dependencies_ok && !dependencies.is_empty() {
+        checks.push(Check::new(
+            CheckStatus::Ok,
+            format!(
+                "All shared libraries the runtime library needs were found ({} in all)",
+                dependencies.len()
+            ),
+        ));
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_binaries::elf_library;
+
+    const MANIFEST: &str = "usr/share/openxr/1/openxr_test.json";
+
+    /// Check a runtime in a temporary root, whose library (if any) has the given contents, and
+    /// which has `libpresent.so.1` for it to need
+    fn check(library: Option<Vec<u8>>, expected_arch: Option<Architecture>) -> Vec<Check> {
+        let root = tempfile::tempdir().unwrap();
+        let manifest = root.path().join(MANIFEST);
+        fs::create_dir_all(manifest.parent().unwrap()).unwrap();
+        fs::write(
+            &manifest,
+            r#"{"file_format_version": "1.0.0", "runtime": {"name": "Test", "library_path": "/usr/lib/libtest.so"}}"#,
+        )
+        .unwrap();
+        let lib = root.path().join("usr/lib");
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(lib.join("libpresent.so.1"), elf_library(&[], &[])).unwrap();
+        if let Some(library) = library {
+            fs::write(lib.join("libtest.so"), library).unwrap();
+        }
+        check_active_manifest(&manifest, &Sysroot::new(root.path()), expected_arch)
+    }
+
+    fn summaries(checks: &[Check], status: CheckStatus) -> Vec<&str> {
+        checks
+            .iter()
+            .filter(|c| c.status == status)
+            .map(|c| c.summary.as_str())
+            .collect()
+    }
+
+    fn runtime_library(needed: &[&str]) -> Vec<u8> {
+        elf_library(needed, &["xrNegotiateLoaderRuntimeInterface"])
+    }
+
+    #[test]
+    fn working_runtime() {
+        let checks = check(
+            Some(runtime_library(&["libpresent.so.1"])),
+            Some(Architecture::X86_64),
+        );
+        assert!(summaries(&checks, CheckStatus::Failure).is_empty());
+        let ok = summaries(&checks, CheckStatus::Ok);
+        assert_eq!(ok.len(), 5, "{:?}", ok);
+        assert!(ok[0].ends_with("openxr_test.json is valid"));
+        assert!(ok[1].ends_with("libtest.so exists"));
+        assert_eq!(ok[2], "The runtime library is built for x86_64");
+        assert_eq!(
+            ok[3],
+            "The runtime library exports xrNegotiateLoaderRuntimeInterface"
+        );
+        assert_eq!(
+            ok[4],
+            "All shared libraries the runtime library needs were found (1 in all)"
+        );
+    }
+
+    #[test]
+    fn unreadable_manifest() {
+        let root = tempfile::tempdir().unwrap();
+        let checks = check_active_manifest(
+            &root.path().join(MANIFEST),
+            &Sysroot::new(root.path()),
+            None,
+        );
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].status, CheckStatus::Failure);
+        assert!(checks[0].summary.starts_with("Could not read"));
+    }
+
+    #[test]
+    fn missing_library() {
+        let checks = check(None, None);
+        assert_eq!(summaries(&checks, CheckStatus::Failure).len(), 1);
+        assert!(summaries(&checks, CheckStatus::Failure)[0].ends_with("has errors"));
+        assert!(summaries(&checks, CheckStatus::Ok).is_empty());
+    }
+
+    #[test]
+    fn not_a_library() {
+        let checks = check(Some(b"#!/bin/sh\n".to_vec()), None);
+        assert_eq!(
+            summaries(&checks, CheckStatus::Failure),
+            ["Could not identify the runtime library as a shared library"]
+        );
+    }
+
+    #[test]
+    fn wrong_architecture() {
+        let checks = check(Some(runtime_library(&[])), Some(Architecture::Aarch64));
+        assert_eq!(
+            summaries(&checks, CheckStatus::Failure),
+            ["The runtime library is built for x86_64, but aarch64 is needed"]
+        );
+    }
+
+    #[test]
+    fn missing_export() {
+        let checks = check(Some(elf_library(&[], &["somethingElse"])), None);
+        let failures = summaries(&checks, CheckStatus::Failure);
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].contains("does not export xrNegotiateLoaderRuntimeInterface"));
+    }
+
+    #[test]
+    fn missing_dependency() {
+        let checks = check(
+            Some(runtime_library(&["libpresent.so.1", "libmissing.so.1"])),
+            None,
+        );
+        let failures: Vec<&Check> = checks
+            .iter()
+            .filter(|c| c.status == CheckStatus::Failure)
+            .collect();
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0]
+            .summary
+            .ends_with("needs libmissing.so.1, which could not be found"));
+        assert_eq!(
+            failures[0].suggestion.as_deref(),
+            Some("Install the package that provides libmissing.so.1, or reinstall the runtime")
+        );
+    }
+
+    #[test]
+    fn missing_override_manifest() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(check_override_manifest(&root.path().join("missing.json"), None).is_empty());
+    }
+}
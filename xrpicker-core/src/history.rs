@@ -0,0 +1,339 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! A record of the changes we make to active runtime files, so they can be undone.
+
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// How many changes to remember unless told otherwise: backups only needed to undo older
+/// changes are removed.
+pub const DEFAULT_HISTORY_LENGTH: usize = 20;
+
+/// File name of the history, in our own per-user config directory
+pub(crate) const HISTORY_FILENAME: &str = "activation_history.json";
+
+/// What an active runtime file was set to, before or after a change.
+///
+/// All paths are as seen inside the system being configured.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "path", rename_all = "snake_case")]
+pub enum ActiveRuntimeSetting {
+    /// The file did not exist
+    Unset,
+    /// A symlink to this manifest
+    Symlink(PathBuf),
+    /// A regular file, of which this is a backup copy
+    Backup(PathBuf),
+}
+
+impl ActiveRuntimeSetting {
+    /// The backup file this setting needs, if any
+    fn backup(&self) -> Option<&Path> {
+        match self {
+            ActiveRuntimeSetting::Backup(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ActiveRuntimeSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActiveRuntimeSetting::Unset => write!(f, "(none)"),
+            ActiveRuntimeSetting::Symlink(path) => write!(f, "{}", path.display()),
+            ActiveRuntimeSetting::Backup(path) => write!(f, "a copy saved as {}", path.display()),
+        }
+    }
+}
+
+/// One change to an active runtime file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivationRecord {
+    /// When the change was made, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The active runtime file that was changed, like `active_runtime.json`, or our
+    /// `environment.d` snippet
+    pub file_name: String,
+    pub previous: ActiveRuntimeSetting,
+    pub new: ActiveRuntimeSetting,
+    /// The program (and version) that made the change
+    pub tool: String,
+    /// Changes made together (like by one reset) share a group, and are undone together
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<u64>,
+}
+
+impl ActivationRecord {
+    /// Describe a change being made right now by this program
+    pub(crate) fn now(
+        file_name: &str,
+        previous: ActiveRuntimeSetting,
+        new: ActiveRuntimeSetting,
+    ) -> Self {
+        let tool = env::current_exe()
+            .ok()
+            .and_then(|exe| exe.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_owned());
+        Self {
+            timestamp: unix_time_now(),
+            file_name: file_name.to_owned(),
+            previous,
+            new,
+            tool: format!("{} {}", tool, env!("CARGO_PKG_VERSION")),
+            group: None,
+        }
+    }
+}
+
+impl Display for ActivationRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} UTC, {}: {} -> {} (by {})",
+            format_unix_time(self.timestamp),
+            self.file_name,
+            self.previous,
+            self.new,
+            self.tool
+        )
+    }
+}
+
+pub(crate) fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Format seconds since the Unix epoch as a UTC date and time, like `2023-05-01 13:45:00`
+fn format_unix_time(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    // Convert days to a civil date: see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The changes we have made, oldest first, as stored on disk
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ActivationHistory {
+    pub(crate) entries: Vec<ActivationRecord>,
+}
+
+impl ActivationHistory {
+    /// Load the history, which is empty if the file does not exist yet
+    pub(crate) fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Add changes made together, so they are undone together
+    pub(crate) fn push_group(&mut self, mut records: Vec<ActivationRecord>) {
+        if records.len() > 1 {
+            let group = self
+                .entries
+                .iter()
+                .filter_map(|entry| entry.group)
+                .max()
+                .map_or(1, |group| group + 1);
+            for record in &mut records {
+                record.group = Some(group);
+            }
+        }
+        self.entries.append(&mut records);
+    }
+
+    /// The indices of the changes made together with the one at `index`, including it
+    pub(crate) fn group_around(&self, index: usize) -> Range<usize> {
+        let group = match self.entries.get(index) {
+            Some(entry) => entry.group,
+            None => return index..index,
+        };
+        if group.is_none() {
+            return index..index + 1;
+        }
+        let start = self.entries[..index]
+            .iter()
+            .rposition(|entry| entry.group != group)
+            .map_or(0, |i| i + 1);
+        let end = self.entries[index..]
+            .iter()
+            .position(|entry| entry.group != group)
+            .map_or(self.entries.len(), |i| index + i);
+        start..end
+    }
+
+    /// All the backup files the remaining entries need
+    pub(crate) fn backups(&self) -> impl '_ + Iterator<Item = &Path> {
+        self.entries
+            .iter()
+            .flat_map(|entry| [entry.previous.backup(), entry.new.backup()])
+            .flatten()
+    }
+
+    /// Forget all but the most recent `keep` entries.
+    ///
+    /// Returns the backup files that only the forgotten entries needed.
+    pub(crate) fn prune(&mut self, keep: usize) -> Vec<PathBuf> {
+        if self.entries.len() <= keep {
+            return vec![];
+        }
+        let forgotten: Vec<ActivationRecord> =
+            self.entries.drain(..self.entries.len() - keep).collect();
+        let mut unneeded: Vec<PathBuf> = vec![];
+        for backup in forgotten
+            .iter()
+            .flat_map(|entry| [entry.previous.backup(), entry.new.backup()])
+            .flatten()
+        {
+            if !self.backups().any(|b| b == backup) && !unneeded.iter().any(|b| b == backup) {
+                unneeded.push(backup.to_owned());
+            }
+        }
+        unneeded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_time_formatting() {
+        assert_eq!(format_unix_time(0), "1970-01-01 00:00:00");
+        assert_eq!(format_unix_time(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_unix_time(1_682_948_700), "2023-05-01 13:45:00");
+        assert_eq!(format_unix_time(4_107_542_399), "2100-02-28 23:59:59");
+    }
+
+    fn record(timestamp: u64, previous: ActiveRuntimeSetting) -> ActivationRecord {
+        ActivationRecord {
+            timestamp,
+            file_name: "active_runtime.json".to_owned(),
+            previous,
+            new: ActiveRuntimeSetting::Symlink(PathBuf::from("/usr/share/openxr/1/test.json")),
+            tool: "xrpicker 1.0.0".to_owned(),
+            group: None,
+        }
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let history = ActivationHistory {
+            entries: vec![
+                record(1, ActiveRuntimeSetting::Unset),
+                record(
+                    2,
+                    ActiveRuntimeSetting::Backup(PathBuf::from("/old_active_runtime2.json")),
+                ),
+            ],
+        };
+        let json = serde_json::to_string(&history).unwrap();
+        assert!(json.contains(r#"{"kind":"unset"}"#));
+        assert!(json.contains(r#"{"kind":"backup","path":"/old_active_runtime2.json"}"#));
+        assert!(json.contains(r#"{"kind":"symlink","path":"/usr/share/openxr/1/test.json"}"#));
+        let parsed: ActivationHistory = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, history);
+    }
+
+    #[test]
+    fn missing_history_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join(HISTORY_FILENAME);
+        assert_eq!(
+            ActivationHistory::load(&path).unwrap(),
+            ActivationHistory::default()
+        );
+        let history = ActivationHistory {
+            entries: vec![record(1, ActiveRuntimeSetting::Unset)],
+        };
+        history.save(&path).unwrap();
+        assert_eq!(ActivationHistory::load(&path).unwrap(), history);
+    }
+
+    #[test]
+    fn prune_returns_only_unneeded_backups() {
+        let shared = PathBuf::from("/shared.json");
+        let mut history = ActivationHistory {
+            entries: vec![
+                record(
+                    1,
+                    ActiveRuntimeSetting::Backup(PathBuf::from("/only_old.json")),
+                ),
+                record(2, ActiveRuntimeSetting::Backup(shared.clone())),
+                record(3, ActiveRuntimeSetting::Backup(shared.clone())),
+            ],
+        };
+        assert!(history.clone().prune(3).is_empty());
+        assert_eq!(history.prune(1), vec![PathBuf::from("/only_old.json")]);
+        assert_eq!(history.entries.len(), 1);
+        assert_eq!(
+            history.backups().collect::<Vec<_>>(),
+            vec![shared.as_path()]
+        );
+    }
+
+    #[test]
+    fn groups() {
+        let mut history = ActivationHistory::default();
+        history.push_group(vec![record(1, ActiveRuntimeSetting::Unset)]);
+        history.push_group(vec![
+            record(2, ActiveRuntimeSetting::Unset),
+            record(2, ActiveRuntimeSetting::Unset),
+        ]);
+        history.push_group(vec![
+            record(3, ActiveRuntimeSetting::Unset),
+            record(3, ActiveRuntimeSetting::Unset),
+        ]);
+        let groups: Vec<Option<u64>> = history.entries.iter().map(|e| e.group).collect();
+        assert_eq!(groups, vec![None, Some(1), Some(1), Some(2), Some(2)]);
+        assert_eq!(history.group_around(0), 0..1);
+        assert_eq!(history.group_around(1), 1..3);
+        assert_eq!(history.group_around(2), 1..3);
+        assert_eq!(history.group_around(4), 3..5);
+        assert_eq!(history.group_around(5), 5..5);
+        // Older histories have no groups at all
+        let json = serde_json::to_string(&history.entries[0]).unwrap();
+        assert!(!json.contains("group"));
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! What we know about specific runtimes, to name runtimes whose manifests do not, and to find
+//! runtimes installed where the loader will not see them.
+//!
+//! The data is in `known_runtimes.json`, built in. Users can add to it, or replace entries by
+//! `id`, with a file of the same format named `known_runtimes.json` in our per-user config
+//! directory.
+//!
+//! Install locations are paths with `/` as the separator, which may start with a placeholder:
+//! `{program_files}`, `{program_files_x86}`, `{system_64}`, or `{system_32}` on Windows, and
+//! `{data_home}` (usually `~/.local/share`) on Linux.
+
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use serde::Deserialize;
+
+use crate::Error;
+
+/// File name of the user's own additions, in our per-user config directory
+pub const KNOWN_RUNTIMES_FILENAME: &str = "known_runtimes.json";
+
+const EMBEDDED: &str = include_str!("known_runtimes.json");
+
+/// Where a runtime might be installed on Windows
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct WindowsLocation {
+    pub manifest_64: Option<String>,
+    pub manifest_32: Option<String>,
+}
+
+/// Where a runtime might be installed, per OS, if the loader might not find it otherwise
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct KnownLocations {
+    pub windows: Vec<WindowsLocation>,
+    /// Manifest paths
+    pub linux: Vec<String>,
+}
+
+/// A runtime we know about
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct KnownRuntime {
+    /// Short, unique, and stable: used to replace an entry with a user's own
+    pub id: String,
+    /// A name for the runtime, for manifests that do not have one
+    pub name: String,
+    pub vendor: String,
+    pub homepage: Option<String>,
+    /// Does the runtime do its work in a separate service (like a compositor), rather than in
+    /// the application's process?
+    pub out_of_process: bool,
+    /// Case-insensitive substrings of the runtime library's file name. One starting with `/`
+    /// only matches at the start of the file name.
+    pub library_patterns: Vec<String>,
+    #[serde(default)]
+    pub locations: KnownLocations,
+}
+
+impl KnownRuntime {
+    /// Is this runtime's library the one at `library_path`, going by its file name?
+    fn matches_library(&self, library_path: &str) -> bool {
+        // Either separator, since Windows manifests may be inspected elsewhere
+        let file_name = library_path
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let file_name = format!("/{}", file_name);
+        self.library_patterns
+            .iter()
+            .any(|pattern| file_name.contains(&pattern.to_lowercase()))
+    }
+}
+
+impl Display for KnownRuntime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} by {}", self.name, self.vendor)?;
+        if self.out_of_process {
+            write!(f, ", out-of-process")?;
+        }
+        if let Some(homepage) = &self.homepage {
+            write!(f, " - {}", homepage)?;
+        }
+        Ok(())
+    }
+}
+
+/// A collection of known runtimes: the built-in ones, plus any from the user
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct KnownRuntimes {
+    runtimes: Vec<KnownRuntime>,
+}
+
+impl KnownRuntimes {
+    /// The built-in collection
+    pub fn embedded() -> &'static KnownRuntimes {
+        static EMBEDDED_RUNTIMES: OnceLock<KnownRuntimes> = OnceLock::new();
+        EMBEDDED_RUNTIMES.get_or_init(|| {
+            serde_json::from_str(EMBEDDED).expect("the built-in known runtimes are valid")
+        })
+    }
+
+    /// The built-in collection, with the entries in the file at `user_path` (if it exists) taking
+    /// precedence: they replace built-in entries with the same `id`, and are checked first.
+    pub fn load(user_path: &Path) -> Result<Self, Error> {
+        let embedded = Self::embedded();
+        let contents = match fs::read_to_string(user_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(embedded.clone()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut known: KnownRuntimes = serde_json::from_str(&contents)?;
+        let built_in: Vec<KnownRuntime> = embedded
+            .runtimes
+            .iter()
+            .filter(|runtime| !known.runtimes.iter().any(|r| r.id == runtime.id))
+            .cloned()
+            .collect();
+        known.runtimes.extend(built_in);
+        Ok(known)
+    }
+
+    /// All the known runtimes, in the order they are checked
+    pub fn runtimes(&self) -> &[KnownRuntime] {
+        &self.runtimes
+    }
+
+    /// Identify the runtime whose library is at `library_path`, as written in its manifest
+    pub fn identify(&self, library_path: &str) -> Option<&KnownRuntime> {
+        self.runtimes
+            .iter()
+            .find(|runtime| runtime.matches_library(library_path))
+    }
+}
+
+/// Turn an install location into a path, replacing its placeholder (if any) using `folder`.
+///
+/// `None` if the placeholder is unknown, or `folder` has no value for it.
+pub(crate) fn expand_location(
+    location: &str,
+    folder: impl Fn(&str) -> Option<PathBuf>,
+) -> Option<PathBuf> {
+    let mut components = location.split('/');
+    let mut path = match location.strip_prefix('{') {
+        Some(rest) => {
+            let (placeholder, _) = rest.split_once('}')?;
+            components.next();
+            folder(placeholder)?
+        }
+        // Absolute
+        None if location.starts_with('/') => {
+            components.next();
+            PathBuf::from("/")
+        }
+        None => PathBuf::new(),
+    };
+    path.extend(components.filter(|c| !c.is_empty()));
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identify(library_path: &str) -> Option<&'static str> {
+        KnownRuntimes::embedded()
+            .identify(library_path)
+            .map(|runtime| runtime.id.as_str())
+    }
+
+    #[test]
+    fn identify_by_library_path() {
+        assert_eq!(
+            identify("C:\\Program Files (x86)\\Steam\\steamapps\\common\\SteamVR\\bin\\win64\\vrclient_x64.dll"),
+            Some("steamvr")
+        );
+        assert_eq!(identify("../lib/libopenxr_monado.so"), Some("monado"));
+        assert_eq!(identify("libopenxr_monado.so"), Some("monado"));
+        assert_eq!(identify("/usr/lib/libopenxr_monado.so"), Some("monado"));
+        assert_eq!(identify("pico_openxr_runtime.dll"), Some("pico"));
+        assert_eq!(identify("/usr/lib/libalvr_openxr.so"), Some("alvr"));
+        assert_eq!(identify("alvr_runtime.dll"), Some("alvr"));
+        // Only the start of the file name, for patterns that say so
+        assert_eq!(identify("epico_runtime.dll"), None);
+        assert_eq!(identify("libmyalvr.so"), None);
+    }
+
+    #[test]
+    fn directories_do_not_identify() {
+        assert_eq!(
+            identify("/home/me/monado-build/lib/libopenxr_wivrn.so"),
+            Some("wivrn")
+        );
+        assert_eq!(identify("/home/pico/runtime/libruntime.so"), None);
+        assert_eq!(
+            identify("C:\\Program Files\\PICO Connect\\vrclient_x64.dll"),
+            Some("steamvr")
+        );
+    }
+
+    #[test]
+    fn user_entries_take_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_path = dir.path().join(KNOWN_RUNTIMES_FILENAME);
+        assert_eq!(
+            KnownRuntimes::load(&user_path).unwrap(),
+            KnownRuntimes::embedded().clone()
+        );
+        fs::write(
+            &user_path,
+            r#"{"runtimes": [{"id": "monado", "name": "My Monado", "vendor": "Me",
+                "homepage": null, "out_of_process": true, "library_patterns": ["/libmine"]}]}"#,
+        )
+        .unwrap();
+        let known = KnownRuntimes::load(&user_path).unwrap();
+        assert_eq!(
+            known.runtimes().len(),
+            KnownRuntimes::embedded().runtimes().len()
+        );
+        assert_eq!(known.identify("/opt/libmine.so").unwrap().name, "My Monado");
+        assert!(known.identify("libopenxr_monado.so").is_none());
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! OpenXR API layers: parsed manifests, and platform-independent parts of finding them.
+
+use std::{
+    collections::HashSet,
+    env,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    manifest::{ApiLayerManifest, GenericManifest},
+    Error, ManifestError,
+};
+
+/// Environment variable that, when set, replaces the directories searched for explicit API layers
+pub(crate) const API_LAYER_PATH_ENV_VAR: &str = "XR_API_LAYER_PATH";
+
+/// Suffix we add to the file name of an implicit layer manifest to disable it,
+/// where disabling is done by renaming (on Linux, for layers in per-user directories).
+pub(crate) const DISABLED_SUFFIX: &str = ".disabled";
+
+/// Whether the loader loads a layer on its own, or only when asked to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApiLayerKind {
+    /// Loaded for every application, unless disabled
+    Implicit,
+    /// Only loaded when an application, or `XR_ENABLE_API_LAYERS`, asks for it
+    Explicit,
+}
+
+impl ApiLayerKind {
+    /// The name of the directory these layer manifests are in, on platforms that use directories
+    pub(crate) fn dir_name(&self) -> &'static str {
+        match self {
+            ApiLayerKind::Implicit => "implicit.d",
+            ApiLayerKind::Explicit => "explicit.d",
+        }
+    }
+}
+
+impl Display for ApiLayerKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiLayerKind::Implicit => write!(f, "implicit"),
+            ApiLayerKind::Explicit => write!(f, "explicit"),
+        }
+    }
+}
+
+/// An API layer manifest we found, and whether it is enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiLayer {
+    manifest_path: PathBuf,
+    kind: ApiLayerKind,
+    enabled: bool,
+    manifest: ApiLayerManifest,
+}
+
+impl ApiLayer {
+    /// Create from a manifest path.
+    ///
+    /// `manifest_path` is where the manifest was found (not necessarily canonical),
+    /// and `load_path` is where to actually read it from.
+    pub(crate) fn new(
+        manifest_path: &Path,
+        load_path: &Path,
+        kind: ApiLayerKind,
+        enabled: bool,
+    ) -> Result<Self, Error> {
+        let contents = fs::read_to_string(load_path)?;
+        let manifest: ApiLayerManifest = serde_json::from_str(&contents)?;
+        if !manifest.is_file_format_version_ok() {
+            return Err(Error::ManifestVersionMismatch);
+        }
+        Ok(Self {
+            manifest_path: manifest_path.to_owned(),
+            kind,
+            enabled,
+            manifest,
+        })
+    }
+
+    /// The layer name, like `XR_APILAYER_LUNARG_core_validation`
+    pub fn name(&self) -> &str {
+        &self.manifest.api_layer.name
+    }
+
+    /// Where we found the manifest: for a disabled layer, this is its disabled name
+    pub fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    pub fn kind(&self) -> ApiLayerKind {
+        self.kind
+    }
+
+    /// Is this layer enabled? Explicit layers are always considered enabled,
+    /// though they are only used when requested.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn api_version(&self) -> Option<&str> {
+        self.manifest.api_layer.api_version.as_deref()
+    }
+
+    pub fn implementation_version(&self) -> Option<&str> {
+        self.manifest.api_layer.implementation_version.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.manifest.api_layer.description.as_deref()
+    }
+
+    /// The environment variable that disables this (implicit) layer, if any
+    pub fn disable_environment(&self) -> Option<&str> {
+        self.manifest.api_layer.disable_environment.as_deref()
+    }
+
+    /// The environment variable that must be set for this (implicit) layer to be loaded, if any
+    pub fn enable_environment(&self) -> Option<&str> {
+        self.manifest.api_layer.enable_environment.as_deref()
+    }
+
+    /// This layer, marked enabled or not: for when something other than the manifest file
+    /// name disables it
+    pub(crate) fn with_enabled(self, enabled: bool) -> Self {
+        Self { enabled, ..self }
+    }
+
+    /// Describe this layer using the manifest path and library path
+    pub fn describe(&self) -> String {
+        self.manifest.describe_manifest(&self.manifest_path)
+    }
+}
+
+/// The directories from `XR_API_LAYER_PATH`, if it is set:
+/// the loader searches only these for explicit layers.
+pub(crate) fn environment_explicit_layer_dirs() -> Option<Vec<PathBuf>> {
+    env::var_os(API_LAYER_PATH_ENV_VAR)
+        .filter(|v| !v.is_empty())
+        .map(|v| env::split_paths(&v).collect())
+}
+
+/// The layer manifests in a directory, with whether each is enabled.
+///
+/// Files named `*.json` are enabled. Files named `*.json.disabled` are disabled, but only
+/// returned for implicit layers. Missing or unreadable directories are just skipped.
+/// The paths are not checked further: they might be dangling symlinks.
+pub(crate) fn layer_manifests_in_dir(dir: &Path, kind: ApiLayerKind) -> Vec<(PathBuf, bool)> {
+    let disabled_extension = format!(".json{}", DISABLED_SUFFIX);
+    let mut manifests: Vec<(PathBuf, bool)> = dir
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|r| r.ok())
+        .map(|entry| entry.path())
+        .filter_map(|path| {
+            let file_name = path.file_name()?.to_str()?;
+            if file_name.ends_with(".json") {
+                Some((path, true))
+            } else if kind == ApiLayerKind::Implicit && file_name.ends_with(&disabled_extension) {
+                Some((path, false))
+            } else {
+                None
+            }
+        })
+        .collect();
+    // read_dir order is arbitrary, so make our output stable
+    manifests.sort();
+    manifests
+}
+
+/// Helper for accumulating layers, skipping duplicates, and keeping non-fatal errors.
+#[derive(Default)]
+pub(crate) struct ApiLayerCollection {
+    layers: Vec<ApiLayer>,
+    nonfatal_errors: Vec<ManifestError>,
+    known_manifests: HashSet<PathBuf>,
+}
+
+impl ApiLayerCollection {
+    /// Try to add a layer found at `manifest_path`, which canonicalizes to `canonical`.
+    pub(crate) fn add(
+        &mut self,
+        manifest_path: &Path,
+        canonical: &Path,
+        kind: ApiLayerKind,
+        enabled: bool,
+    ) {
+        if !self.known_manifests.insert(canonical.to_owned()) {
+            return;
+        }
+        match ApiLayer::new(manifest_path, canonical, kind, enabled) {
+            Ok(layer) => self.layers.push(layer),
+            Err(e) => self
+                .nonfatal_errors
+                .push(ManifestError(manifest_path.to_owned(), e)),
+        }
+    }
+
+    pub(crate) fn finish(self) -> (Vec<ApiLayer>, Vec<ManifestError>) {
+        (self.layers, self.nonfatal_errors)
+    }
+}
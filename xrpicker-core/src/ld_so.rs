@@ -0,0 +1,744 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Find ELF shared libraries the way the glibc dynamic linker (`ld.so`) does, so we can tell
+//! whether a runtime library (and everything it needs) can actually be loaded.
+//!
+//! This is a model of `ld.so`, not a perfect copy: it does not know about hardware capability
+//! subdirectories, `/etc/ld.so.preload`, or libraries the application has already loaded.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    env,
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use object::{
+    elf,
+    read::elf::{Dyn, FileHeader},
+    Endianness, FileKind,
+};
+
+use crate::{library::ResolvedDependency, sysroot::Sysroot};
+
+/// The cache of library locations written by `ldconfig`
+const LD_SO_CACHE: &str = "/etc/ld.so.cache";
+
+/// Environment variable with extra directories to search before the cache
+const LD_LIBRARY_PATH_ENV_VAR: &str = "LD_LIBRARY_PATH";
+
+/// Magic at the start of the old (libc5-era) cache format
+const CACHE_MAGIC_OLD: &[u8] = b"ld.so-1.7.0";
+
+/// Magic at the start of the current cache format, which may follow the old one
+const CACHE_MAGIC_NEW: &[u8] = b"glibc-ld.so.cache1.1";
+
+/// Size of the header of the old cache format: magic padded to 12 bytes, and the entry count
+const CACHE_OLD_HEADER_SIZE: usize = 16;
+
+/// Size of each entry in the old cache format: flags, key, and value
+const CACHE_OLD_ENTRY_SIZE: usize = 12;
+
+/// Size of the header of the new cache format
+const CACHE_NEW_HEADER_SIZE: usize = 48;
+
+/// Size of each entry in the new cache format: flags, key, value, OS version, and hwcap
+const CACHE_NEW_ENTRY_SIZE: usize = 24;
+
+/// Value of the flags byte in the new cache header for big-endian caches
+const CACHE_FLAGS_BIG_ENDIAN: u8 = 3;
+
+pub(crate) fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes: [u8; 4] = data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+/// Read a nul-terminated string
+fn read_c_str(data: &[u8], offset: usize) -> Option<&str> {
+    let rest = data.get(offset..)?;
+    let end = rest.iter().position(|&b| b == 0)?;
+    std::str::from_utf8(&rest[..end]).ok()
+}
+
+/// The contents of `/etc/ld.so.cache`: library names and the paths they were found at
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct LdSoCache {
+    /// Pairs of library name (usually the soname) and path, in cache order
+    entries: Vec<(String, PathBuf)>,
+}
+
+impl LdSoCache {
+    /// Parse a cache file in either the old or new format (or both, one after the other)
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        if data.starts_with(CACHE_MAGIC_NEW) {
+            return Self::parse_new(data, 0);
+        }
+        if !data.starts_with(CACHE_MAGIC_OLD) {
+            return None;
+        }
+        // Old format entries have no alignment requirements, and are native-endian:
+        // assume little-endian, which is what almost everything is.
+        let nlibs = read_u32(data, CACHE_OLD_HEADER_SIZE - 4, false)? as usize;
+        let strings_offset = nlibs
+            .checked_mul(CACHE_OLD_ENTRY_SIZE)?
+            .checked_add(CACHE_OLD_HEADER_SIZE)?;
+
+        // A new-format cache may follow, aligned to 8 bytes: prefer it if so
+        let new_offset = (strings_offset + 7) & !7;
+        if data
+            .get(new_offset..)
+            .is_some_and(|d| d.starts_with(CACHE_MAGIC_NEW))
+        {
+            return Self::parse_new(data, new_offset);
+        }
+
+        let entries = (0..nlibs)
+            .filter_map(|i| {
+                let entry = CACHE_OLD_HEADER_SIZE + i * CACHE_OLD_ENTRY_SIZE;
+                let key = read_u32(data, entry + 4, false)? as usize;
+                let value = read_u32(data, entry + 8, false)? as usize;
+                Some((
+                    read_c_str(data, strings_offset + key)?.to_owned(),
+                    PathBuf::from(read_c_str(data, strings_offset + value)?),
+                ))
+            })
+            .collect();
+        Some(Self { entries })
+    }
+
+    /// Parse the new cache format, which starts at `base` in `data`
+    fn parse_new(data: &[u8], base: usize) -> Option<Self> {
+        let flags = *data.get(base + 28)?;
+        let big_endian = flags == CACHE_FLAGS_BIG_ENDIAN;
+        let nlibs = read_u32(data, base + CACHE_MAGIC_NEW.len(), big_endian)? as usize;
+        let entries = (0..nlibs)
+            .filter_map(|i| {
+                let entry = base + CACHE_NEW_HEADER_SIZE + i * CACHE_NEW_ENTRY_SIZE;
+                // String offsets are relative to the start of the new-format cache
+                let key = read_u32(data, entry + 4, big_endian)? as usize;
+                let value = read_u32(data, entry + 8, big_endian)? as usize;
+                Some((
+                    read_c_str(data, base + key)?.to_owned(),
+                    PathBuf::from(read_c_str(data, base + value)?),
+                ))
+            })
+            .collect();
+        Some(Self { entries })
+    }
+
+    /// All the paths listed for a library name, in cache order
+    fn lookup<'a>(&'a self, name: &'a str) -> impl 'a + Iterator<Item = &'a Path> {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, path)| path.as_path())
+    }
+}
+
+/// The ELF class and machine of a binary: a library can only load others with the same ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ElfTarget {
+    is_64: bool,
+    machine: u16,
+}
+
+impl ElfTarget {
+    /// Read just the ELF header of a file
+    fn of_file(path: &Path) -> Option<Self> {
+        let mut header = [0u8; 20];
+        File::open(path).ok()?.read_exact(&mut header).ok()?;
+        if header[..4] != elf::ELFMAG {
+            return None;
+        }
+        // e_ident[EI_CLASS] and e_ident[EI_DATA], then e_type, then e_machine
+        let is_64 = header[4] == elf::ELFCLASS64;
+        let machine_bytes = [header[18], header[19]];
+        let machine = if header[5] == elf::ELFDATA2MSB {
+            u16::from_be_bytes(machine_bytes)
+        } else {
+            u16::from_le_bytes(machine_bytes)
+        };
+        Some(Self { is_64, machine })
+    }
+
+    /// The Debian-style multiarch tuple for this target, if we know it
+    fn multiarch_tuple(&self) -> Option<&'static str> {
+        match (self.machine, self.is_64) {
+            (elf::EM_X86_64, true) => Some("x86_64-linux-gnu"),
+            (elf::EM_386, false) => Some("i386-linux-gnu"),
+            (elf::EM_AARCH64, true) => Some("aarch64-linux-gnu"),
+            (elf::EM_ARM, false) => Some("arm-linux-gnueabihf"),
+            (elf::EM_RISCV, true) => Some("riscv64-linux-gnu"),
+            (elf::EM_PPC64, true) => Some("powerpc64le-linux-gnu"),
+            (elf::EM_S390, true) => Some("s390x-linux-gnu"),
+            (elf::EM_LOONGARCH, true) => Some("loongarch64-linux-gnu"),
+            _ => None,
+        }
+    }
+
+    /// The value of `$LIB` in search paths
+    fn lib_dir_name(&self) -> &'static str {
+        if self.is_64 && self.machine != elf::EM_AARCH64 {
+            "lib64"
+        } else {
+            "lib"
+        }
+    }
+}
+
+/// The parts of an ELF file's dynamic section that matter for finding its dependencies
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ElfDynamicInfo {
+    pub(crate) needed: Vec<String>,
+    /// The name the library was linked as, like `libfoo.so.1`
+    pub(crate) soname: Option<String>,
+    rpath: Vec<String>,
+    runpath: Vec<String>,
+}
+
+impl ElfDynamicInfo {
+    /// Parse the dynamic section, if this is an ELF file. A file without one has no dependencies.
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        match FileKind::parse(data).ok()? {
+            FileKind::Elf32 => {
+                Self::parse_header(elf::FileHeader32::<Endianness>::parse(data).ok()?, data)
+            }
+            FileKind::Elf64 => {
+                Self::parse_header(elf::FileHeader64::<Endianness>::parse(data).ok()?, data)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_header<Elf: FileHeader<Endian = Endianness>>(
+        header: &Elf,
+        data: &[u8],
+    ) -> Option<Self> {
+        let endian = header.endian().ok()?;
+        let sections = header.sections(endian, data).ok()?;
+        let (dynamic, link) = match sections.dynamic(endian, data).ok()? {
+            Some(dynamic) => dynamic,
+            None => return Some(Self::default()),
+        };
+        let strings = sections.strings(endian, data, link).ok()?;
+        let mut info = Self::default();
+        for entry in dynamic {
+            let tag = match entry.tag32(endian) {
+                Some(elf::DT_NULL) => break,
+                Some(tag) => tag,
+                None => continue,
+            };
+            if tag == elf::DT_SONAME {
+                info.soname = entry
+                    .string(endian, strings)
+                    .ok()
+                    .and_then(|s| std::str::from_utf8(s).ok())
+                    .map(str::to_owned);
+                continue;
+            }
+            let list = match tag {
+                elf::DT_NEEDED => &mut info.needed,
+                elf::DT_RPATH => &mut info.rpath,
+                elf::DT_RUNPATH => &mut info.runpath,
+                _ => continue,
+            };
+            if let Some(value) = entry
+                .string(endian, strings)
+                .ok()
+                .and_then(|s| std::str::from_utf8(s).ok())
+            {
+                if tag == elf::DT_NEEDED {
+                    list.push(value.to_owned());
+                } else {
+                    list.extend(
+                        value
+                            .split(':')
+                            .filter(|s| !s.is_empty())
+                            .map(str::to_owned),
+                    );
+                }
+            }
+        }
+        Some(info)
+    }
+}
+
+/// Searches for shared libraries in a system, like `ld.so` would.
+#[derive(Debug, Clone)]
+pub(crate) struct LibrarySearch {
+    sysroot: Sysroot,
+    /// From `LD_LIBRARY_PATH`, as host paths: only used for the live system
+    ld_library_path: Vec<PathBuf>,
+    cache: LdSoCache,
+}
+
+impl LibrarySearch {
+    /// Set up to search the system in `sysroot`.
+    ///
+    /// `LD_LIBRARY_PATH` is only used when inspecting the live system.
+    pub(crate) fn new(sysroot: &Sysroot) -> Self {
+        let ld_library_path = if sysroot.is_host() {
+            env::var(LD_LIBRARY_PATH_ENV_VAR)
+                .map(|v| {
+                    v.split([':', ';'])
+                        .filter(|s| !s.is_empty())
+                        .map(PathBuf::from)
+                        .collect()
+                })
+                .unwrap_or_default()
+        } else {
+            vec![]
+        };
+        let cache = fs::read(sysroot.map(Path::new(LD_SO_CACHE)))
+            .ok()
+            .and_then(|data| LdSoCache::parse(&data))
+            .unwrap_or_default();
+        Self {
+            sysroot: sysroot.clone(),
+            ld_library_path,
+            cache,
+        }
+    }
+
+    /// Expand `$ORIGIN`, `$LIB`, and `$PLATFORM` in a search path entry, and map it to the host.
+    ///
+    /// `origin` is the host path of the directory containing the object with this search path.
+    fn expand_search_dir(&self, dir: &str, origin: &Path, target: ElfTarget) -> Option<PathBuf> {
+        let platform = target.multiarch_tuple()?.split('-').next()?;
+        let expanded = dir
+            .replace("${ORIGIN}", "$ORIGIN")
+            .replace("${LIB}", "$LIB")
+            .replace("${PLATFORM}", "$PLATFORM")
+            .replace("$LIB", target.lib_dir_name())
+            .replace("$PLATFORM", platform);
+        if let Some(rest) = expanded.strip_prefix("$ORIGIN") {
+            let rest = rest.trim_start_matches('/');
+            return Some(if rest.is_empty() {
+                origin.to_owned()
+            } else {
+                origin.join(rest)
+            });
+        }
+        if expanded.contains("$ORIGIN") {
+            // Only supported at the start, which is what everyone uses
+            return None;
+        }
+        Some(self.sysroot.map(Path::new(&expanded)))
+    }
+
+    /// The directories `ld.so` searches last, as paths in the target
+    fn default_dirs(target: ElfTarget) -> Vec<PathBuf> {
+        let mut dirs = vec![];
+        if let Some(tuple) = target.multiarch_tuple() {
+            dirs.push(Path::new("/lib").join(tuple));
+            dirs.push(Path::new("/usr/lib").join(tuple));
+        }
+        if target.is_64 {
+            dirs.push(PathBuf::from("/lib64"));
+            dirs.push(PathBuf::from("/usr/lib64"));
+        } else {
+            dirs.push(PathBuf::from("/lib32"));
+            dirs.push(PathBuf::from("/usr/lib32"));
+        }
+        dirs.push(PathBuf::from("/lib"));
+        dirs.push(PathBuf::from("/usr/lib"));
+        dirs
+    }
+
+    /// Return the (canonical, host) path of `candidate` if it exists and suits `target`
+    fn try_candidate(&self, candidate: &Path, target: Option<ElfTarget>) -> Option<PathBuf> {
+        let canonical = self.sysroot.canonicalize(candidate).ok()?;
+        if !canonical.is_file() {
+            return None;
+        }
+        match target {
+            Some(target) if ElfTarget::of_file(&canonical) != Some(target) => None,
+            _ => Some(canonical),
+        }
+    }
+
+    /// Find a library by name, for an object of the given target, with the given search paths.
+    ///
+    /// If `target` is `None` (when finding a library named in a manifest, rather than a
+    /// dependency), the first library found with that name is used.
+    /// Returns the canonical path on the host, or `None` if it was not found.
+    pub(crate) fn find(
+        &self,
+        name: &str,
+        target: Option<ElfTarget>,
+        origin: &Path,
+        info: &ElfDynamicInfo,
+    ) -> Option<PathBuf> {
+        if name.contains('/') {
+            return self.try_candidate(&self.sysroot.map(Path::new(name)), target);
+        }
+        let expand = |dirs: &[String]| -> Vec<PathBuf> {
+            target
+                .map(|target| {
+                    dirs.iter()
+                        .filter_map(|dir| self.expand_search_dir(dir, origin, target))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        // DT_RPATH is ignored if DT_RUNPATH is present
+        let rpath = if info.runpath.is_empty() {
+            expand(&info.rpath)
+        } else {
+            vec![]
+        };
+        let runpath = expand(&info.runpath);
+        let cache = self
+            .cache
+            .lookup(name)
+            .map(|path| self.sysroot.map(path))
+            .collect::<Vec<_>>();
+        let defaults = match target {
+            Some(target) => Self::default_dirs(target),
+            None => {
+                // Check the possibilities for our own architecture first
+                let host = Self::host_target();
+                let mut dirs = host.map(Self::default_dirs).unwrap_or_default();
+                dirs.extend(
+                    ["/lib64", "/usr/lib64", "/lib", "/usr/lib"]
+                        .into_iter()
+                        .map(PathBuf::from),
+                );
+                dirs
+            }
+        }
+        .into_iter()
+        .map(|dir| self.sysroot.map(&dir));
+
+        rpath
+            .iter()
+            .chain(self.ld_library_path.iter())
+            .chain(runpath.iter())
+            .map(|dir| dir.join(name))
+            .chain(cache)
+            .chain(defaults.map(|dir| dir.join(name)))
+            .find_map(|candidate| self.try_candidate(&candidate, target))
+    }
+
+    /// The target of this program, if we are an ELF program ourselves
+    fn host_target() -> Option<ElfTarget> {
+        env::current_exe()
+            .ok()
+            .and_then(|exe| ElfTarget::of_file(&exe))
+    }
+}
+
+/// Find a library named without a path, like `libopenxr_monado.so.1`, the way `dlopen` would,
+/// in the system in `sysroot`.
+///
+/// On the live system, only libraries this program could load are considered. Returns the
+/// canonical path on the host, or `None` if it was not found.
+pub(crate) fn find_library_by_name(name: &str, sysroot: &Sysroot) -> Option<PathBuf> {
+    let target = LibrarySearch::host_target().filter(|_| sysroot.is_host());
+    LibrarySearch::new(sysroot).find(name, target, Path::new("/"), &ElfDynamicInfo::default())
+}
+
+/// Find all the dependencies of an ELF library, recursively, the way `ld.so` would.
+///
+/// Returns `None` if the library is not an ELF file we can read.
+pub(crate) fn resolve_dependencies(
+    library_path: &Path,
+    search: &LibrarySearch,
+) -> Option<Vec<ResolvedDependency>> {
+    let target = ElfTarget::of_file(library_path)?;
+    let mut resolved = vec![];
+    // The loader only loads a given name once, wherever it is needed
+    let mut seen_names: HashSet<String> = HashSet::new();
+    let mut seen_paths: HashSet<PathBuf> = HashSet::new();
+    let mut queue = VecDeque::from([library_path.to_owned()]);
+    seen_paths.insert(library_path.to_owned());
+
+    while let Some(object_path) = queue.pop_front() {
+        let data = match fs::read(&object_path) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+        let info = match ElfDynamicInfo::parse(&data) {
+            Some(info) => info,
+            None if object_path == library_path => return None,
+            None => continue,
+        };
+        let origin = object_path
+            .parent()
+            .expect("files always have parents")
+            .to_owned();
+        for name in &info.needed {
+            if !seen_names.insert(name.clone()) {
+                continue;
+            }
+            let path = search.find(name, Some(target), &origin, &info);
+            if let Some(path) = &path {
+                if seen_paths.insert(path.clone()) {
+                    queue.push_back(path.clone());
+                }
+            }
+            resolved.push(ResolvedDependency {
+                name: name.clone(),
+                needed_by: object_path.clone(),
+                path,
+                provided_by_system: false,
+            });
+        }
+    }
+    Some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strings for a cache, each nul-terminated, with their offsets from the start of the strings
+    fn string_table(strings: &[&str]) -> (Vec<u8>, Vec<u32>) {
+        let mut table = vec![];
+        let mut offsets = vec![];
+        for s in strings {
+            offsets.push(table.len() as u32);
+            table.extend_from_slice(s.as_bytes());
+            table.push(0);
+        }
+        (table, offsets)
+    }
+
+    /// An old-format cache of (name, path) pairs
+    fn old_cache(entries: &[(&str, &str)]) -> Vec<u8> {
+        let strings: Vec<&str> = entries.iter().flat_map(|(k, v)| [*k, *v]).collect();
+        let (table, offsets) = string_table(&strings);
+        let mut data = CACHE_MAGIC_OLD.to_vec();
+        data.resize(CACHE_OLD_HEADER_SIZE - 4, 0);
+        data.extend((entries.len() as u32).to_le_bytes());
+        for pair in offsets.chunks(2) {
+            data.extend(1i32.to_le_bytes());
+            data.extend(pair[0].to_le_bytes());
+            data.extend(pair[1].to_le_bytes());
+        }
+        data.extend(table);
+        data
+    }
+
+    /// A new-format cache of (name, path) pairs
+    fn new_cache(entries: &[(&str, &str)], big_endian: bool) -> Vec<u8> {
+        let u32_bytes = |v: u32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let strings: Vec<&str> = entries.iter().flat_map(|(k, v)| [*k, *v]).collect();
+        let (table, offsets) = string_table(&strings);
+        // String offsets are from the start of the new-format cache
+        let strings_start = (CACHE_NEW_HEADER_SIZE + entries.len() * CACHE_NEW_ENTRY_SIZE) as u32;
+        let mut data = CACHE_MAGIC_NEW.to_vec();
+        data.extend(u32_bytes(entries.len() as u32));
+        data.extend(u32_bytes(table.len() as u32));
+        data.push(if big_endian {
+            CACHE_FLAGS_BIG_ENDIAN
+        } else {
+            2
+        });
+        data.resize(CACHE_NEW_HEADER_SIZE, 0);
+        for pair in offsets.chunks(2) {
+            data.extend(u32_bytes(1));
+            data.extend(u32_bytes(strings_start + pair[0]));
+            data.extend(u32_bytes(strings_start + pair[1]));
+            data.extend([0u8; 12]);
+        }
+        data.extend(table);
+        data
+    }
+
+    fn lookup_all(cache: &LdSoCache, name: &str) -> Vec<PathBuf> {
+        cache.lookup(name).map(Path::to_owned).collect()
+    }
+
+    #[test]
+    fn old_format_cache() {
+        let data = old_cache(&[
+            ("libfoo.so.1", "/usr/lib/libfoo.so.1"),
+            ("libbar.so.2", "/usr/lib/libbar.so.2"),
+        ]);
+        let cache = LdSoCache::parse(&data).unwrap();
+        assert_eq!(
+            lookup_all(&cache, "libbar.so.2"),
+            vec![PathBuf::from("/usr/lib/libbar.so.2")]
+        );
+        assert!(lookup_all(&cache, "libbaz.so").is_empty());
+    }
+
+    #[test]
+    fn new_format_cache() {
+        for big_endian in [false, true] {
+            let data = new_cache(
+                &[
+                    ("libfoo.so.1", "/usr/lib64/libfoo.so.1"),
+                    ("libfoo.so.1", "/usr/lib/libfoo.so.1"),
+                ],
+                big_endian,
+            );
+            let cache = LdSoCache::parse(&data).unwrap();
+            assert_eq!(
+                lookup_all(&cache, "libfoo.so.1"),
+                vec![
+                    PathBuf::from("/usr/lib64/libfoo.so.1"),
+                    PathBuf::from("/usr/lib/libfoo.so.1")
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn new_format_after_old_is_preferred() {
+        // The new format goes right after the old entries, and has the strings for both
+        let mut data = old_cache(&[("libold.so", "/old/libold.so")]);
+        data.truncate(CACHE_OLD_HEADER_SIZE + CACHE_OLD_ENTRY_SIZE);
+        data.resize((data.len() + 7) & !7, 0);
+        data.extend(new_cache(&[("libnew.so", "/new/libnew.so")], false));
+        let cache = LdSoCache::parse(&data).unwrap();
+        assert!(lookup_all(&cache, "libold.so").is_empty());
+        assert_eq!(
+            lookup_all(&cache, "libnew.so"),
+            vec![PathBuf::from("/new/libnew.so")]
+        );
+    }
+
+    #[test]
+    fn broken_caches() {
+        assert!(LdSoCache::parse(b"not a cache").is_none());
+        let data = new_cache(&[("libfoo.so.1", "/usr/lib/libfoo.so.1")], false);
+        // Truncated anywhere, nothing panics, and no made-up entries appear
+        for len in 0..data.len() {
+            if let Some(cache) = LdSoCache::parse(&data[..len]) {
+                assert!(cache.entries.is_empty());
+            }
+        }
+        let data = old_cache(&[("libfoo.so.1", "/usr/lib/libfoo.so.1")]);
+        for len in 0..data.len() {
+            if let Some(cache) = LdSoCache::parse(&data[..len]) {
+                assert!(cache.entries.is_empty());
+            }
+        }
+    }
+
+    /// Just enough of an ELF header for `ElfTarget::of_file`
+    fn write_fake_elf(path: &Path, is_64: bool, machine: u16) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut header = elf::ELFMAG.to_vec();
+        header.push(if is_64 {
+            elf::ELFCLASS64
+        } else {
+            elf::ELFCLASS32
+        });
+        header.push(elf::ELFDATA2LSB);
+        header.resize(18, 0);
+        header.extend(machine.to_le_bytes());
+        fs::write(path, header).unwrap();
+    }
+
+    const X86_64: ElfTarget = ElfTarget {
+        is_64: true,
+        machine: elf::EM_X86_64,
+    };
+
+    fn search_paths(rpath: &[&str], runpath: &[&str]) -> ElfDynamicInfo {
+        ElfDynamicInfo {
+            rpath: rpath.iter().map(|s| s.to_string()).collect(),
+            runpath: runpath.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn search_path_expansion() {
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+        let lib_dir = root_path.join("opt/rt/lib");
+        let lib64_dir = root_path.join("opt/rt/lib64");
+        write_fake_elf(&lib_dir.join("libdep.so"), true, elf::EM_X86_64);
+        write_fake_elf(&lib64_dir.join("libdep64.so"), true, elf::EM_X86_64);
+        write_fake_elf(&lib_dir.join("lib32only.so"), false, elf::EM_386);
+        // The directory of the library with these search paths
+        let origin = root_path.join("opt/rt/bin");
+        fs::create_dir_all(&origin).unwrap();
+        let search = LibrarySearch::new(&Sysroot::new(&root_path));
+        let find =
+            |name: &str, info: &ElfDynamicInfo| search.find(name, Some(X86_64), &origin, info);
+
+        let found = Some(lib_dir.join("libdep.so"));
+        assert_eq!(
+            find("libdep.so", &search_paths(&["$ORIGIN/../lib"], &[])),
+            found
+        );
+        assert_eq!(
+            find("libdep.so", &search_paths(&[], &["${ORIGIN}/../lib"])),
+            found
+        );
+        // Absolute entries are inside the root
+        assert_eq!(
+            find("libdep.so", &search_paths(&["/opt/rt/lib"], &[])),
+            found
+        );
+        // DT_RPATH is ignored when there is a DT_RUNPATH
+        assert_eq!(
+            find(
+                "libdep.so",
+                &search_paths(&["$ORIGIN/../lib"], &["/nowhere"])
+            ),
+            None
+        );
+        assert_eq!(
+            find("libdep64.so", &search_paths(&["/opt/rt/$LIB"], &[])),
+            Some(lib64_dir.join("libdep64.so"))
+        );
+        // Only supported at the start
+        assert_eq!(
+            find("libdep.so", &search_paths(&["/x/$ORIGIN/../lib"], &[])),
+            None
+        );
+        // A library of the wrong class is skipped
+        assert_eq!(
+            find("lib32only.so", &search_paths(&["$ORIGIN/../lib"], &[])),
+            None
+        );
+    }
+
+    #[test]
+    fn cache_in_root() {
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+        write_fake_elf(
+            &root_path.join("usr/lib/custom/libcached.so.1"),
+            true,
+            elf::EM_X86_64,
+        );
+        fs::create_dir_all(root_path.join("etc")).unwrap();
+        fs::write(
+            root_path.join("etc/ld.so.cache"),
+            new_cache(
+                &[("libcached.so.1", "/usr/lib/custom/libcached.so.1")],
+                false,
+            ),
+        )
+        .unwrap();
+        let search = LibrarySearch::new(&Sysroot::new(&root_path));
+        assert_eq!(
+            search.find(
+                "libcached.so.1",
+                Some(X86_64),
+                &root_path,
+                &ElfDynamicInfo::default()
+            ),
+            Some(root_path.join("usr/lib/custom/libcached.so.1"))
+        );
+    }
+}
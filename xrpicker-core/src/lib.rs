@@ -6,20 +6,36 @@
 pub const OPENXR_MAJOR_VERSION: i32 = 1;
 
 pub const ACTIVE_RUNTIME_FILENAME: &str = "active_runtime.json";
+/// Environment variable that, when set, makes the loader use that runtime manifest instead of the active runtime
+pub const RUNTIME_JSON_ENV_VAR: &str = "XR_RUNTIME_JSON";
 /// Directory used in constructing paths
 pub const OPENXR: &str = "openxr";
 
 mod app_state;
-#[cfg(windows)]
-pub(crate) mod arch_detect;
+pub mod arch_detect;
+pub mod diagnostics;
+pub mod history;
+pub mod known_runtimes;
+pub mod layer;
+pub(crate) mod ld_so;
+pub mod library;
 pub(crate) mod manifest;
+pub mod pairing;
 pub(crate) mod path_simplifier;
+pub(crate) mod pe_deps;
 pub mod platform;
+pub mod registry;
 pub(crate) mod runtime;
+pub(crate) mod sysroot;
+#[cfg(test)]
+mod test_binaries;
+pub mod validate;
 
 pub use app_state::{AppState, PersistentAppState};
 
-use std::{fmt::Display, io, path::PathBuf};
+use std::{fmt::Display, io, path::PathBuf, str::FromStr};
+
+use serde::{Deserialize, Serialize};
 
 pub(crate) use manifest::RuntimeManifest;
 
@@ -40,10 +56,124 @@ pub enum Error {
     #[error("Error when trying to set active runtime: {0}")]
     SetActiveError(String),
 
+    #[error("Error when trying to enable or disable an API layer: {0}")]
+    ApiLayerError(String),
+
+    #[error("Error when trying to change the list of available runtimes: {0}")]
+    AvailableRuntimesError(String),
+
+    #[error("Error with the activation history: {0}")]
+    HistoryError(String),
+
+    #[error("Could not {step} {}: {source}", .path.display())]
+    ActivationFailed {
+        step: ActivationStep,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
     #[error("Error when trying to load the runtime binary {0} to guess its architecture")]
     RuntimeBinaryLoadError(String),
 }
 
+/// A step in changing an active runtime file, for reporting errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationStep {
+    /// Finding out what the file is now
+    Inspect,
+    /// Keeping a copy of the old file
+    BackUp,
+    /// Creating the new file under a temporary name
+    CreateTemporary,
+    /// Renaming the new file over the old one
+    Replace,
+    /// Removing the file, when there should be none
+    Remove,
+}
+
+impl Display for ActivationStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActivationStep::Inspect => write!(f, "inspect"),
+            ActivationStep::BackUp => write!(f, "back up"),
+            ActivationStep::CreateTemporary => write!(f, "create the temporary file"),
+            ActivationStep::Replace => write!(f, "replace"),
+            ActivationStep::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+/// How to make a runtime active. Platforms without a choice only support the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActivationStrategy {
+    /// Point the active runtime file at the manifest with an absolute symlink
+    #[default]
+    Symlink,
+    /// Point the active runtime file at the manifest with a relative symlink, which keeps
+    /// working if the home directory is synced or managed as dotfiles
+    RelativeSymlink,
+    /// Copy the manifest to the active runtime file, with its library path made absolute,
+    /// for filesystems or containers where the original manifest is not visible
+    Copy,
+    /// Set the `XR_RUNTIME_JSON` environment variable for the systemd user session,
+    /// with an `environment.d` snippet. Takes effect at the next login.
+    Environment,
+}
+
+/// All the activation strategies, in the order to offer them
+pub const ACTIVATION_STRATEGIES: [ActivationStrategy; 4] = [
+    ActivationStrategy::Symlink,
+    ActivationStrategy::RelativeSymlink,
+    ActivationStrategy::Copy,
+    ActivationStrategy::Environment,
+];
+
+impl ActivationStrategy {
+    /// The name used on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActivationStrategy::Symlink => "symlink",
+            ActivationStrategy::RelativeSymlink => "relative-symlink",
+            ActivationStrategy::Copy => "copy",
+            ActivationStrategy::Environment => "environment",
+        }
+    }
+}
+
+impl Display for ActivationStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActivationStrategy::Symlink => write!(f, "Symlink"),
+            ActivationStrategy::RelativeSymlink => write!(f, "Relative symlink"),
+            ActivationStrategy::Copy => write!(f, "Copy of the manifest"),
+            ActivationStrategy::Environment => {
+                write!(f, "{} in the session environment", RUNTIME_JSON_ENV_VAR)
+            }
+        }
+    }
+}
+
+impl FromStr for ActivationStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ACTIVATION_STRATEGIES
+            .into_iter()
+            .find(|strategy| strategy.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown activation strategy '{}', expected one of: {}",
+                    s,
+                    ACTIVATION_STRATEGIES
+                        .map(|strategy| strategy.name())
+                        .join(", ")
+                )
+            })
+    }
+}
+
 #[derive(Debug)]
 pub struct ManifestError(pub PathBuf, pub Error);
 
@@ -54,6 +184,13 @@ pub enum ActiveState {
     Active64,
     Active32,
     Active64and32,
+    /// Not necessarily the active runtime, but selected by the `XR_RUNTIME_JSON` environment variable,
+    /// so the loader will use it
+    ActiveByEnvironment,
+    /// Marked as active, but the `XR_RUNTIME_JSON` environment variable selects a different runtime
+    OverriddenByEnvironment,
+    /// Not active, and marked as disabled in the list of available runtimes, usually by its installer
+    Disabled,
 }
 
 impl Display for ActiveState {
@@ -64,13 +201,23 @@ impl Display for ActiveState {
             ActiveState::Active64 => write!(f, "Active - 64-bit only"),
             ActiveState::Active32 => write!(f, "Active - 32-bit only"),
             ActiveState::Active64and32 => write!(f, "Active"),
+            ActiveState::ActiveByEnvironment => {
+                write!(
+                    f,
+                    "Active - from {} environment variable",
+                    RUNTIME_JSON_ENV_VAR
+                )
+            }
+            ActiveState::OverriddenByEnvironment => {
+                write!(f, "Active, but overridden by environment")
+            }
+            ActiveState::Disabled => write!(f, "Disabled"),
         }
     }
 }
 
 impl ActiveState {
     /// Turn a pair of booleans (one for 64 bit, one for 32) into an active state enum.
-    #[cfg(windows)]
     pub(crate) fn from_active_64_and_32(active_64: bool, active_32: bool) -> Self {
         match (active_64, active_32) {
             (true, true) => Self::Active64and32,
@@ -88,6 +235,36 @@ impl ActiveState {
             ActiveState::Active64 => true,
             ActiveState::Active32 => true,
             ActiveState::Active64and32 => false,
+            ActiveState::ActiveByEnvironment => true,
+            ActiveState::OverriddenByEnvironment => false,
+            ActiveState::Disabled => true,
+        }
+    }
+
+    /// Will the loader (at least sometimes) use this runtime, given the current environment?
+    pub fn is_effectively_active(&self) -> bool {
+        match self {
+            ActiveState::NotActive => false,
+            ActiveState::ActiveIndependentRuntime => true,
+            ActiveState::Active64 => true,
+            ActiveState::Active32 => true,
+            ActiveState::Active64and32 => true,
+            ActiveState::ActiveByEnvironment => true,
+            ActiveState::OverriddenByEnvironment => false,
+            ActiveState::Disabled => false,
+        }
+    }
+
+    /// Adjust an active state computed from the platform's settings, to account for an
+    /// environment variable override (if any).
+    pub(crate) fn with_environment_override(self, overridden: Option<bool>) -> Self {
+        match overridden {
+            None => self,
+            Some(true) => ActiveState::ActiveByEnvironment,
+            Some(false) => match self {
+                ActiveState::NotActive => ActiveState::NotActive,
+                _ => ActiveState::OverriddenByEnvironment,
+            },
         }
     }
 }
@@ -95,7 +272,7 @@ impl ActiveState {
 #[cfg(unix)]
 mod linux;
 #[cfg(unix)]
-pub use linux::make_platform;
+pub use linux::{make_platform, make_platform_with_root, LinuxPlatform, XdgConfigVars};
 
 #[cfg(windows)]
 mod windows;
@@ -0,0 +1,394 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Inspection of the shared library a runtime manifest points to, to find problems before
+//! an application does.
+//!
+//! This reads ELF, PE, and Mach-O files alike, so (for instance) a Windows runtime can be
+//! checked from Linux.
+
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use object::{read::Object, BinaryFormat, File};
+
+use crate::{
+    arch_detect::{binary_architecture, binary_version, Architecture, LibraryVersion},
+    ld_so::{resolve_dependencies, LibrarySearch},
+    pe_deps::{is_vc_runtime, resolve_dll_dependencies, DllSearch},
+    sysroot::Sysroot,
+};
+
+/// The function the loader calls first in a runtime, unless the manifest renames it
+pub(crate) const NEGOTIATE_FUNCTION: &str = "xrNegotiateLoaderRuntimeInterface";
+
+/// A problem with a runtime that will keep applications from using it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeProblem {
+    /// The runtime library does not exist
+    LibraryNotFound(PathBuf),
+
+    /// The runtime library is named without a path, and is not in the dynamic library search path
+    LibraryNotInSearchPath(String),
+
+    /// The runtime library exists but could not be read or parsed as a shared library
+    LibraryUnreadable { library: PathBuf, reason: String },
+
+    /// The runtime library does not export the function the loader calls first
+    MissingNegotiateExport { library: PathBuf, symbol: String },
+
+    /// The runtime library is built for an architecture that cannot run here
+    UnsupportedArchitecture {
+        library: PathBuf,
+        architecture: Architecture,
+        host: Architecture,
+    },
+
+    /// A shared library the runtime library needs, directly or indirectly, could not be found
+    MissingDependency {
+        library: PathBuf,
+        dependency: String,
+        needed_by: PathBuf,
+    },
+}
+
+impl Display for RuntimeProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeProblem::LibraryNotFound(library) => {
+                write!(
+                    f,
+                    "The runtime library {} does not exist",
+                    library.display()
+                )
+            }
+            RuntimeProblem::LibraryNotInSearchPath(name) => write!(
+                f,
+                "The runtime library {} was not found in the dynamic library search path",
+                name
+            ),
+            RuntimeProblem::LibraryUnreadable { library, reason } => write!(
+                f,
+                "The runtime library {} could not be read: {}",
+                library.display(),
+                reason
+            ),
+            RuntimeProblem::MissingNegotiateExport { library, symbol } => write!(
+                f,
+                "The runtime library {} does not export {}, so the loader cannot use it",
+                library.display(),
+                symbol
+            ),
+            RuntimeProblem::UnsupportedArchitecture {
+                library,
+                architecture,
+                host,
+            } => write!(
+                f,
+                "The runtime library {} is built for {}, which cannot run on this {} system",
+                library.display(),
+                architecture,
+                host
+            ),
+            RuntimeProblem::MissingDependency {
+                library,
+                dependency,
+                needed_by,
+            } => {
+                if library == needed_by {
+                    write!(
+                        f,
+                        "The runtime library {} needs {}, which could not be found",
+                        library.display(),
+                        dependency
+                    )?;
+                } else {
+                    write!(
+                        f,
+                        "The runtime library {} indirectly needs {} (for {}), which could not be found",
+                        library.display(),
+                        dependency,
+                        needed_by.display()
+                    )?;
+                }
+                if is_vc_runtime(dependency) {
+                    write!(f, ": it requires the Visual C++ Redistributable")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// One shared library a runtime library needs, directly or indirectly, and where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedDependency {
+    /// The name as listed in `DT_NEEDED`, usually a soname like `libvulkan.so.1`, or in the
+    /// imports of a DLL
+    pub name: String,
+    /// The library that needs it
+    pub needed_by: PathBuf,
+    /// Where it was found, or `None` if it was not found (or, for API sets, not looked for)
+    pub path: Option<PathBuf>,
+    /// Part of Windows itself, like `kernel32.dll` or an API set, so present wherever the
+    /// runtime is used, even if not found here
+    pub provided_by_system: bool,
+}
+
+impl ResolvedDependency {
+    /// Will loading the runtime fail for lack of this library?
+    pub fn is_missing(&self) -> bool {
+        self.path.is_none() && !self.provided_by_system
+    }
+
+    /// Is this part of the Visual C++ runtime, which comes from the Visual C++ Redistributable
+    /// rather than with Windows?
+    pub fn is_vc_runtime(&self) -> bool {
+        is_vc_runtime(&self.name)
+    }
+}
+
+/// Does this binary export a function with the given name?
+///
+/// Also accepts the decorated form of a 32-bit Windows `__stdcall` function, like `_name@8`.
+fn exports_symbol(file: &File, symbol: &str) -> bool {
+    let decorated_prefix = format!("_{}@", symbol);
+    file.exports()
+        .map(|exports| {
+            exports.iter().any(|export| {
+                let name = export.name();
+                name == symbol.as_bytes() || name.starts_with(decorated_prefix.as_bytes())
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// What we learned by inspecting a runtime library
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct LibraryInspection {
+    /// The architecture of the library, if we could read it
+    pub(crate) architecture: Option<Architecture>,
+    /// The version information in the library, if any
+    pub(crate) version: Option<LibraryVersion>,
+    /// Problems with the library itself: missing dependencies are looked for separately, with
+    /// `resolve_library_dependencies()`
+    pub(crate) problems: Vec<RuntimeProblem>,
+}
+
+impl LibraryInspection {
+    pub(crate) fn with_problem(problem: RuntimeProblem) -> Self {
+        Self {
+            architecture: None,
+            version: None,
+            problems: vec![problem],
+        }
+    }
+}
+
+/// Inspect a resolved runtime library: it must exist, be a shared library, and export
+/// `negotiate_symbol`.
+///
+/// If `host` is given, the library must also be loadable on a system with that architecture.
+/// Only the library itself is read: its dependencies are not looked for.
+pub(crate) fn inspect_runtime_library(
+    library_path: PathBuf,
+    negotiate_symbol: &str,
+    host: Option<Architecture>,
+) -> LibraryInspection {
+    if !library_path.exists() {
+        return LibraryInspection::with_problem(RuntimeProblem::LibraryNotFound(library_path));
+    }
+    let data = match fs::read(&library_path) {
+        Ok(data) => data,
+        Err(e) => {
+            return LibraryInspection::with_problem(RuntimeProblem::LibraryUnreadable {
+                library: library_path,
+                reason: e.to_string(),
+            })
+        }
+    };
+    let file = match File::parse(&*data) {
+        Ok(file) => file,
+        Err(e) => {
+            return LibraryInspection::with_problem(RuntimeProblem::LibraryUnreadable {
+                library: library_path,
+                reason: e.to_string(),
+            })
+        }
+    };
+    let architecture = binary_architecture(&data);
+    let version = binary_version(
+        &data,
+        library_path.file_name().and_then(|name| name.to_str()),
+    );
+    let mut problems = vec![];
+    if let (Some(host), Some(architecture)) = (host, architecture) {
+        if !host.can_load(architecture) {
+            problems.push(RuntimeProblem::UnsupportedArchitecture {
+                library: library_path.clone(),
+                architecture,
+                host,
+            });
+        }
+    }
+    if !exports_symbol(&file, negotiate_symbol) {
+        problems.push(RuntimeProblem::MissingNegotiateExport {
+            library: library_path.clone(),
+            symbol: negotiate_symbol.to_owned(),
+        });
+    }
+    LibraryInspection {
+        architecture,
+        version,
+        problems,
+    }
+}
+
+/// Find the shared libraries a runtime library needs, directly or indirectly, in the system in
+/// `sysroot`: only for ELF and PE libraries, and empty if the library cannot be read.
+///
+/// This reads every library in the tree, so runtimes only do it the first time it is needed.
+pub(crate) fn resolve_library_dependencies(
+    library_path: &Path,
+    sysroot: &Sysroot,
+) -> Vec<ResolvedDependency> {
+    let format = fs::read(library_path)
+        .ok()
+        .and_then(|data| File::parse(&*data).ok().map(|file| file.format()));
+    match format {
+        Some(BinaryFormat::Elf) => resolve_dependencies(library_path, &LibrarySearch::new(sysroot)),
+        Some(BinaryFormat::Pe) => resolve_dll_dependencies(library_path, &DllSearch::new(sysroot)),
+        _ => None,
+    }
+    .unwrap_or_default()
+}
+
+/// The problems a runtime library has for lack of some of its dependencies
+pub(crate) fn missing_dependency_problems(
+    library_path: &Path,
+    dependencies: &[ResolvedDependency],
+) -> Vec<RuntimeProblem> {
+    dependencies
+        .iter()
+        .filter(|dep| dep.is_missing())
+        .map(|dep| RuntimeProblem::MissingDependency {
+            library: library_path.to_owned(),
+            dependency: dep.name.clone(),
+            needed_by: dep.needed_by.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::BaseRuntime,
+        test_binaries::{elf_library, pe_dll},
+    };
+
+    fn exports(data: &[u8], symbol: &str) -> bool {
+        exports_symbol(&File::parse(data).unwrap(), symbol)
+    }
+
+    #[test]
+    fn exported_functions() {
+        let exporting = &["xrOther", NEGOTIATE_FUNCTION];
+        for data in [
+            elf_library(&[], exporting),
+            pe_dll(false, &[], exporting),
+            pe_dll(true, &[], exporting),
+        ] {
+            assert!(exports(&data, NEGOTIATE_FUNCTION));
+            assert!(exports(&data, "xrOther"));
+            assert!(!exports(&data, "xrNegotiateLoaderRuntime"));
+            assert!(!exports(&data, "xrMissing"));
+        }
+        for data in [
+            elf_library(&["libc.so.6"], &[]),
+            pe_dll(true, &["KERNEL32.dll"], &[]),
+        ] {
+            assert!(!exports(&data, NEGOTIATE_FUNCTION));
+        }
+    }
+
+    #[test]
+    fn decorated_stdcall_functions() {
+        let data = pe_dll(false, &[], &["_xrNegotiateLoaderRuntimeInterface@8"]);
+        assert!(exports(&data, NEGOTIATE_FUNCTION));
+        assert!(!exports(&data, "xrNegotiateLoaderRuntime"));
+        assert!(!exports(&data, "xrNegotiateLoaderRuntimeInterface@8"));
+    }
+
+    #[test]
+    fn inspection() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("runtime.dll");
+        fs::write(&library, pe_dll(true, &[], &[NEGOTIATE_FUNCTION])).unwrap();
+
+        let inspection = inspect_runtime_library(
+            library.clone(),
+            NEGOTIATE_FUNCTION,
+            Some(Architecture::X86_64),
+        );
+        assert_eq!(inspection.architecture, Some(Architecture::X86_64));
+        assert_eq!(inspection.problems, vec![]);
+
+        let inspection =
+            inspect_runtime_library(library.clone(), "xrRenamed", Some(Architecture::X86));
+        assert_eq!(
+            inspection.problems,
+            vec![
+                RuntimeProblem::UnsupportedArchitecture {
+                    library: library.clone(),
+                    architecture: Architecture::X86_64,
+                    host: Architecture::X86,
+                },
+                RuntimeProblem::MissingNegotiateExport {
+                    library: library.clone(),
+                    symbol: "xrRenamed".to_owned(),
+                },
+            ]
+        );
+
+        fs::write(&library, b"not a library").unwrap();
+        assert!(matches!(
+            inspect_runtime_library(library, NEGOTIATE_FUNCTION, None).problems[..],
+            [RuntimeProblem::LibraryUnreadable { .. }]
+        ));
+        let missing = dir.path().join("missing.dll");
+        assert_eq!(
+            inspect_runtime_library(missing.clone(), NEGOTIATE_FUNCTION, None).problems,
+            vec![RuntimeProblem::LibraryNotFound(missing)]
+        );
+    }
+
+    #[test]
+    fn renamed_negotiate_function() {
+        let root = tempfile::tempdir().unwrap();
+        let manifest = root.path().join("usr/share/openxr/1/openxr_test.json");
+        fs::create_dir_all(manifest.parent().unwrap()).unwrap();
+        fs::write(
+            &manifest,
+            r#"{"file_format_version": "1.0.0", "runtime": {"library_path": "/usr/lib/libtest.so", "functions": {"xrNegotiateLoaderRuntimeInterface": "xrRenamed"}}}"#,
+        )
+        .unwrap();
+        let library = root.path().join("usr/lib/libtest.so");
+        fs::create_dir_all(library.parent().unwrap()).unwrap();
+        let sysroot = Sysroot::new(root.path());
+        let runtime = BaseRuntime::new(&manifest).unwrap();
+        assert_eq!(runtime.negotiate_function_name(), "xrRenamed");
+
+        fs::write(&library, elf_library(&[], &["xrRenamed"])).unwrap();
+        assert_eq!(runtime.inspect_library_in(&sysroot).problems, vec![]);
+
+        fs::write(&library, elf_library(&[], &[NEGOTIATE_FUNCTION])).unwrap();
+        assert!(matches!(
+            &runtime.inspect_library_in(&sysroot).problems[..],
+            [RuntimeProblem::MissingNegotiateExport { symbol, .. }] if symbol == "xrRenamed"
+        ));
+    }
+}
@@ -1,92 +1,1023 @@
 // Copyright 2022-2023, Collabora, Ltd.
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use xdg::{BaseDirectories, BaseDirectoriesError};
+use itertools::Itertools;
+use xdg::BaseDirectories;
 
 use crate::{
+    arch_detect::{Architecture, LibraryVersion},
+    diagnostics::{
+        check_active_manifest, check_environment, check_override_manifest, Check, CheckStatus,
+        Diagnosis,
+    },
+    history::{
+        unix_time_now, ActivationHistory, ActivationRecord, ActiveRuntimeSetting,
+        DEFAULT_HISTORY_LENGTH, HISTORY_FILENAME,
+    },
+    known_runtimes::{expand_location, KnownRuntime, KnownRuntimes, KNOWN_RUNTIMES_FILENAME},
+    layer::{
+        environment_explicit_layer_dirs, layer_manifests_in_dir, ApiLayer, ApiLayerCollection,
+        ApiLayerKind, DISABLED_SUFFIX,
+    },
+    library::{missing_dependency_problems, LibraryInspection, ResolvedDependency, RuntimeProblem},
     manifest::{GenericManifest, FILE_INDIRECTION_ARROW},
     path_simplifier::PathSimplifier,
     platform::{Platform, PlatformRuntime},
-    runtime::BaseRuntime,
-    ActiveState, Error, ManifestError, ACTIVE_RUNTIME_FILENAME, OPENXR, OPENXR_MAJOR_VERSION,
+    runtime::{environment_override_manifest, BaseRuntime},
+    sysroot::Sysroot,
+    ActivationStep, ActivationStrategy, ActiveState, Error, ManifestError, ACTIVATION_STRATEGIES,
+    ACTIVE_RUNTIME_FILENAME, OPENXR, OPENXR_MAJOR_VERSION, RUNTIME_JSON_ENV_VAR,
 };
 use std::{
     collections::HashSet,
-    fs,
+    fmt::{Debug, Display},
+    fs, io,
     iter::once,
-    os::unix::{self, prelude::OsStrExt},
-    path::{Path, PathBuf},
-    time::{SystemTime, UNIX_EPOCH},
+    os::unix::{self, fs::PermissionsExt},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::{Arc, OnceLock},
 };
 
+/// The system config directory, in the target system
 const ETC: &str = "/etc";
 
+/// The default for `XDG_CONFIG_DIRS`
+const DEFAULT_XDG_CONFIG_DIRS: &str = "/etc/xdg";
+
+/// The defaults for `XDG_DATA_DIRS`
+const DEFAULT_XDG_DATA_DIRS: [&str; 2] = ["/usr/local/share", "/usr/share"];
+
+/// Directory in an OpenXR directory that contains API layer manifests
+const API_LAYERS: &str = "api_layers";
+
+/// Prefix of the backups we keep of active runtime files we replace
+const BACKUP_PREFIX: &str = "old_";
+
+/// Prefix of the copies of manifests we make active with `ActivationStrategy::Copy`
+const COPY_PREFIX: &str = "copied_";
+
+/// The `environment.d` snippet we write to set `XR_RUNTIME_JSON` for the user session,
+/// late in the order so it wins over most others
+const ENVIRONMENT_D_SNIPPET: &str = "60-xrpicker-openxr-runtime.conf";
+
+/// The `environment.d` snippet we write to disable system-wide implicit API layers for the user
+/// session, through their `disable_environment` variables
+const LAYERS_ENVIRONMENT_D_SNIPPET: &str = "60-xrpicker-openxr-layers.conf";
+
+/// The active runtime file the loader checks first for applications of the given architecture,
+/// before `active_runtime.json`
+fn active_runtime_filename(architecture: Architecture) -> Option<String> {
+    architecture
+        .openxr_abi_name()
+        .map(|abi| format!("active_runtime.{}.json", abi))
+}
+
 fn make_path_suffix() -> PathBuf {
     Path::new(OPENXR).join(OPENXR_MAJOR_VERSION.to_string())
 }
 
-fn make_sysconfdir(suffix: &Path) -> PathBuf {
-    Path::new(ETC).join(suffix)
+/// A new, unused name for a file saved next to an active runtime file, like
+/// `old_active_runtime1680000000.json`
+fn saved_file_path(path: &Path, prefix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "json".to_owned());
+    let timestamp = unix_time_now();
+    let mut saved = path.with_file_name(format!("{}{}{}.{}", prefix, stem, timestamp, extension));
+    let mut counter = 1;
+    while saved.symlink_metadata().is_ok() {
+        saved = path.with_file_name(format!(
+            "{}{}{}-{}.{}",
+            prefix, stem, timestamp, counter, extension
+        ));
+        counter += 1;
+    }
+    saved
+}
+
+/// Create a new file with `create` under a temporary name next to `path`, and rename it over
+/// `path`, so at every moment `path` is either the old file or the new one.
+fn replace_via_temporary(
+    path: &Path,
+    create: impl FnOnce(&Path) -> io::Result<()>,
+) -> Result<(), Error> {
+    let temp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        process::id()
+    ));
+    // Left over from a crash, perhaps
+    let _ = fs::remove_file(&temp);
+    create(&temp).map_err(|source| Error::ActivationFailed {
+        step: ActivationStep::CreateTemporary,
+        path: temp.clone(),
+        source,
+    })?;
+    fs::rename(&temp, path).map_err(|source| {
+        let _ = fs::remove_file(&temp);
+        Error::ActivationFailed {
+            step: ActivationStep::Replace,
+            path: path.to_owned(),
+            source,
+        }
+    })
+}
+
+/// The relative path from the directory `from` to `to`, both absolute and without symlinks
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    from[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .chain(to[common..].iter().copied())
+        .collect()
+}
+
+/// Quote a value for an `environment.d` file
+fn quote_environment_value(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// XDG base directory settings to use instead of the environment, for `make_platform_with_root()`.
+///
+/// All paths are as seen from inside the root directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XdgConfigVars {
+    /// Like `HOME`: only used to compute the defaults for `config_home` and `data_home`.
+    pub home: Option<PathBuf>,
+    /// Like `XDG_CONFIG_HOME`: the per-user config directory. Defaults to `$HOME/.config`,
+    /// and if neither is set, there is no per-user config directory.
+    pub config_home: Option<PathBuf>,
+    /// Like `XDG_CONFIG_DIRS`: the system-wide config directories, most important first.
+    /// Defaults to `/etc/xdg`.
+    pub config_dirs: Option<Vec<PathBuf>>,
+    /// Like `XDG_DATA_HOME`: the per-user data directory. Defaults to `$HOME/.local/share`,
+    /// and if neither is set, there is no per-user data directory.
+    pub data_home: Option<PathBuf>,
+    /// Like `XDG_DATA_DIRS`: the system-wide data directories, most important first.
+    /// Defaults to `/usr/local/share` and `/usr/share`.
+    pub data_dirs: Option<Vec<PathBuf>>,
+}
+
+fn default_data_dirs() -> Vec<PathBuf> {
+    DEFAULT_XDG_DATA_DIRS.iter().map(PathBuf::from).collect()
+}
+
+/// The directories we use, and the root directory they are in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LinuxPaths {
+    sysroot: Sysroot,
+    /// Per-user config directory, in the target
+    config_home: Option<PathBuf>,
+    /// System-wide XDG config directories, most important first, in the target
+    config_dirs: Vec<PathBuf>,
+    /// Per-user data directory, in the target
+    data_home: Option<PathBuf>,
+    /// System-wide XDG data directories, most important first, in the target
+    data_dirs: Vec<PathBuf>,
+    /// Should we look at environment variables like `XR_RUNTIME_JSON`?
+    /// Only makes sense when looking at the live system with its own environment.
+    use_environment: bool,
+}
+
+impl LinuxPaths {
+    /// Use the live system and our environment
+    fn from_environment() -> Self {
+        let dirs = BaseDirectories::new().ok();
+        Self {
+            sysroot: Sysroot::host(),
+            config_home: dirs.as_ref().map(|d| d.get_config_home()),
+            config_dirs: dirs
+                .as_ref()
+                .map(|d| d.get_config_dirs())
+                .unwrap_or_else(|| vec![PathBuf::from(DEFAULT_XDG_CONFIG_DIRS)]),
+            data_home: dirs.as_ref().map(|d| d.get_data_home()),
+            data_dirs: dirs
+                .map(|d| d.get_data_dirs())
+                .unwrap_or_else(default_data_dirs),
+            use_environment: true,
+        }
+    }
+
+    /// Use the given root directory and explicit XDG settings, ignoring our environment
+    fn from_root_and_vars(root: &Path, vars: XdgConfigVars) -> Self {
+        let config_home = vars
+            .config_home
+            .or_else(|| vars.home.as_ref().map(|home| home.join(".config")));
+        let config_dirs = vars
+            .config_dirs
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(|| vec![PathBuf::from(DEFAULT_XDG_CONFIG_DIRS)]);
+        let data_home = vars
+            .data_home
+            .or_else(|| vars.home.map(|home| home.join(".local").join("share")));
+        let data_dirs = vars
+            .data_dirs
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(default_data_dirs);
+        Self {
+            sysroot: Sysroot::new(root),
+            config_home,
+            config_dirs,
+            data_home,
+            data_dirs,
+            use_environment: false,
+        }
+    }
+
+    /// The OpenXR directories we search, in decreasing order of precedence, as host paths.
+    fn openxr_dirs(&self) -> impl '_ + Iterator<Item = PathBuf> {
+        let suffix = make_path_suffix();
+        self.config_home
+            .iter()
+            .chain(self.config_dirs.iter())
+            .map(PathBuf::as_path)
+            .chain(once(Path::new(ETC)))
+            .map(move |dir| self.sysroot.map(&dir.join(&suffix)))
+    }
+
+    /// The directories we search for API layers of the given kind, as host paths.
+    fn api_layer_dirs(&self, kind: ApiLayerKind) -> Vec<PathBuf> {
+        if kind == ApiLayerKind::Explicit && self.use_environment {
+            if let Some(dirs) = environment_explicit_layer_dirs() {
+                return dirs;
+            }
+        }
+        let suffix = make_path_suffix().join(API_LAYERS).join(kind.dir_name());
+        self.config_home
+            .iter()
+            .chain(self.config_dirs.iter())
+            .map(PathBuf::as_path)
+            .chain(once(Path::new(ETC)))
+            .chain(self.data_home.iter().map(PathBuf::as_path))
+            .chain(self.data_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| self.sysroot.map(&dir.join(&suffix)))
+            .collect()
+    }
+
+    /// The per-user OpenXR config directory as a host path, if we know it
+    fn user_openxr_dir(&self) -> Option<PathBuf> {
+        self.config_home
+            .as_ref()
+            .map(|config_home| self.sysroot.map(&config_home.join(make_path_suffix())))
+    }
+
+    /// The per-user OpenXR config directory as a host path, created if needed
+    fn place_user_openxr_dir(&self) -> Result<PathBuf, Error> {
+        let dir = self.user_openxr_dir().ok_or_else(|| {
+            Error::SetActiveError("Could not determine the per-user config directory".to_owned())
+        })?;
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// The architectures of applications we consider: ours, then any 32-bit ones we can also run.
+    fn architectures(&self) -> Vec<Architecture> {
+        let host = Architecture::host();
+        once(host).chain(host.companion_architectures()).collect()
+    }
+
+    /// A file in our own per-user config directory, as a host path
+    fn own_config_file(&self, file_name: &str) -> Option<PathBuf> {
+        self.config_home.as_ref().map(|config_home| {
+            self.sysroot
+                .map(&config_home.join(env!("CARGO_PKG_NAME")).join(file_name))
+        })
+    }
+
+    /// Where we keep our activation history, as a host path
+    fn history_path(&self) -> Option<PathBuf> {
+        self.own_config_file(HISTORY_FILENAME)
+    }
+
+    /// The user's additions to the known runtimes, as a host path
+    fn known_runtimes_path(&self) -> Option<PathBuf> {
+        self.own_config_file(KNOWN_RUNTIMES_FILENAME)
+    }
+
+    /// Where the known runtimes might be installed, as host paths
+    fn known_runtime_locations(&self, known: &KnownRuntimes) -> Vec<PathBuf> {
+        let folder = |placeholder: &str| match placeholder {
+            "data_home" => self.data_home.clone(),
+            _ => None,
+        };
+        known
+            .runtimes()
+            .iter()
+            .flat_map(|runtime| &runtime.locations.linux)
+            .filter_map(|location| expand_location(location, folder))
+            .map(|path| self.sysroot.map(&path))
+            .collect()
+    }
+
+    fn load_history(&self) -> Result<(PathBuf, ActivationHistory), Error> {
+        let path = self.history_path().ok_or_else(|| {
+            Error::HistoryError("Could not determine the per-user config directory".to_owned())
+        })?;
+        let history = ActivationHistory::load(&path)?;
+        Ok((path, history))
+    }
+
+    /// Where a file whose changes we record is, as a host path: an active runtime file in the
+    /// per-user directory, or our `environment.d` snippet. Its directory is created if needed.
+    fn tracked_file_path(&self, file_name: &str) -> Result<PathBuf, Error> {
+        if file_name != ENVIRONMENT_D_SNIPPET {
+            return Ok(self.place_user_openxr_dir()?.join(file_name));
+        }
+        let path = self.environment_d_snippet_path().ok_or_else(|| {
+            Error::SetActiveError("Could not determine the per-user config directory".to_owned())
+        })?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(path)
+    }
+
+    /// Change an active runtime file in the per-user directory (or our `environment.d`
+    /// snippet), backing up whatever was there, so it can be undone.
+    ///
+    /// The file is replaced atomically: at every moment, it is either the old one or the new one.
+    /// Returns the change, for `record_changes()` to record in the history.
+    fn change_tracked_file(
+        &self,
+        file_name: &str,
+        new: &ActiveRuntimeSetting,
+    ) -> Result<ActivationRecord, Error> {
+        let path = self.tracked_file_path(file_name)?;
+        let (previous, backup) = self.back_up(&path, &self.place_user_openxr_dir()?)?;
+        if let Err(e) = self.replace_atomically(&path, new) {
+            // The original is untouched, so rolling back is just removing our backup of it
+            if let Some(backup) = backup {
+                let _ = fs::remove_file(backup);
+            }
+            return Err(e);
+        }
+        Ok(ActivationRecord::now(file_name, previous, new.clone()))
+    }
+
+    /// Like `change_tracked_file()`, to a regular file with `contents`. That is saved next to the
+    /// per-user active runtime files first, so the change can be restored later.
+    fn change_tracked_file_contents(
+        &self,
+        file_name: &str,
+        contents: &str,
+    ) -> Result<ActivationRecord, Error> {
+        let saved = saved_file_path(&self.place_user_openxr_dir()?.join(file_name), COPY_PREFIX);
+        fs::write(&saved, contents)?;
+        let new = ActiveRuntimeSetting::Backup(
+            self.sysroot.unmap(&saved).unwrap_or_else(|| saved.clone()),
+        );
+        let result = self.change_tracked_file(file_name, &new);
+        if result.is_err() {
+            let _ = fs::remove_file(saved);
+        }
+        result
+    }
+
+    /// Make changes with `change`, and record the ones it made (even if it then failed) in the
+    /// history as one group, so they are undone together.
+    fn record_changes(
+        &self,
+        change: impl FnOnce(&mut Vec<ActivationRecord>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut records = vec![];
+        let result = change(&mut records);
+        let recorded = self.record_activation(records);
+        result.and(recorded)
+    }
+
+    /// Stop setting `XR_RUNTIME_JSON` for future user sessions, if we did, returning the change
+    /// for `record_changes()`.
+    fn remove_session_environment(&self) -> Result<Option<ActivationRecord>, Error> {
+        match self.environment_d_snippet_path() {
+            Some(path) if path.symlink_metadata().is_ok() => self
+                .change_tracked_file(ENVIRONMENT_D_SNIPPET, &ActiveRuntimeSetting::Unset)
+                .map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Find out what an active runtime file is set to, leaving it in place.
+    ///
+    /// A regular file is copied to a backup in `backup_dir`, whose host path is also returned,
+    /// while for a symlink, its target is all we need to restore it.
+    fn back_up(
+        &self,
+        path: &Path,
+        backup_dir: &Path,
+    ) -> Result<(ActiveRuntimeSetting, Option<PathBuf>), Error> {
+        let failed = |step, path: &Path| {
+            let path = path.to_owned();
+            move |source| Error::ActivationFailed { step, path, source }
+        };
+        let metadata = match path.symlink_metadata() {
+            Ok(m) => m,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok((ActiveRuntimeSetting::Unset, None))
+            }
+            Err(e) => return Err(failed(ActivationStep::Inspect, path)(e)),
+        };
+        if metadata.is_symlink() {
+            let target = fs::read_link(path).map_err(failed(ActivationStep::Inspect, path))?;
+            return Ok((ActiveRuntimeSetting::Symlink(target), None));
+        }
+        let backup = saved_file_path(
+            &backup_dir.join(path.file_name().unwrap_or_default()),
+            BACKUP_PREFIX,
+        );
+        fs::copy(path, &backup).map_err(failed(ActivationStep::BackUp, path))?;
+        let setting = ActiveRuntimeSetting::Backup(
+            self.sysroot
+                .unmap(&backup)
+                .unwrap_or_else(|| backup.clone()),
+        );
+        Ok((setting, Some(backup)))
+    }
+
+    /// Replace (or remove) an active runtime file, by creating the new one under a temporary
+    /// name and renaming it over the old one.
+    fn replace_atomically(&self, path: &Path, new: &ActiveRuntimeSetting) -> Result<(), Error> {
+        match new {
+            ActiveRuntimeSetting::Unset => remove_if_present(path),
+            ActiveRuntimeSetting::Symlink(target) => {
+                replace_via_temporary(path, |temp| unix::fs::symlink(target, temp))
+            }
+            ActiveRuntimeSetting::Backup(backup) => replace_via_temporary(path, |temp| {
+                fs::copy(self.sysroot.map(backup), temp).map(|_| ())
+            }),
+        }
+    }
+
+    /// Where our `environment.d` snippet with the given name goes, as a host path
+    fn user_environment_d_path(&self, name: &str) -> Option<PathBuf> {
+        self.config_home.as_ref().map(|config_home| {
+            self.sysroot
+                .map(&config_home.join("environment.d").join(name))
+        })
+    }
+
+    /// Where our `environment.d` snippet goes, as a host path
+    fn environment_d_snippet_path(&self) -> Option<PathBuf> {
+        self.user_environment_d_path(ENVIRONMENT_D_SNIPPET)
+    }
+
+    /// Is this host path in a per-user directory, which the user can change without root and
+    /// no package manager owns?
+    fn is_user_path(&self, path: &Path) -> bool {
+        self.config_home
+            .iter()
+            .chain(self.data_home.iter())
+            .any(|dir| path.starts_with(self.sysroot.map(dir)))
+    }
+
+    /// The `disable_environment` variables of the API layers we disable for the user session
+    fn disabled_layer_variables(&self) -> Vec<String> {
+        self.user_environment_d_path(LAYERS_ENVIRONMENT_D_SNIPPET)
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| parse_environment_d_names(&contents))
+            .unwrap_or_default()
+    }
+
+    /// Disable (or stop disabling) an implicit API layer for future user sessions, by setting its
+    /// `disable_environment` variable.
+    fn set_layer_disabled_for_session(
+        &self,
+        layer: &ApiLayer,
+        disabled: bool,
+    ) -> Result<(), Error> {
+        let variable = layer.disable_environment().ok_or_else(|| {
+            Error::ApiLayerError(format!(
+                "{} is installed system-wide and has no disable_environment variable, \
+                 so it cannot be disabled for just this user",
+                layer.name()
+            ))
+        })?;
+        let path = self
+            .user_environment_d_path(LAYERS_ENVIRONMENT_D_SNIPPET)
+            .ok_or_else(|| {
+                Error::ApiLayerError("Could not determine the per-user config directory".to_owned())
+            })?;
+        let mut variables = self.disabled_layer_variables();
+        variables.retain(|v| v != variable);
+        if disabled {
+            variables.push(variable.to_owned());
+        }
+        write_layers_environment_d_snippet(&path, &variables)
+    }
+
+    /// Where the system-wide `environment.d` snippet goes, for every user's session, as a host path
+    fn system_environment_d_snippet_path(&self) -> PathBuf {
+        self.sysroot.map(
+            &Path::new(ETC)
+                .join("environment.d")
+                .join(ENVIRONMENT_D_SNIPPET),
+        )
+    }
+
+    /// The system-wide OpenXR config directory, where the active runtime for all users goes,
+    /// as a host path: in the first XDG config directory, usually `/etc/xdg/openxr/1`.
+    fn system_openxr_dir(&self) -> PathBuf {
+        let config_dir = self
+            .config_dirs
+            .first()
+            .map(PathBuf::as_path)
+            .unwrap_or_else(|| Path::new(ETC));
+        self.sysroot.map(&config_dir.join(make_path_suffix()))
+    }
+
+    /// Replace a system-wide active runtime file, keeping a backup if it is a regular file.
+    ///
+    /// Not recorded in the history: that belongs to whoever runs this, usually root.
+    fn replace_system_active_runtime_file(
+        &self,
+        path: &Path,
+        new: &NewActiveRuntimeFile,
+    ) -> Result<(), Error> {
+        let dir = path.parent().expect("files always have parents");
+        fs::create_dir_all(dir)?;
+        self.back_up(path, dir)?;
+        match new {
+            NewActiveRuntimeFile::Symlink(target) => {
+                replace_via_temporary(path, |temp| unix::fs::symlink(target, temp))
+            }
+            NewActiveRuntimeFile::Copy { contents, .. } => {
+                replace_via_temporary(path, |temp| fs::write(temp, contents))
+            }
+        }
+    }
+
+    /// The first of a file and the directories above it (inside our root) that keep other users
+    /// from reading it, if any
+    fn blocks_other_users(&self, path: &Path) -> Option<PathBuf> {
+        let readable = path
+            .metadata()
+            .is_ok_and(|m| m.permissions().mode() & 0o004 != 0);
+        if !readable {
+            return Some(path.to_owned());
+        }
+        path.ancestors()
+            .skip(1)
+            .take_while(|dir| self.sysroot.unmap(dir).is_some())
+            .find(|dir| {
+                !dir.metadata()
+                    .is_ok_and(|m| m.permissions().mode() & 0o001 != 0)
+            })
+            .map(Path::to_owned)
+    }
+
+    /// Add changes made together to the history, applying the default retention policy.
+    ///
+    /// The changes themselves already happened, so the error says so if this fails.
+    fn record_activation(&self, records: Vec<ActivationRecord>) -> Result<(), Error> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.load_history()
+            .and_then(|(path, mut history)| {
+                history.push_group(records);
+                let unneeded = history.prune(DEFAULT_HISTORY_LENGTH);
+                history.save(&path)?;
+                for backup in unneeded {
+                    remove_if_present(&self.sysroot.map(&backup))?;
+                }
+                Ok(())
+            })
+            .map_err(|e| {
+                Error::HistoryError(format!(
+                    "The change was made, but could not be recorded in the activation history: {}",
+                    e
+                ))
+            })
+    }
+
+    /// All the places an active runtime manifest for applications of the given architecture
+    /// might be, in decreasing order of precedence, whether or not they exist.
+    ///
+    /// Like the loader, all the architecture-specific files come before any
+    /// `active_runtime.json`. Each is returned with whether it is architecture-specific.
+    fn active_runtime_candidates(
+        &self,
+        architecture: Architecture,
+    ) -> impl '_ + Iterator<Item = (PathBuf, bool)> {
+        let arch_specific: Vec<(PathBuf, bool)> = active_runtime_filename(architecture)
+            .map(|name| {
+                self.openxr_dirs()
+                    .map(|dir| (dir.join(&name), true))
+                    .collect()
+            })
+            .unwrap_or_default();
+        arch_specific.into_iter().chain(
+            self.openxr_dirs()
+                .map(|dir| (dir.join(ACTIVE_RUNTIME_FILENAME), false)),
+        )
+    }
+
+    /// The active runtime manifests that exist for any architecture, canonicalized.
+    fn possible_active_runtimes(&self) -> impl '_ + Iterator<Item = PathBuf> {
+        self.architectures()
+            .into_iter()
+            .flat_map(|arch| self.active_runtime_candidates(arch))
+            .filter_map(|(p, _)| self.sysroot.canonicalize(&p).ok())
+            .filter(|p| p.is_file())
+    }
+
+    /// The active runtime manifest (canonical) for applications of the given architecture,
+    /// if any, and whether it came from an architecture-specific file.
+    fn active_runtime_for(&self, architecture: Architecture) -> Option<(PathBuf, bool)> {
+        self.active_runtime_candidates(architecture)
+            .filter_map(|(p, arch_specific)| {
+                self.sysroot
+                    .canonicalize(&p)
+                    .ok()
+                    .map(|canonical| (canonical, arch_specific))
+            })
+            .find(|(p, _)| p.is_file())
+    }
+
+    /// The active runtime file in effect for applications of the given architecture, if any,
+    /// with the manifest it selects (canonical)
+    fn active_runtime_file_for(&self, architecture: Architecture) -> Option<(PathBuf, PathBuf)> {
+        self.active_runtime_candidates(architecture)
+            .filter_map(|(p, _)| {
+                self.sysroot
+                    .canonicalize(&p)
+                    .ok()
+                    .map(|canonical| (p, canonical))
+            })
+            .find(|(_, canonical)| canonical.is_file())
+    }
+
+    /// The names of the active runtime files in the per-user directory that exist
+    fn user_active_runtime_files(&self) -> Vec<String> {
+        let dir = match self.user_openxr_dir() {
+            Some(dir) => dir,
+            None => return vec![],
+        };
+        once(ACTIVE_RUNTIME_FILENAME.to_owned())
+            .chain(
+                self.architectures()
+                    .into_iter()
+                    .filter_map(active_runtime_filename),
+            )
+            .filter(|name| dir.join(name).symlink_metadata().is_ok())
+            .collect()
+    }
+
+    /// Is there a per-user setting that overrides the system-wide active runtime?
+    fn has_user_active_runtime(&self) -> bool {
+        !self.user_active_runtime_files().is_empty()
+            || self
+                .environment_d_snippet_path()
+                .is_some_and(|p| p.symlink_metadata().is_ok())
+    }
+
+    /// All files in our OpenXR directories that might be runtime manifests
+    fn find_potential_manifests(&self) -> impl '_ + Iterator<Item = PathBuf> {
+        self.openxr_dirs()
+            .flat_map(|dir| dir.read_dir().into_iter().flatten())
+            .filter_map(|r| r.ok())
+            .filter(|entry| {
+                // keep only files and symlinks
+                entry
+                    .metadata()
+                    .map(|m| m.is_file() || m.is_symlink())
+                    .unwrap_or(false)
+            })
+            .map(|entry| entry.path())
+            .filter(|p| !is_active_runtime_name(p) && !is_saved_active_runtime_name(p))
+    }
+
+    /// The manifest from `XR_RUNTIME_JSON`, if set and we are using the environment
+    fn environment_override(&self) -> Option<PathBuf> {
+        if self.use_environment {
+            environment_override_manifest()
+        } else {
+            None
+        }
+    }
+}
+
+/// A new active runtime file, selecting a manifest
+enum NewActiveRuntimeFile {
+    /// A symlink to this path in the target, absolute or relative
+    Symlink(PathBuf),
+    /// A copy of a manifest, changed to work from anywhere
+    Copy { of: PathBuf, contents: String },
+}
+
+impl Display for NewActiveRuntimeFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NewActiveRuntimeFile::Symlink(target) => write!(f, "symlink to {}", target.display()),
+            NewActiveRuntimeFile::Copy { of, .. } => {
+                write!(f, "copy of {}, with an absolute library path", of.display())
+            }
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(PartialEq, Eq)]
 pub struct LinuxRuntime {
     base: BaseRuntime,
     orig_path: PathBuf,
+    paths: Arc<LinuxPaths>,
+    /// Resolved once up front, since searching for a library by name is slow
+    library_path: PathBuf,
+    /// Inspected once up front, since reading the library is slow
+    library: LibraryInspection,
+    /// Resolved the first time they are needed, since that reads the whole tree of libraries
+    dependencies: OnceLock<Vec<ResolvedDependency>>,
 }
 
 impl LinuxRuntime {
-    fn new(orig_path: &Path, canonical_path: &Path) -> Result<Self, Error> {
-        let base = BaseRuntime::new(canonical_path)?;
+    /// Can applications of the given architecture use this runtime? Assume so if we could not
+    /// tell what the library is built for.
+    fn supports_architecture(&self, architecture: Architecture) -> bool {
+        !matches!(self.library.architecture, Some(arch) if arch != architecture)
+    }
+
+    /// Our manifest's path inside the target system, which is what links and settings must use
+    fn manifest_in_target(&self) -> Result<PathBuf, Error> {
+        self.paths
+            .sysroot
+            .unmap(self.base.get_manifest_path())
+            .ok_or_else(|| {
+                Error::SetActiveError(format!(
+                    "The manifest {} is outside of the root directory",
+                    self.base.get_manifest_path().display()
+                ))
+            })
+    }
+
+    /// Our manifest, with the library path made absolute so it works from anywhere
+    fn manifest_copy_contents(&self) -> Result<String, Error> {
+        let library_path = Some(&self.library_path)
+            .filter(|p| p.is_absolute())
+            .and_then(|p| self.paths.sysroot.unmap(p))
+            .ok_or_else(|| {
+                Error::SetActiveError(format!(
+                    "Cannot copy the manifest of {}, since its library {} was not found",
+                    self.get_runtime_name(),
+                    self.base.library_path()
+                ))
+            })?;
+        let contents = fs::read_to_string(self.base.get_manifest_path())?;
+        let mut manifest: serde_json::Value = serde_json::from_str(&contents)?;
+        manifest["runtime"]["library_path"] = library_path.to_string_lossy().into();
+        Ok(serde_json::to_string_pretty(&manifest)?)
+    }
+
+    /// What an active runtime file in `dir` (a host path) should be, to select our manifest
+    /// in the given way
+    fn new_active_runtime_file(
+        &self,
+        dir: &Path,
+        strategy: ActivationStrategy,
+    ) -> Result<NewActiveRuntimeFile, Error> {
+        let manifest = self.manifest_in_target()?;
+        match strategy {
+            ActivationStrategy::Symlink => Ok(NewActiveRuntimeFile::Symlink(manifest)),
+            ActivationStrategy::RelativeSymlink => {
+                // Relative to where the directory really is, in case it is itself a symlink
+                let real_dir = self
+                    .paths
+                    .sysroot
+                    .canonicalize(dir)
+                    .unwrap_or_else(|_| dir.to_owned());
+                let real_dir = self.paths.sysroot.unmap(&real_dir).ok_or_else(|| {
+                    Error::SetActiveError(format!(
+                        "{} is outside of the root directory",
+                        real_dir.display()
+                    ))
+                })?;
+                Ok(NewActiveRuntimeFile::Symlink(relative_path(
+                    &real_dir, &manifest,
+                )))
+            }
+            ActivationStrategy::Copy => Ok(NewActiveRuntimeFile::Copy {
+                of: manifest,
+                contents: self.manifest_copy_contents()?,
+            }),
+            ActivationStrategy::Environment => Err(Error::SetActiveError(format!(
+                "{} is not an active runtime file setting",
+                strategy
+            ))),
+        }
+    }
+
+    /// Check that every user of the system could use this runtime, made active in the given way
+    fn check_usable_by_all_users(&self, strategy: ActivationStrategy) -> Result<(), Error> {
+        // A copy of the manifest is made, so only the library must be readable
+        let manifest =
+            Some(self.base.get_manifest_path()).filter(|_| strategy != ActivationStrategy::Copy);
+        // A library not found by name cannot be checked: the loader will have no better luck
+        let library = Some(self.library_path.as_path()).filter(|p| p.is_absolute());
+        for path in manifest.into_iter().chain(library) {
+            if let Some(blocker) = self.paths.blocks_other_users(path) {
+                return Err(Error::SetActiveError(format!(
+                    "Other users cannot read {}, because of the permissions of {}",
+                    path.display(),
+                    blocker.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the given active runtime file in the per-user directory, so it selects our
+    /// manifest in the given way. Returns the change, for `record_changes()`.
+    fn replace_active_runtime_file(
+        &self,
+        file_name: &str,
+        strategy: ActivationStrategy,
+    ) -> Result<ActivationRecord, Error> {
+        let dir = self.paths.place_user_openxr_dir()?;
+        match self.new_active_runtime_file(&dir, strategy)? {
+            NewActiveRuntimeFile::Symlink(target) => self
+                .paths
+                .change_tracked_file(file_name, &ActiveRuntimeSetting::Symlink(target)),
+            NewActiveRuntimeFile::Copy { contents, .. } => self
+                .paths
+                .change_tracked_file_contents(file_name, &contents),
+        }
+    }
+
+    fn new(
+        orig_path: &Path,
+        canonical_path: &Path,
+        paths: Arc<LinuxPaths>,
+        known: &KnownRuntimes,
+    ) -> Result<Self, Error> {
+        let base = BaseRuntime::new(canonical_path)?.with_known_runtimes(known);
+        let library_path = base.resolve_library_path_in(&paths.sysroot);
+        let library = base.inspect_library_in(&paths.sysroot);
+        let base = base.with_library_version(library.version.as_ref());
         Ok(LinuxRuntime {
             base,
             orig_path: orig_path.to_owned(),
+            paths,
+            library_path,
+            library,
+            dependencies: OnceLock::new(),
         })
     }
+
+    fn resolved_dependencies(&self) -> &[ResolvedDependency] {
+        self.dependencies
+            .get_or_init(|| self.base.resolve_dependencies_in(&self.paths.sysroot))
+    }
+}
+
+impl Debug for LinuxRuntime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The paths are shared by all runtimes, so they are just noise here
+        f.debug_struct("LinuxRuntime")
+            .field("base", &self.base)
+            .field("orig_path", &self.orig_path)
+            .field("architecture", &self.library.architecture)
+            .finish_non_exhaustive()
+    }
 }
 
 impl PlatformRuntime for LinuxRuntime {
     fn make_active(&self) -> Result<(), Error> {
-        fn convert_err(e: BaseDirectoriesError) -> Error {
-            Error::SetActiveError(e.to_string())
+        self.make_active_with_strategy(ActivationStrategy::default())
+    }
+
+    fn make_active_with_strategy(&self, strategy: ActivationStrategy) -> Result<(), Error> {
+        if strategy == ActivationStrategy::Environment {
+            let contents = environment_d_snippet_contents(&self.manifest_in_target()?)?;
+            return self.paths.record_changes(|records| {
+                records.push(
+                    self.paths
+                        .change_tracked_file_contents(ENVIRONMENT_D_SNIPPET, &contents)?,
+                );
+                Ok(())
+            });
         }
-        let dirs = BaseDirectories::new().map_err(convert_err)?;
-        let suffix = make_path_suffix();
-        let path = dirs.place_config_file(suffix.join(ACTIVE_RUNTIME_FILENAME))?;
+        // All recorded together, so undoing this undoes all of it
+        self.paths.record_changes(|records| {
+            records.push(self.replace_active_runtime_file(ACTIVE_RUNTIME_FILENAME, strategy)?);
 
-        // First move the old file out of the way, if any.
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        let move_target =
-            dirs.place_config_file(suffix.join(format!("old_active_runtime{}.json", timestamp)))?;
-
-        match fs::rename(&path, &move_target) {
-            Ok(_) => {
-                // Only keep our renamed file if it wasn't a symlink
-                if let Ok(m) = move_target.symlink_metadata() {
-                    if m.is_symlink() && fs::remove_file(&move_target).is_err() {
-                        // that's ok
-                        eprintln!(
-                            "Got an error trying to remove an apparently-symlink {}",
-                            move_target.display()
-                        )
+            // Architecture-specific files take precedence wherever they are, so for each
+            // architecture this runtime can take over that has one (ours or a system-wide one),
+            // write our own. The others (like a separate 32-bit runtime) are left alone.
+            let user_dir = self.paths.place_user_openxr_dir()?;
+            for arch in self.paths.architectures() {
+                if let Some(name) = active_runtime_filename(arch) {
+                    let shadowed = user_dir.join(&name).symlink_metadata().is_ok()
+                        || self
+                            .paths
+                            .active_runtime_for(arch)
+                            .is_some_and(|(_, arch_specific)| arch_specific);
+                    if shadowed && self.supports_architecture(arch) {
+                        records.push(self.replace_active_runtime_file(&name, strategy)?);
                     }
                 }
             }
-            Err(e) => {
-                // ignore and hope it meant there was just nothing to move
-                eprintln!(
-                    "Got an error trying to rename {} to {}: {}",
-                    path.display(),
-                    move_target.display(),
-                    e
-                );
+
+            // Otherwise, this would still override the active runtime in the next session
+            records.extend(self.paths.remove_session_environment()?);
+            Ok(())
+        })
+    }
+
+    fn make_active_system_wide(
+        &self,
+        strategy: ActivationStrategy,
+        dry_run: bool,
+    ) -> Result<Vec<String>, Error> {
+        self.check_usable_by_all_users(strategy)?;
+        let snippet = self.paths.system_environment_d_snippet_path();
+        if strategy == ActivationStrategy::Environment {
+            let manifest = self.manifest_in_target()?;
+            if !dry_run {
+                write_environment_d_snippet(&snippet, Some(&manifest))?;
             }
+            return Ok(vec![format!(
+                "{}: set {} to {}",
+                snippet.display(),
+                RUNTIME_JSON_ENV_VAR,
+                manifest.display()
+            )]);
         }
-        unix::fs::symlink(self.base.get_manifest_path(), &path)?;
-        Ok(())
+
+        // Like for a single user, also update the architecture-specific files there are
+        let dir = self.paths.system_openxr_dir();
+        let file_names = once(ACTIVE_RUNTIME_FILENAME.to_owned()).chain(
+            self.paths
+                .architectures()
+                .into_iter()
+                .filter(|arch| self.supports_architecture(*arch))
+                .filter_map(active_runtime_filename)
+                .filter(|name| dir.join(name).symlink_metadata().is_ok()),
+        );
+        let mut changes = vec![];
+        for name in file_names {
+            let path = dir.join(name);
+            let new = self.new_active_runtime_file(&dir, strategy)?;
+            if !dry_run {
+                self.paths.replace_system_active_runtime_file(&path, &new)?;
+            }
+            changes.push(format!("{}: {}", path.display(), new));
+        }
+        if snippet.symlink_metadata().is_ok() {
+            if !dry_run {
+                remove_if_present(&snippet)?;
+            }
+            changes.push(format!(
+                "{}: remove, since it would override the active runtime",
+                snippet.display()
+            ));
+        }
+        Ok(changes)
+    }
+
+    fn make_active_for_architecture(
+        &self,
+        architecture: Architecture,
+        strategy: ActivationStrategy,
+    ) -> Result<(), Error> {
+        if strategy == ActivationStrategy::Environment {
+            return Err(Error::SetActiveError(format!(
+                "{} applies to applications of every architecture",
+                RUNTIME_JSON_ENV_VAR
+            )));
+        }
+        let name = active_runtime_filename(architecture).ok_or_else(|| {
+            Error::SetActiveError(format!(
+                "The OpenXR loader has no active runtime setting for {}",
+                architecture
+            ))
+        })?;
+        if !self.supports_architecture(architecture) {
+            return Err(Error::SetActiveError(format!(
+                "{} is built for {}, so {} applications cannot use it",
+                self.get_runtime_name(),
+                self.library.architecture.unwrap_or(Architecture::Unknown),
+                architecture
+            )));
+        }
+        self.paths.record_changes(|records| {
+            records.push(self.replace_active_runtime_file(&name, strategy)?);
+            Ok(())
+        })
     }
 
     fn get_runtime_name(&self) -> String {
@@ -98,12 +1029,48 @@ impl PlatformRuntime for LinuxRuntime {
     }
 
     fn get_libraries(&self) -> Vec<PathBuf> {
-        let path = self.base.resolve_library_path();
-        vec![path]
+        vec![self.library_path.clone()]
+    }
+
+    fn get_problems(&self) -> Vec<RuntimeProblem> {
+        let mut problems = self.library.problems.clone();
+        problems.extend(missing_dependency_problems(
+            &self.library_path,
+            self.resolved_dependencies(),
+        ));
+        problems
+    }
+
+    fn get_architectures(&self) -> Vec<Architecture> {
+        self.library.architecture.into_iter().collect()
+    }
+
+    fn get_version(&self) -> Option<LibraryVersion> {
+        self.library.version.clone()
+    }
+
+    fn get_known_runtime(&self) -> Option<&KnownRuntime> {
+        self.base.get_known_runtime()
+    }
+
+    fn get_dependencies(&self) -> Vec<ResolvedDependency> {
+        self.resolved_dependencies().to_vec()
     }
 
     fn describe(&self) -> String {
-        let description = self.base.describe_manifest(self.base.get_manifest_path());
+        let mut description = self.base.describe_manifest(self.base.get_manifest_path());
+        if self.base.uses_search_path() {
+            if self.library_path.is_absolute() {
+                description = format!(
+                    "{}{}{}",
+                    description,
+                    FILE_INDIRECTION_ARROW,
+                    PathSimplifier::new().simplify(&self.library_path).display()
+                );
+            } else {
+                description.push_str(" (not found)");
+            }
+        }
         if self.orig_path != self.base.get_manifest_path() {
             format!(
                 "{}{}{}",
@@ -118,83 +1085,212 @@ impl PlatformRuntime for LinuxRuntime {
 }
 
 pub struct LinuxPlatform {
-    path_suffix: PathBuf,
+    paths: Arc<LinuxPaths>,
 }
 
 impl LinuxPlatform {
-    fn new() -> Self {
-        let path_suffix = make_path_suffix();
-        Self { path_suffix }
+    fn new(paths: LinuxPaths) -> Self {
+        Self {
+            paths: Arc::new(paths),
+        }
     }
 }
 
+/// The path an implicit layer manifest should have when enabled or disabled
+fn layer_manifest_path_for(path: &Path, enabled: bool) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_str()?;
+    let new_name = if enabled {
+        file_name.strip_suffix(DISABLED_SUFFIX)?.to_owned()
+    } else {
+        format!("{}{}", file_name, DISABLED_SUFFIX)
+    };
+    Some(path.with_file_name(new_name))
+}
+
+/// Is this `active_runtime.json`, or an architecture-specific one like `active_runtime.i686.json`?
 fn is_active_runtime_name(p: &Path) -> bool {
-    p.file_name().map(|s| s.as_bytes()) == Some(ACTIVE_RUNTIME_FILENAME.as_bytes())
-}
-
-fn find_potential_manifests_xdg(suffix: &Path) -> impl Iterator<Item = PathBuf> {
-    let suffix = suffix.to_owned();
-    BaseDirectories::new()
-        .ok()
-        .into_iter()
-        .flat_map(move |xdg_dirs| xdg_dirs.list_config_files(&suffix))
-        .filter(|p| !is_active_runtime_name(p))
-}
-
-fn find_potential_manifests_sysconfdir(suffix: &Path) -> impl Iterator<Item = PathBuf> {
-    make_sysconfdir(suffix)
-        .read_dir()
-        .into_iter()
-        .flatten()
-        .filter_map(|r| r.ok())
-        .filter(|entry| {
-            // keep only files and symlinks
-            entry
-                .metadata()
-                .map(|m| m.is_file() || m.is_symlink())
-                .unwrap_or(false)
-        })
-        .map(|entry| entry.path())
-        .filter(|p| !is_active_runtime_name(p))
+    p.file_name().and_then(|s| s.to_str()).is_some_and(|s| {
+        s == ACTIVE_RUNTIME_FILENAME || (s.starts_with("active_runtime.") && s.ends_with(".json"))
+    })
 }
 
-pub struct LinuxActiveRuntimeData(Option<PathBuf>);
+/// Is this a file we saved for the activation history: a backup of an active runtime file, like
+/// `old_active_runtime1680000000.json`, or a copy of a manifest we made active?
+fn is_saved_active_runtime_name(p: &Path) -> bool {
+    p.file_name().and_then(|s| s.to_str()).is_some_and(|s| {
+        [BACKUP_PREFIX, COPY_PREFIX]
+            .iter()
+            .any(|prefix| s.starts_with(&format!("{}active_runtime", prefix)))
+            && s.ends_with(".json")
+    })
+}
 
-impl LinuxActiveRuntimeData {
-    fn new() -> Self {
-        LinuxActiveRuntimeData(possible_active_runtimes().next())
+/// Write an `environment.d` snippet (a host path) setting `XR_RUNTIME_JSON` to the given manifest
+/// (a path in the target), or with `None`, remove it.
+fn write_environment_d_snippet(path: &Path, manifest: Option<&Path>) -> Result<(), Error> {
+    let manifest = match manifest {
+        Some(manifest) => manifest,
+        None => return remove_if_present(path),
+    };
+    let contents = environment_d_snippet_contents(manifest)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
     }
+    replace_via_temporary(path, |temp| fs::write(temp, contents))
+}
 
-    fn check_runtime(&self, runtime: &LinuxRuntime) -> ActiveState {
-        if let Some(active_path) = &self.0 {
-            if active_path == runtime.base.get_manifest_path() {
-                return ActiveState::ActiveIndependentRuntime;
-            }
-        }
-        ActiveState::NotActive
+/// The contents of an `environment.d` snippet setting `XR_RUNTIME_JSON` to the given manifest
+/// (a path in the target)
+fn environment_d_snippet_contents(manifest: &Path) -> Result<String, Error> {
+    let value = manifest.to_str().ok_or_else(|| {
+        Error::SetActiveError(format!(
+            "The manifest path {} cannot be put in an environment variable",
+            manifest.display()
+        ))
+    })?;
+    Ok(format!(
+        "# Written by {}: the OpenXR runtime for applications in the user session\n{}={}\n",
+        env!("CARGO_PKG_NAME"),
+        RUNTIME_JSON_ENV_VAR,
+        quote_environment_value(value)
+    ))
+}
+
+/// The names of the variables an `environment.d` file sets
+fn parse_environment_d_names(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, _)| name.trim().to_owned())
+        .collect()
+}
+
+/// Write an `environment.d` snippet (a host path) setting each of the given `disable_environment`
+/// variables, or with none, remove it.
+fn write_layers_environment_d_snippet(path: &Path, variables: &[String]) -> Result<(), Error> {
+    if variables.is_empty() {
+        return remove_if_present(path);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut contents = format!(
+        "# Written by {}: OpenXR API layers disabled in the user session\n",
+        env!("CARGO_PKG_NAME")
+    );
+    for variable in variables {
+        contents.push_str(&format!("{}=1\n", variable));
+    }
+    replace_via_temporary(path, |temp| fs::write(temp, contents))
+}
+
+/// Remove a file, which is fine if it is already gone
+fn remove_if_present(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::ActivationFailed {
+            step: ActivationStep::Remove,
+            path: path.to_owned(),
+            source: e,
+        }),
+        _ => Ok(()),
     }
 }
 
-fn possible_active_runtimes() -> impl Iterator<Item = PathBuf> {
-    let suffix = make_path_suffix().join(ACTIVE_RUNTIME_FILENAME);
-    let etc_iter = once(make_sysconfdir(&suffix));
-    // Warning: BaseDirectories returns increasing order of importance, which is
-    // opposite of what we want, so we reverse it.
-    let xdg_iter = BaseDirectories::new()
-        .ok()
-        .into_iter()
-        .flat_map(move |d| d.find_config_files(&suffix))
-        .rev();
+pub struct LinuxActiveRuntimeData {
+    /// For each architecture we consider (ours first), the active manifest, if any, and whether
+    /// it came from an architecture-specific file
+    active: Vec<(Architecture, Option<(PathBuf, bool)>)>,
+    /// Canonical path of the manifest from `XR_RUNTIME_JSON`, if set
+    environment_override: Option<PathBuf>,
+    /// Active runtime files that are copies of a manifest rather than symlinks, with the library
+    /// each one loads, so we can tell which runtime they are a copy of
+    copies: Vec<(PathBuf, PathBuf)>,
+    /// Is there a per-user setting that overrides the system-wide active runtime?
+    user_setting: bool,
+}
 
-    xdg_iter
-        .chain(etc_iter)
-        .filter(|p| {
-            p.metadata()
-                .map(|m| m.is_file() || m.is_symlink())
-                .ok()
-                .unwrap_or_default()
-        })
-        .filter_map(|p| p.canonicalize().ok())
+impl LinuxActiveRuntimeData {
+    fn new(paths: &LinuxPaths) -> Self {
+        let active: Vec<(Architecture, Option<(PathBuf, bool)>)> = paths
+            .architectures()
+            .into_iter()
+            .map(|arch| (arch, paths.active_runtime_for(arch)))
+            .collect();
+        let copies = active
+            .iter()
+            .filter_map(|(_, active)| active.as_ref())
+            .map(|(path, _)| path)
+            .filter(|path| is_active_runtime_name(path))
+            .unique()
+            .filter_map(|path| {
+                BaseRuntime::new(path)
+                    .ok()
+                    .map(|base| (path.clone(), base.resolve_library_path_in(&paths.sysroot)))
+            })
+            .collect();
+        LinuxActiveRuntimeData {
+            active,
+            environment_override: paths
+                .environment_override()
+                .map(|p| p.canonicalize().unwrap_or(p)),
+            copies,
+            user_setting: paths.has_user_active_runtime(),
+        }
+    }
+    fn check_runtime(&self, runtime: &LinuxRuntime) -> ActiveState {
+        let manifest_path = runtime.base.get_manifest_path();
+        let is_active = |active: &Option<(PathBuf, bool)>| {
+            active.as_ref().is_some_and(|(path, _)| {
+                path == manifest_path
+                    || self
+                        .copies
+                        .iter()
+                        .any(|(copy, library)| copy == path && *library == runtime.library_path)
+            })
+        };
+        let any_arch_specific = self.active.iter().any(|(_, active)| {
+            active
+                .as_ref()
+                .is_some_and(|(_, arch_specific)| *arch_specific)
+        });
+        let (_, host_active) = self
+            .active
+            .first()
+            .expect("we always consider our own architecture");
+        let active_64 = is_active(host_active);
+        let active_32 = self.active[1..].iter().any(|(_, active)| is_active(active));
+        let state = if !any_arch_specific || self.active.len() == 1 {
+            // One setting covers everything
+            if active_64 {
+                ActiveState::ActiveIndependentRuntime
+            } else {
+                ActiveState::NotActive
+            }
+        } else {
+            ActiveState::from_active_64_and_32(active_64, active_32)
+        };
+        state.with_environment_override(
+            self.environment_override
+                .as_ref()
+                .map(|p| p == manifest_path),
+        )
+    }
+
+    /// The manifest(s) the loader will actually use: one per architecture, without duplicates
+    fn effective(self) -> Vec<PathBuf> {
+        if let Some(manifest) = self.environment_override {
+            return vec![manifest];
+        }
+        let mut manifests: Vec<PathBuf> = vec![];
+        for (path, _) in self.active.into_iter().filter_map(|(_, active)| active) {
+            if !manifests.contains(&path) {
+                manifests.push(path);
+            }
+        }
+        manifests
+    }
 }
 
 impl Platform for LinuxPlatform {
@@ -206,15 +1302,35 @@ impl Platform for LinuxPlatform {
         extra_paths: Box<dyn '_ + Iterator<Item = PathBuf>>,
     ) -> Result<(Vec<Self::PlatformRuntimeType>, Vec<ManifestError>), Error> {
         let mut known_manifests: HashSet<PathBuf> = HashSet::default();
+        let mut nonfatal_errors = vec![];
+
+        let known_runtimes = match self.paths.known_runtimes_path() {
+            Some(path) => KnownRuntimes::load(&path).unwrap_or_else(|e| {
+                nonfatal_errors.push(ManifestError(path, e));
+                KnownRuntimes::embedded().clone()
+            }),
+            None => KnownRuntimes::embedded().clone(),
+        };
 
-        let manifest_files = find_potential_manifests_xdg(&self.path_suffix)
-            .chain(find_potential_manifests_sysconfdir(&self.path_suffix))
-            .chain(possible_active_runtimes()) // put these almost last so they are only included if they mention a not-previously-found runtime
+        let sysroot = &self.paths.sysroot;
+        let manifest_files = self
+            .paths
+            .find_potential_manifests()
+            // in case they are installed somewhere the loader does not look
+            .chain(self.paths.known_runtime_locations(&known_runtimes))
+            .chain(self.paths.possible_active_runtimes()) // put these almost last so they are only included if they mention a not-previously-found runtime
+            .chain(self.paths.environment_override())
             .chain(extra_paths)
-            .filter_map(|p| p.canonicalize().ok().map(|canonical| (p, canonical)));
+            .filter_map(|p| {
+                // Extra paths might be outside of the root
+                sysroot
+                    .canonicalize(&p)
+                    .or_else(|_| p.canonicalize())
+                    .ok()
+                    .map(|canonical| (p, canonical))
+            });
 
         let mut runtimes = vec![];
-        let mut nonfatal_errors = vec![];
 
         for (orig_path, canonical) in manifest_files {
             if known_manifests.contains(&orig_path) {
@@ -223,7 +1339,12 @@ impl Platform for LinuxPlatform {
             if known_manifests.contains(&canonical) {
                 continue;
             }
-            let runtime = match LinuxRuntime::new(&orig_path, &canonical) {
+            let runtime = match LinuxRuntime::new(
+                &orig_path,
+                &canonical,
+                self.paths.clone(),
+                &known_runtimes,
+            ) {
                 Ok(r) => r,
                 Err(e) => {
                     eprintln!(
@@ -246,11 +1367,11 @@ impl Platform for LinuxPlatform {
     }
 
     fn get_active_runtime_manifests(&self) -> Vec<PathBuf> {
-        LinuxActiveRuntimeData::new().0.into_iter().collect()
+        LinuxActiveRuntimeData::new(&self.paths).effective()
     }
 
     fn get_active_data(&self) -> Self::PlatformActiveData {
-        LinuxActiveRuntimeData::new()
+        LinuxActiveRuntimeData::new(&self.paths)
     }
 
     fn get_runtime_active_state(
@@ -260,9 +1381,710 @@ impl Platform for LinuxPlatform {
     ) -> ActiveState {
         active_data.check_runtime(runtime)
     }
+
+    fn diagnose(&self) -> Diagnosis {
+        let mut diagnosis = Diagnosis::default();
+        let sysroot = &self.paths.sysroot;
+        if self.paths.use_environment {
+            for check in check_environment() {
+                diagnosis.push(check);
+            }
+        } else {
+            diagnosis.push(Check::new(
+                CheckStatus::Info,
+                "Inspecting an alternate root or explicit directories: environment variables are not checked",
+            ));
+        }
+
+        // Walk the candidates like the loader does: the first existing one wins, unless
+        // XR_RUNTIME_JSON overrides them all.
+        let host = Architecture::host();
+        let environment_override = self.paths.environment_override();
+        let mut winner: Option<PathBuf> = None;
+        for (candidate, arch_specific) in self.paths.active_runtime_candidates(host) {
+            // Architecture-specific files are rarely used, so only mention them if present
+            if arch_specific && candidate.symlink_metadata().is_err() {
+                continue;
+            }
+            let simplified = PathSimplifier::new()
+                .simplify(&candidate)
+                .display()
+                .to_string();
+            let is_symlink = candidate
+                .symlink_metadata()
+                .map(|m| m.is_symlink())
+                .unwrap_or_default();
+            let target = fs::read_link(&candidate)
+                .map(|t| format!("symlink to {}", t.display()))
+                .ok();
+            let canonical = sysroot.canonicalize(&candidate).ok();
+            if canonical.is_some() {
+                if environment_override.is_some() {
+                    diagnosis.push(Check::new(
+                        CheckStatus::Info,
+                        format!(
+                            "{} exists, but is overridden by {}",
+                            simplified, RUNTIME_JSON_ENV_VAR
+                        ),
+                    ));
+                } else if winner.is_some() {
+                    diagnosis.push(Check::new(
+                        CheckStatus::Info,
+                        format!("{} exists, but is overridden by the one above", simplified),
+                    ));
+                } else {
+                    let mut check = Check::new(
+                        CheckStatus::Ok,
+                        format!("{} exists, and is used as the active runtime", simplified),
+                    );
+                    if let Some(target) = target {
+                        check = check.with_detail(target);
+                    }
+                    diagnosis.push(check);
+                    winner = canonical;
+                }
+            } else if is_symlink {
+                diagnosis.push(
+                    Check::new(
+                        CheckStatus::Warning,
+                        format!("{} is a dangling symlink, so it is skipped", simplified),
+                    )
+                    .with_detail(target.unwrap_or_default())
+                    .with_suggestion(format!(
+                        "Remove {} or choose a runtime with `xrpicker set`",
+                        candidate.display()
+                    )),
+                );
+            } else {
+                diagnosis.push(Check::new(
+                    CheckStatus::Info,
+                    format!("{} does not exist", simplified),
+                ));
+            }
+        }
+
+        if let Some(manifest) = &environment_override {
+            // It applies to applications of every architecture, so that is all to check
+            for check in check_override_manifest(manifest, Some(host)) {
+                diagnosis.push(check);
+            }
+            return diagnosis;
+        }
+        match &winner {
+            Some(manifest) => {
+                // We can only know what architecture is needed on the live system
+                let expected_arch = Some(host).filter(|_| sysroot.is_host());
+                for check in check_active_manifest(manifest, sysroot, expected_arch) {
+                    diagnosis.push(check);
+                }
+            }
+            None => diagnosis.push(
+                Check::new(CheckStatus::Failure, "No active runtime is set")
+                    .with_suggestion("Choose a runtime with `xrpicker list` and `xrpicker set`"),
+            ),
+        }
+
+        // 32-bit applications only get a runtime of their own if one is set up specifically
+        for arch in host.companion_architectures() {
+            if let Some((manifest, true)) = self.paths.active_runtime_for(arch) {
+                diagnosis.push(
+                    Check::new(
+                        CheckStatus::Info,
+                        format!("{} applications use a separate active runtime", arch),
+                    )
+                    .with_detail(manifest.display().to_string()),
+                );
+                let expected_arch = Some(arch).filter(|_| sysroot.is_host());
+                for check in check_active_manifest(&manifest, sysroot, expected_arch) {
+                    diagnosis.push(check);
+                }
+            }
+        }
+        diagnosis
+    }
+
+    fn activation_strategies(&self) -> Vec<ActivationStrategy> {
+        ACTIVATION_STRATEGIES.to_vec()
+    }
+
+    fn find_api_layers(&self) -> Result<(Vec<ApiLayer>, Vec<ManifestError>), Error> {
+        let sysroot = &self.paths.sysroot;
+        let mut collection = ApiLayerCollection::default();
+        for kind in [ApiLayerKind::Implicit, ApiLayerKind::Explicit] {
+            for dir in self.paths.api_layer_dirs(kind) {
+                for (path, enabled) in layer_manifests_in_dir(&dir, kind) {
+                    // Skip dangling symlinks and anything else we cannot resolve
+                    if let Ok(canonical) = sysroot.canonicalize(&path) {
+                        collection.add(&path, &canonical, kind, enabled);
+                    }
+                }
+            }
+        }
+        let disabled_variables = self.paths.disabled_layer_variables();
+        let (layers, nonfatal_errors) = collection.finish();
+        let layers = layers
+            .into_iter()
+            .map(|layer| {
+                let disabled_for_session = layer.kind() == ApiLayerKind::Implicit
+                    && layer
+                        .disable_environment()
+                        .is_some_and(|v| disabled_variables.iter().any(|d| d == v));
+                if disabled_for_session {
+                    layer.with_enabled(false)
+                } else {
+                    layer
+                }
+            })
+            .collect();
+        Ok((layers, nonfatal_errors))
+    }
+
+    fn set_api_layer_enabled(&self, layer: &ApiLayer, enabled: bool) -> Result<(), Error> {
+        if layer.kind() != ApiLayerKind::Implicit {
+            return Err(Error::ApiLayerError(format!(
+                "{} is an explicit layer: it is only loaded when requested",
+                layer.name()
+            )));
+        }
+        if layer.is_enabled() == enabled {
+            return Ok(());
+        }
+        let from = layer.manifest_path();
+        let renamed = from
+            .file_name()
+            .and_then(|s| s.to_str())
+            .is_some_and(|s| s.ends_with(DISABLED_SUFFIX));
+        if !renamed && !self.paths.is_user_path(from) {
+            // Renaming a package's manifest would need root, and the next update would put it
+            // back, so use the layer's own switch in the user session instead.
+            return self.paths.set_layer_disabled_for_session(layer, !enabled);
+        }
+        // Per-user layers are disabled by renaming the manifest, so the loader no longer sees it.
+        let to = layer_manifest_path_for(from, enabled).ok_or_else(|| {
+            Error::ApiLayerError(format!("Unexpected manifest file name {}", from.display()))
+        })?;
+        if to.symlink_metadata().is_ok() {
+            return Err(Error::ApiLayerError(format!(
+                "Cannot rename {}: {} already exists",
+                from.display(),
+                to.display()
+            )));
+        }
+        fs::rename(from, &to).map_err(|e| {
+            Error::ApiLayerError(format!(
+                "Could not rename {} to {}: {}",
+                from.display(),
+                to.display(),
+                e
+            ))
+        })
+    }
+
+    fn has_user_active_runtime(&self, active_data: &Self::PlatformActiveData) -> bool {
+        active_data.user_setting
+    }
+
+    fn clear_user_active_runtime(&self) -> Result<Option<(PathBuf, PathBuf)>, Error> {
+        // Backed up and recorded like any other change, all together, so this can be undone
+        self.paths.record_changes(|records| {
+            for name in self.paths.user_active_runtime_files() {
+                records.push(
+                    self.paths
+                        .change_tracked_file(&name, &ActiveRuntimeSetting::Unset)?,
+                );
+            }
+            records.extend(self.paths.remove_session_environment()?);
+            Ok(())
+        })?;
+        Ok(self.paths.active_runtime_file_for(Architecture::host()))
+    }
+
+    fn get_activation_history(&self) -> Result<Vec<ActivationRecord>, Error> {
+        // Without a per-user config directory, there is nowhere we could have recorded anything
+        if self.paths.history_path().is_none() {
+            return Ok(vec![]);
+        }
+        Ok(self.paths.load_history()?.1.entries)
+    }
+
+    fn undo_last_activation(&self) -> Result<(), Error> {
+        let (_, history) = self.paths.load_history()?;
+        if history.entries.is_empty() {
+            return Err(Error::HistoryError(
+                "There are no changes to undo".to_owned(),
+            ));
+        }
+        // The last change, with any made together with it, undone in reverse order
+        let group = &history.entries[history.group_around(history.entries.len() - 1)];
+        self.paths.record_changes(|records| {
+            for entry in group.iter().rev() {
+                records.push(
+                    self.paths
+                        .change_tracked_file(&entry.file_name, &entry.previous)?,
+                );
+            }
+            Ok(())
+        })
+    }
+
+    fn restore_activation(&self, index: usize) -> Result<(), Error> {
+        let (_, history) = self.paths.load_history()?;
+        if index >= history.entries.len() {
+            return Err(Error::HistoryError(format!(
+                "There is no change {} in the history, which has {} change(s)",
+                index,
+                history.entries.len()
+            )));
+        }
+        // Along with any changes made together with it
+        let group = &history.entries[history.group_around(index)];
+        self.paths.record_changes(|records| {
+            for entry in group {
+                records.push(
+                    self.paths
+                        .change_tracked_file(&entry.file_name, &entry.new)?,
+                );
+            }
+            Ok(())
+        })
+    }
+
+    fn clean_activation_history(
+        &self,
+        keep: usize,
+        remove_unrecorded: bool,
+    ) -> Result<usize, Error> {
+        let (path, mut history) = self.paths.load_history()?;
+        let mut unneeded: Vec<PathBuf> = history
+            .prune(keep)
+            .into_iter()
+            .map(|backup| self.paths.sysroot.map(&backup))
+            .collect();
+        history.save(&path)?;
+
+        // Older versions kept backups without recording them anywhere: only removed on request,
+        // since we cannot tell those apart from files someone else put there.
+        if remove_unrecorded {
+            let needed: HashSet<PathBuf> = history
+                .backups()
+                .map(|backup| self.paths.sysroot.map(backup))
+                .collect();
+            if let Some(dir) = self.paths.user_openxr_dir() {
+                unneeded.extend(
+                    dir.read_dir()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|r| r.ok())
+                        .map(|entry| entry.path())
+                        .filter(|p| {
+                            is_saved_active_runtime_name(p)
+                                && !needed.contains(p)
+                                && !unneeded.contains(p)
+                        })
+                        .collect::<Vec<_>>(),
+                );
+            }
+        }
+        let mut removed = 0;
+        for backup in unneeded {
+            match fs::remove_file(&backup) {
+                Ok(()) => removed += 1,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(Error::HistoryError(format!(
+                        "Could not remove the backup {}: {}",
+                        backup.display(),
+                        e
+                    )))
+                }
+            }
+        }
+        Ok(removed)
+    }
 }
 
 /// Call to create a platform-specific object implementing the `Platform` trait.
 pub fn make_platform() -> LinuxPlatform {
-    LinuxPlatform::new()
+    LinuxPlatform::new(LinuxPaths::from_environment())
+}
+
+/// Create a platform object that works on the system in an alternate root directory
+/// (e.g. a mounted disk image, a chroot, or a container rootfs), using the given XDG settings
+/// instead of the environment.
+///
+/// The root may be `/` to use the live system with explicit XDG settings.
+pub fn make_platform_with_root(root: &Path, vars: XdgConfigVars) -> LinuxPlatform {
+    LinuxPlatform::new(LinuxPaths::from_root_and_vars(root, vars))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::test_binaries::elf_library;
+
+    /// A fake root with one runtime, made active system-wide with an absolute symlink as seen
+    /// inside the root
+    fn fake_root() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let share = root.path().join("usr/share/openxr/1");
+        fs::create_dir_all(&share).unwrap();
+        fs::write(
+            share.join("openxr_test.json"),
+            r#"{"file_format_version": "1.0.0", "runtime": {"name": "Test", "library_path": "/usr/lib/libtest.so"}}"#,
+        )
+        .unwrap();
+        let etc = root.path().join("etc/xdg/openxr/1");
+        fs::create_dir_all(&etc).unwrap();
+        symlink(
+            "/usr/share/openxr/1/openxr_test.json",
+            etc.join("active_runtime.json"),
+        )
+        .unwrap();
+        root
+    }
+
+    #[test]
+    fn discovery_stays_inside_root() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        let platform = make_platform_with_root(&root_path, XdgConfigVars::default());
+        let manifest = root_path.join("usr/share/openxr/1/openxr_test.json");
+
+        assert_eq!(
+            platform.get_active_runtime_manifests(),
+            vec![manifest.clone()]
+        );
+
+        let (runtimes, errors) = platform
+            .find_available_runtimes(Box::new(std::iter::empty()))
+            .unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(runtimes.len(), 1);
+        let runtime = &runtimes[0];
+        assert_eq!(runtime.get_runtime_name(), "Test");
+        assert_eq!(runtime.get_manifests(), vec![manifest.as_path()]);
+        // The library is looked for in the root, not on the host
+        assert_eq!(
+            runtime.get_libraries(),
+            vec![root_path.join("usr/lib/libtest.so")]
+        );
+        for path in runtime
+            .get_manifests()
+            .into_iter()
+            .map(Path::to_owned)
+            .chain(runtime.get_libraries())
+        {
+            assert!(
+                path.starts_with(&root_path),
+                "{} left the root",
+                path.display()
+            );
+        }
+        let state = platform.get_runtime_active_state(runtime, &platform.get_active_data());
+        assert!(state.is_effectively_active());
+    }
+
+    fn write_layer(dir: &Path, file_name: &str, name: &str) {
+        fs::create_dir_all(dir).unwrap();
+        let manifest = format!(
+            r#"{{"file_format_version": "1.0.0", "api_layer": {{"name": "{}", "library_path": "lib.so", "api_version": "1.0", "implementation_version": "1", "description": "", "disable_environment": "DISABLE_{}"}}}}"#,
+            name, name
+        );
+        fs::write(dir.join(file_name), manifest).unwrap();
+    }
+
+    #[test]
+    fn disabling_layers_leaves_system_files_alone() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        let system_dir = root_path.join("usr/share/openxr/1/api_layers/implicit.d");
+        let user_dir = root_path.join("home/user/.config/openxr/1/api_layers/implicit.d");
+        write_layer(&system_dir, "system.json", "SYSTEM");
+        write_layer(&user_dir, "user.json", "USER");
+        let platform = make_platform_with_root(
+            &root_path,
+            XdgConfigVars {
+                home: Some(PathBuf::from("/home/user")),
+                ..Default::default()
+            },
+        );
+        let find = |name: &str| {
+            let (layers, errors) = platform.find_api_layers().unwrap();
+            assert!(errors.is_empty());
+            layers.into_iter().find(|l| l.name() == name).unwrap()
+        };
+        let snippet = root_path
+            .join("home/user/.config/environment.d")
+            .join(LAYERS_ENVIRONMENT_D_SNIPPET);
+
+        platform
+            .set_api_layer_enabled(&find("SYSTEM"), false)
+            .unwrap();
+        assert!(system_dir.join("system.json").exists());
+        assert!(!find("SYSTEM").is_enabled());
+        assert!(fs::read_to_string(&snippet)
+            .unwrap()
+            .contains("\nDISABLE_SYSTEM=1\n"));
+
+        platform
+            .set_api_layer_enabled(&find("USER"), false)
+            .unwrap();
+        assert!(user_dir.join("user.json.disabled").exists());
+        assert!(!find("USER").is_enabled());
+
+        platform
+            .set_api_layer_enabled(&find("SYSTEM"), true)
+            .unwrap();
+        platform.set_api_layer_enabled(&find("USER"), true).unwrap();
+        assert!(find("SYSTEM").is_enabled());
+        assert!(find("USER").is_enabled());
+        assert!(user_dir.join("user.json").exists());
+        assert!(!snippet.exists());
+    }
+
+    #[test]
+    fn user_activation_wins_over_system_architecture_specific_file() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        let share = root_path.join("usr/share/openxr/1");
+        fs::write(
+            share.join("openxr_other.json"),
+            r#"{"file_format_version": "1.0.0", "runtime": {"name": "Other", "library_path": "/usr/lib/libother.so"}}"#,
+        )
+        .unwrap();
+        let arch_file = active_runtime_filename(Architecture::host()).unwrap();
+        symlink(
+            "/usr/share/openxr/1/openxr_other.json",
+            root_path.join("etc/xdg/openxr/1").join(&arch_file),
+        )
+        .unwrap();
+        let platform = make_platform_with_root(
+            &root_path,
+            XdgConfigVars {
+                home: Some(PathBuf::from("/home/user")),
+                ..Default::default()
+            },
+        );
+        let (runtimes, _) = platform
+            .find_available_runtimes(Box::new(std::iter::empty()))
+            .unwrap();
+        let runtime = runtimes
+            .iter()
+            .find(|r| r.get_runtime_name() == "Test")
+            .unwrap();
+
+        runtime.make_active().unwrap();
+        let user_dir = root_path.join("home/user/.config/openxr/1");
+        assert!(user_dir.join(&arch_file).symlink_metadata().is_ok());
+        assert_eq!(
+            platform.paths.active_runtime_for(Architecture::host()),
+            Some((share.join("openxr_test.json"), true))
+        );
+    }
+
+    #[test]
+    fn cleaning_history_keeps_unrecorded_files_unless_asked() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        let platform = make_platform_with_root(
+            &root_path,
+            XdgConfigVars {
+                home: Some(PathBuf::from("/home/user")),
+                ..Default::default()
+            },
+        );
+        let user_dir = root_path.join("home/user/.config/openxr/1");
+        fs::create_dir_all(&user_dir).unwrap();
+        let stray = user_dir.join("old_active_runtime1680000000.json");
+        fs::write(&stray, "{}").unwrap();
+
+        assert_eq!(platform.clean_activation_history(0, false).unwrap(), 0);
+        assert!(stray.exists());
+        assert_eq!(platform.clean_activation_history(0, true).unwrap(), 1);
+        assert!(!stray.exists());
+    }
+
+    #[test]
+    fn system_wide_dry_run_changes_nothing() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        // Readable by everyone, as a real system would be
+        fs::set_permissions(&root_path, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(
+            root_path.join("usr/share/openxr/1/openxr_other.json"),
+            r#"{"file_format_version": "1.0.0", "runtime": {"name": "Other", "library_path": "/usr/lib/libother.so"}}"#,
+        )
+        .unwrap();
+        fs::create_dir_all(root_path.join("usr/lib")).unwrap();
+        fs::write(root_path.join("usr/lib/libother.so"), "").unwrap();
+        let etc = root_path.join("etc/xdg/openxr/1");
+        let arch_file = active_runtime_filename(Architecture::host()).unwrap();
+        symlink("/usr/share/openxr/1/openxr_test.json", etc.join(&arch_file)).unwrap();
+        let snippet = root_path
+            .join("etc/environment.d")
+            .join(ENVIRONMENT_D_SNIPPET);
+        fs::create_dir_all(snippet.parent().unwrap()).unwrap();
+        fs::write(&snippet, "XR_RUNTIME_JSON=/somewhere.json\n").unwrap();
+        let list = |dir: &Path| -> Vec<PathBuf> {
+            let mut files: Vec<PathBuf> = dir
+                .read_dir()
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect();
+            files.sort();
+            files
+        };
+        let before = list(&etc);
+
+        let platform = make_platform_with_root(&root_path, XdgConfigVars::default());
+        let other_manifest = root_path.join("usr/share/openxr/1/openxr_other.json");
+        let (runtimes, _) = platform
+            .find_available_runtimes(Box::new(std::iter::once(other_manifest)))
+            .unwrap();
+        let runtime = runtimes
+            .iter()
+            .find(|r| r.get_runtime_name() == "Other")
+            .unwrap();
+        let changes = runtime
+            .make_active_system_wide(ActivationStrategy::Symlink, true)
+            .unwrap();
+
+        let other = "/usr/share/openxr/1/openxr_other.json";
+        assert_eq!(changes.len(), 3, "{:?}", changes);
+        assert!(changes[0].starts_with(&format!(
+            "{}: ",
+            etc.join(ACTIVE_RUNTIME_FILENAME).display()
+        )));
+        assert!(changes[0].contains(other));
+        assert!(changes[1].starts_with(&format!("{}: ", etc.join(&arch_file).display())));
+        assert!(changes[1].contains(other));
+        assert!(changes[2].starts_with(&format!("{}: remove", snippet.display())));
+
+        assert_eq!(list(&etc), before);
+        for name in [ACTIVE_RUNTIME_FILENAME, arch_file.as_str()] {
+            assert_eq!(
+                fs::read_link(etc.join(name)).unwrap(),
+                Path::new("/usr/share/openxr/1/openxr_test.json")
+            );
+        }
+        assert!(snippet.exists());
+    }
+
+    #[test]
+    fn reset_is_undone_as_a_whole() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        let platform = make_platform_with_root(
+            &root_path,
+            XdgConfigVars {
+                home: Some(PathBuf::from("/home/user")),
+                ..Default::default()
+            },
+        );
+        let user_dir = root_path.join("home/user/.config/openxr/1");
+        fs::create_dir_all(&user_dir).unwrap();
+        let user_file = user_dir.join(ACTIVE_RUNTIME_FILENAME);
+        symlink("/usr/share/openxr/1/openxr_test.json", &user_file).unwrap();
+        let snippet = root_path
+            .join("home/user/.config/environment.d")
+            .join(ENVIRONMENT_D_SNIPPET);
+        fs::create_dir_all(snippet.parent().unwrap()).unwrap();
+        let snippet_contents = "XR_RUNTIME_JSON=\"/usr/share/openxr/1/openxr_test.json\"\n";
+        fs::write(&snippet, snippet_contents).unwrap();
+
+        platform.clear_user_active_runtime().unwrap();
+        assert!(user_file.symlink_metadata().is_err());
+        assert!(!snippet.exists());
+        let history = platform.get_activation_history().unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(history[0].group.is_some());
+        assert_eq!(history[0].group, history[1].group);
+
+        platform.undo_last_activation().unwrap();
+        assert_eq!(
+            fs::read_link(&user_file).unwrap(),
+            Path::new("/usr/share/openxr/1/openxr_test.json")
+        );
+        assert_eq!(fs::read_to_string(&snippet).unwrap(), snippet_contents);
+        // The undo is itself one change, so undoing it resets again
+        assert_eq!(platform.get_activation_history().unwrap().len(), 4);
+        platform.undo_last_activation().unwrap();
+        assert!(user_file.symlink_metadata().is_err());
+        assert!(!snippet.exists());
+    }
+
+    #[test]
+    fn environment_activation_is_undone() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        let platform = make_platform_with_root(
+            &root_path,
+            XdgConfigVars {
+                home: Some(PathBuf::from("/home/user")),
+                ..Default::default()
+            },
+        );
+        let snippet = root_path
+            .join("home/user/.config/environment.d")
+            .join(ENVIRONMENT_D_SNIPPET);
+        let (runtimes, _) = platform
+            .find_available_runtimes(Box::new(std::iter::empty()))
+            .unwrap();
+        runtimes[0]
+            .make_active_with_strategy(ActivationStrategy::Environment)
+            .unwrap();
+        let contents = fs::read_to_string(&snippet).unwrap();
+        assert!(contents.contains("XR_RUNTIME_JSON=\"/usr/share/openxr/1/openxr_test.json\""));
+        let history = platform.get_activation_history().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].file_name, ENVIRONMENT_D_SNIPPET);
+        assert_eq!(history[0].previous, ActiveRuntimeSetting::Unset);
+
+        platform.undo_last_activation().unwrap();
+        assert!(snippet.symlink_metadata().is_err());
+        // The active runtime file was never touched
+        assert_eq!(
+            fs::read_link(root_path.join("etc/xdg/openxr/1/active_runtime.json")).unwrap(),
+            Path::new("/usr/share/openxr/1/openxr_test.json")
+        );
+
+        // And undoing the undo brings it back
+        platform.undo_last_activation().unwrap();
+        assert_eq!(fs::read_to_string(&snippet).unwrap(), contents);
+    }
+
+    #[test]
+    fn missing_dependencies_are_problems() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        let lib = root_path.join("usr/lib");
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(
+            lib.join("libtest.so"),
+            elf_library(
+                &["libpresent.so.1", "libmissing.so.1"],
+                &["xrNegotiateLoaderRuntimeInterface"],
+            ),
+        )
+        .unwrap();
+        fs::write(lib.join("libpresent.so.1"), elf_library(&[], &[])).unwrap();
+        let platform = make_platform_with_root(&root_path, XdgConfigVars::default());
+        let (runtimes, _) = platform
+            .find_available_runtimes(Box::new(std::iter::empty()))
+            .unwrap();
+        let runtime = &runtimes[0];
+
+        let problems = runtime.get_problems();
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(matches!(
+            &problems[0],
+            RuntimeProblem::MissingDependency { dependency, .. } if dependency == "libmissing.so.1"
+        ));
+        let dependencies = runtime.get_dependencies();
+        assert_eq!(dependencies.len(), 2);
+        assert_eq!(dependencies[0].path, Some(lib.join("libpresent.so.1")));
+        assert!(dependencies[1].is_missing());
+    }
 }
@@ -81,6 +81,20 @@ pub(crate) mod json_subobjects {
         pub(crate) name: Option<String>,
         pub(crate) functions: Option<RuntimeFunctions>,
     }
+
+    /// The main object in an API layer manifest
+    #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+    pub(crate) struct ApiLayer {
+        pub(crate) name: String,
+        pub(crate) library_path: String,
+        pub(crate) api_version: Option<String>,
+        pub(crate) implementation_version: Option<String>,
+        pub(crate) description: Option<String>,
+        /// Required for implicit layers: if this environment variable is set, the layer is not loaded
+        pub(crate) disable_environment: Option<String>,
+        /// For implicit layers: if present, the layer is only loaded if this environment variable is set
+        pub(crate) enable_environment: Option<String>,
+    }
 }
 
 /// Top level structure corresponding to a runtime manifest
@@ -98,3 +112,19 @@ impl GenericManifest for RuntimeManifest {
         self.file_format_version == "1.0.0"
     }
 }
+
+/// Top level structure corresponding to an API layer manifest
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub(crate) struct ApiLayerManifest {
+    file_format_version: String,
+    pub(crate) api_layer: json_subobjects::ApiLayer,
+}
+
+impl GenericManifest for ApiLayerManifest {
+    fn library_path(&self) -> &str {
+        &self.api_layer.library_path
+    }
+    fn is_file_format_version_ok(&self) -> bool {
+        self.file_format_version == "1.0.0"
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Group the 64-bit and 32-bit manifests of a runtime, for platforms (Windows) that list them
+//! separately.
+//!
+//! This only looks at paths and manifest contents, not at the filesystem, so it behaves the
+//! same on any OS. Paths may use either `/` or `\` as a separator.
+
+use std::path::Path;
+
+/// What pairing needs to know about a runtime manifest
+#[derive(Debug, Clone, Copy)]
+pub struct ManifestSummary<'a> {
+    pub manifest_path: &'a Path,
+    /// The runtime name, if known
+    pub runtime_name: Option<&'a str>,
+    /// The library path, as written in the manifest
+    pub library_path: &'a str,
+}
+
+/// The manifests of one runtime: at least one of these is `Some`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuntimeGroup<'a> {
+    pub manifest_64: Option<&'a Path>,
+    pub manifest_32: Option<&'a Path>,
+}
+
+/// Words, or endings of words, that only say which architecture something is for
+const BITNESS_MARKERS: [&str; 7] = ["amd64", "x64", "x86", "i386", "i686", "64", "32"];
+
+/// A word without its bitness marker: either all of it, or an ending after a letter, like in
+/// `win64`. Other digits are left alone, so `v3264` stays as it is.
+fn strip_bitness(word: &str) -> &str {
+    BITNESS_MARKERS
+        .iter()
+        .filter_map(|marker| word.strip_suffix(marker))
+        .find(|rest| rest.is_empty() || rest.ends_with(char::is_alphabetic))
+        .unwrap_or(word)
+}
+
+/// Lowercase, without bitness markers or punctuation, so `win64` and `win32` (or
+/// `Program Files` and `Program Files (x86)`) come out the same.
+fn without_bitness(s: &str) -> String {
+    s.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .map(strip_bitness)
+        .collect()
+}
+
+/// The components of a path, splitting on either separator
+fn components(path: &str) -> Vec<&str> {
+    path.split(['/', '\\']).filter(|c| !c.is_empty()).collect()
+}
+
+/// The file name without its extension, whichever separator the path uses
+fn file_stem(path: &str) -> &str {
+    let name = components(path).pop().unwrap_or_default();
+    name.rsplit_once('.').map_or(name, |(stem, _)| stem)
+}
+
+/// The directories containing a manifest, lowercased, since Windows paths are not
+/// case-sensitive
+fn parent_components(manifest: &Path) -> Vec<String> {
+    let path = manifest.to_string_lossy();
+    let mut components: Vec<String> = components(&path)
+        .into_iter()
+        .map(str::to_lowercase)
+        .collect();
+    components.pop();
+    components
+}
+
+/// How the directories of two manifests relate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Location {
+    Unrelated,
+    /// Directories whose names only differ in bitness, like `bin/win64` and `bin/win32`
+    Sibling,
+    SameDirectory,
+}
+
+fn location(a: &ManifestSummary, b: &ManifestSummary) -> Location {
+    let (a, b) = (
+        parent_components(a.manifest_path),
+        parent_components(b.manifest_path),
+    );
+    if a == b {
+        Location::SameDirectory
+    } else if a.len() == b.len()
+        && a.iter()
+            .zip(&b)
+            .all(|(a, b)| without_bitness(a) == without_bitness(b))
+    {
+        Location::Sibling
+    } else {
+        Location::Unrelated
+    }
+}
+
+/// Do these look like manifests for the same runtime, going by names rather than location?
+fn same_identity(a: &ManifestSummary, b: &ManifestSummary) -> bool {
+    let same_name = matches!(
+        (a.runtime_name, b.runtime_name),
+        (Some(a), Some(b)) if a.eq_ignore_ascii_case(b)
+    );
+    let same_stem = |a: &str, b: &str| {
+        let a = without_bitness(file_stem(a));
+        !a.is_empty() && a == without_bitness(file_stem(b))
+    };
+    same_name
+        || same_stem(a.library_path, b.library_path)
+        || same_stem(
+            &a.manifest_path.to_string_lossy(),
+            &b.manifest_path.to_string_lossy(),
+        )
+}
+
+/// How well a 32-bit manifest matches a 64-bit one, if at all: higher is better
+fn match_score(manifest_64: &ManifestSummary, manifest_32: &ManifestSummary) -> Option<u8> {
+    if manifest_64.manifest_path == manifest_32.manifest_path {
+        // The same manifest in both lists: it finds the right library itself
+        return Some(u8::MAX);
+    }
+    let identity = same_identity(manifest_64, manifest_32);
+    match (location(manifest_64, manifest_32), identity) {
+        (Location::SameDirectory, true) => Some(3),
+        (Location::Sibling, true) => Some(2),
+        // As always done: one runtime per directory is the norm
+        (Location::SameDirectory, false) => Some(1),
+        (Location::Sibling, false) | (Location::Unrelated, _) => None,
+    }
+}
+
+/// Group 64-bit and 32-bit runtime manifests into runtimes.
+///
+/// A 64-bit manifest is paired with a 32-bit one that is the same manifest, or in the same
+/// directory or a sibling directory (like `bin/win32` for `bin/win64`), preferring ones with
+/// the same runtime name, library file name or manifest file name (ignoring bitness markers
+/// like `64` or `x86`). The best matches of all are paired first, so a runtime without a 32-bit
+/// manifest does not take another one's. The groups follow the order of the 64-bit manifests,
+/// then the remaining 32-bit manifests follow on their own.
+pub fn pair_manifests<'a>(
+    manifests_64: &[ManifestSummary<'a>],
+    manifests_32: &[ManifestSummary<'a>],
+) -> Vec<RuntimeGroup<'a>> {
+    let mut candidates: Vec<(usize, usize, u8)> = manifests_64
+        .iter()
+        .enumerate()
+        .flat_map(|(i, manifest_64)| {
+            manifests_32
+                .iter()
+                .enumerate()
+                .filter_map(move |(j, manifest_32)| {
+                    Some((i, j, match_score(manifest_64, manifest_32)?))
+                })
+        })
+        .collect();
+    // Stable, so among equals, earlier manifests are paired first
+    candidates.sort_by_key(|(_, _, score)| std::cmp::Reverse(*score));
+
+    let mut pairs: Vec<Option<usize>> = vec![None; manifests_64.len()];
+    let mut paired_32 = vec![false; manifests_32.len()];
+    for (i, j, _) in candidates {
+        if pairs[i].is_none() && !paired_32[j] {
+            pairs[i] = Some(j);
+            paired_32[j] = true;
+        }
+    }
+    let mut groups: Vec<RuntimeGroup> = manifests_64
+        .iter()
+        .zip(pairs)
+        .map(|(manifest_64, pair)| RuntimeGroup {
+            manifest_64: Some(manifest_64.manifest_path),
+            manifest_32: pair.map(|j| manifests_32[j].manifest_path),
+        })
+        .collect();
+    groups.extend(
+        manifests_32
+            .iter()
+            .zip(paired_32)
+            .filter(|(_, paired)| !paired)
+            .map(|(manifest_32, _)| RuntimeGroup {
+                manifest_64: None,
+                manifest_32: Some(manifest_32.manifest_path),
+            }),
+    );
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary<'a>(manifest_path: &'a str, name: &'a str, library: &'a str) -> ManifestSummary<'a> {
+        ManifestSummary {
+            manifest_path: Path::new(manifest_path),
+            runtime_name: Some(name),
+            library_path: library,
+        }
+    }
+
+    fn paired<'a>(a: &'a str, b: &'a str) -> RuntimeGroup<'a> {
+        RuntimeGroup {
+            manifest_64: Some(Path::new(a)),
+            manifest_32: Some(Path::new(b)),
+        }
+    }
+
+    #[test]
+    fn bitness_markers() {
+        assert_eq!(without_bitness("win64"), "win");
+        assert_eq!(without_bitness("Win32"), "win");
+        assert_eq!(without_bitness("Program Files (x86)"), "programfiles");
+        assert_eq!(without_bitness("runtime_x86_64"), "runtime");
+        assert_eq!(without_bitness("runtime-x86-64"), "runtime");
+        assert_eq!(without_bitness("runtime_i686"), "runtime");
+        // Only whole words, or endings after a letter
+        assert_eq!(
+            without_bitness("openxr_runtime_v3264"),
+            "openxrruntimev3264"
+        );
+        assert_eq!(without_bitness("d3d11"), "d3d11");
+    }
+
+    #[test]
+    fn sibling_bin_directories() {
+        let m64 = [summary(
+            "C:\\Runtime\\bin\\win64\\runtime.json",
+            "Runtime",
+            "runtime64.dll",
+        )];
+        let m32 = [summary(
+            "C:\\Runtime\\bin\\win32\\runtime.json",
+            "Runtime",
+            "runtime32.dll",
+        )];
+        assert_eq!(
+            pair_manifests(&m64, &m32),
+            vec![paired(
+                "C:\\Runtime\\bin\\win64\\runtime.json",
+                "C:\\Runtime\\bin\\win32\\runtime.json"
+            )]
+        );
+    }
+
+    #[test]
+    fn same_manifest_in_both_views() {
+        let manifest = "C:\\Runtime\\runtime.json";
+        let m64 = [summary(manifest, "Runtime", "runtime.dll")];
+        let m32 = [
+            summary("C:\\Other\\other.json", "Other", "other.dll"),
+            summary(manifest, "Runtime", "runtime.dll"),
+        ];
+        assert_eq!(
+            pair_manifests(&m64, &m32),
+            vec![
+                paired(manifest, manifest),
+                RuntimeGroup {
+                    manifest_64: None,
+                    manifest_32: Some(Path::new("C:\\Other\\other.json")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn program_files_and_program_files_x86() {
+        let m64 = [summary(
+            "C:\\Program Files\\Vendor\\openxr_64.json",
+            "Vendor",
+            "vendor_openxr_x64.dll",
+        )];
+        let m32 = [summary(
+            "C:\\Program Files (x86)\\Vendor\\openxr_32.json",
+            "Vendor",
+            "vendor_openxr_x86.dll",
+        )];
+        assert_eq!(
+            pair_manifests(&m64, &m32),
+            vec![paired(
+                "C:\\Program Files\\Vendor\\openxr_64.json",
+                "C:\\Program Files (x86)\\Vendor\\openxr_32.json"
+            )]
+        );
+    }
+
+    #[test]
+    fn names_alone_do_not_pair_unrelated_directories() {
+        let m64 = [summary("C:\\One\\runtime.json", "Runtime", "runtime.dll")];
+        let m32 = [summary("D:\\Two\\runtime.json", "Runtime", "runtime.dll")];
+        assert_eq!(
+            pair_manifests(&m64, &m32),
+            vec![
+                RuntimeGroup {
+                    manifest_64: Some(Path::new("C:\\One\\runtime.json")),
+                    manifest_32: None,
+                },
+                RuntimeGroup {
+                    manifest_64: None,
+                    manifest_32: Some(Path::new("D:\\Two\\runtime.json")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn best_matches_are_paired_first() {
+        // Only the second runtime has a 32-bit manifest, in the same directory as both
+        let m64 = [
+            summary("C:\\Runtimes\\alpha.json", "Alpha", "alpha.dll"),
+            summary("C:\\Runtimes\\beta_64.json", "Beta", "beta64.dll"),
+        ];
+        let m32 = [summary("C:\\Runtimes\\beta_32.json", "Beta", "beta32.dll")];
+        assert_eq!(
+            pair_manifests(&m64, &m32),
+            vec![
+                RuntimeGroup {
+                    manifest_64: Some(Path::new("C:\\Runtimes\\alpha.json")),
+                    manifest_32: None,
+                },
+                paired("C:\\Runtimes\\beta_64.json", "C:\\Runtimes\\beta_32.json"),
+            ]
+        );
+    }
+}
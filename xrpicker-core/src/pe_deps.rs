@@ -0,0 +1,444 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Find the DLLs a PE (Windows) library imports, roughly the way the Windows loader does, so we
+//! can tell whether a runtime library (and everything it needs) can actually be loaded.
+//!
+//! This works on any OS, so a Windows runtime can be checked elsewhere, including a Windows
+//! system mounted with `--root`. It is a model, not a perfect copy: it does not know about
+//! side-by-side assemblies, delay-loaded imports, or the application's own directory and `PATH`,
+//! which depend on the application.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use object::{
+    pe,
+    read::pe::{ImageNtHeaders, PeFile},
+    FileKind, LittleEndian,
+};
+
+use crate::{
+    arch_detect::{binary_architecture, Architecture},
+    library::ResolvedDependency,
+    sysroot::Sysroot,
+};
+
+/// Prefixes of API sets: virtual DLL names the loader maps to whichever system DLL implements
+/// them on that version of Windows
+const API_SET_PREFIXES: [&str; 2] = ["api-ms-win-", "ext-ms-"];
+
+/// DLLs that come with every Windows installation, so they count as found even without a
+/// system directory to look in, as when checking a Windows runtime on another OS
+const CORE_SYSTEM_DLLS: [&str; 46] = [
+    "advapi32.dll",
+    "avrt.dll",
+    "bcrypt.dll",
+    "cfgmgr32.dll",
+    "combase.dll",
+    "comctl32.dll",
+    "comdlg32.dll",
+    "crypt32.dll",
+    "d3d11.dll",
+    "d3d12.dll",
+    "d3d9.dll",
+    "d3dcompiler_47.dll",
+    "dbghelp.dll",
+    "dwmapi.dll",
+    "dxgi.dll",
+    "gdi32.dll",
+    "hid.dll",
+    "imm32.dll",
+    "iphlpapi.dll",
+    "kernel32.dll",
+    "kernelbase.dll",
+    "mfplat.dll",
+    "msvcrt.dll",
+    "ncrypt.dll",
+    "ntdll.dll",
+    "ole32.dll",
+    "oleaut32.dll",
+    "opengl32.dll",
+    "powrprof.dll",
+    "psapi.dll",
+    "rpcrt4.dll",
+    "secur32.dll",
+    "setupapi.dll",
+    "shcore.dll",
+    "shell32.dll",
+    "shlwapi.dll",
+    "ucrtbase.dll",
+    "user32.dll",
+    "userenv.dll",
+    "uxtheme.dll",
+    "version.dll",
+    "winhttp.dll",
+    "winmm.dll",
+    "winusb.dll",
+    "wintrust.dll",
+    "ws2_32.dll",
+];
+
+/// DLLs of the Visual C++ runtime, each with optional `_suffix` variants like `msvcp140_1.dll`.
+/// Libraries built with MSVC need them unless linked statically, and they are not part of
+/// Windows: they come from the Visual C++ Redistributable, or are shipped next to the library.
+const VC_RUNTIME_DLLS: [&str; 5] = [
+    "concrt140",
+    "msvcp140",
+    "vccorlib140",
+    "vcomp140",
+    "vcruntime140",
+];
+
+fn is_api_set(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    API_SET_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Is this DLL part of the (release) Visual C++ runtime?
+pub(crate) fn is_vc_runtime(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.strip_suffix(".dll").is_some_and(|stem| {
+        VC_RUNTIME_DLLS.iter().any(|dll| {
+            stem.strip_prefix(dll)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'))
+        })
+    })
+}
+
+/// Is this DLL provided by Windows itself, wherever it is found (or not)?
+fn is_provided_by_system(name: &str) -> bool {
+    is_api_set(name) || CORE_SYSTEM_DLLS.contains(&name.to_ascii_lowercase().as_str())
+}
+
+/// The names of the DLLs a PE file imports, in order
+fn imported_dlls<Pe: ImageNtHeaders>(data: &[u8]) -> Option<Vec<String>> {
+    let file = PeFile::<Pe>::parse(data).ok()?;
+    let mut names = vec![];
+    if let Some(table) = file.import_table().ok()? {
+        let mut descriptors = table.descriptors().ok()?;
+        while let Some(descriptor) = descriptors.next().ok()? {
+            let name = table.name(descriptor.name.get(LittleEndian)).ok()?;
+            names.push(String::from_utf8_lossy(name).into_owned());
+        }
+    }
+    Some(names)
+}
+
+/// Parse the imports of a PE file: `None` if it is not one we can read.
+fn parse_imports(data: &[u8]) -> Option<Vec<String>> {
+    match FileKind::parse(data).ok()? {
+        FileKind::Pe32 => imported_dlls::<pe::ImageNtHeaders32>(data),
+        FileKind::Pe64 => imported_dlls::<pe::ImageNtHeaders64>(data),
+        _ => None,
+    }
+}
+
+/// Find a file in a directory, ignoring case as Windows does, even on other systems
+fn find_in_dir(dir: &Path, name: &str) -> Option<PathBuf> {
+    let candidate = dir.join(name);
+    if candidate.is_file() {
+        return Some(candidate);
+    }
+    if cfg!(windows) {
+        return None;
+    }
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .eq_ignore_ascii_case(name)
+        })
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+}
+
+/// Searches for DLLs in a Windows system.
+#[derive(Debug, Clone)]
+pub(crate) struct DllSearch {
+    /// For 64-bit libraries: the system directory, then the Windows directory
+    dirs_64: Vec<PathBuf>,
+    /// For 32-bit libraries: the system directory, then the Windows directory
+    dirs_32: Vec<PathBuf>,
+}
+
+impl DllSearch {
+    /// Set up to search the system in `sysroot`, which has no system directories unless it is
+    /// (or contains) a Windows installation.
+    pub(crate) fn new(sysroot: &Sysroot) -> Self {
+        #[cfg(windows)]
+        if sysroot.is_host() {
+            let (system_64, system_32) = crate::windows::system_dll_dirs();
+            return Self::with_system_dirs(system_64, system_32);
+        }
+        let windows = sysroot.map(Path::new("/Windows"));
+        let (system32, syswow64) = (windows.join("System32"), windows.join("SysWOW64"));
+        if syswow64.is_dir() {
+            Self::with_system_dirs(Some(system32), Some(syswow64))
+        } else {
+            // 32-bit Windows, or no Windows at all
+            Self::with_system_dirs(None, Some(system32).filter(|d| d.is_dir()))
+        }
+    }
+
+    fn with_system_dirs(system_64: Option<PathBuf>, system_32: Option<PathBuf>) -> Self {
+        let dirs = |system: Option<PathBuf>| -> Vec<PathBuf> {
+            system
+                .into_iter()
+                .flat_map(|dir| {
+                    let windows = dir.parent().map(Path::to_owned);
+                    std::iter::once(dir).chain(windows)
+                })
+                .collect()
+        };
+        Self {
+            dirs_64: dirs(system_64),
+            dirs_32: dirs(system_32),
+        }
+    }
+
+    /// Find a DLL for a library of the given architecture in `library_dir`, the directory of
+    /// the runtime library (which the loader searches first when loading it by full path), then
+    /// the system directories.
+    pub(crate) fn find(
+        &self,
+        name: &str,
+        architecture: Architecture,
+        library_dir: &Path,
+    ) -> Option<PathBuf> {
+        let system_dirs = if architecture.is_64() {
+            &self.dirs_64
+        } else {
+            &self.dirs_32
+        };
+        std::iter::once(library_dir)
+            .chain(system_dirs.iter().map(PathBuf::as_path))
+            .find_map(|dir| find_in_dir(dir, name))
+    }
+}
+
+/// Find all the DLLs a PE library imports, along with those the DLLs next to it import, the
+/// way the Windows loader would.
+///
+/// API sets are not looked for, since they are not files. Returns `None` if the library is not
+/// a PE file we can read.
+pub(crate) fn resolve_dll_dependencies(
+    library_path: &Path,
+    search: &DllSearch,
+) -> Option<Vec<ResolvedDependency>> {
+    let data = fs::read(library_path).ok()?;
+    let architecture = binary_architecture(&data)?;
+    parse_imports(&data)?;
+    let library_dir = library_path.parent()?;
+    let mut resolved = vec![];
+    // The loader only loads a given name once, wherever it is needed
+    let mut seen_names: HashSet<String> = HashSet::new();
+    let mut queue = VecDeque::from([library_path.to_owned()]);
+
+    while let Some(object_path) = queue.pop_front() {
+        let imports = fs::read(&object_path)
+            .ok()
+            .and_then(|data| parse_imports(&data))
+            .unwrap_or_default();
+        for name in imports {
+            if !seen_names.insert(name.to_ascii_lowercase()) {
+                continue;
+            }
+            let path = if is_api_set(&name) {
+                None
+            } else {
+                search.find(&name, architecture, library_dir)
+            };
+            // DLLs shipped with the runtime have imports of their own to check, but system
+            // ones can be trusted to work
+            if let Some(path) = path.as_ref().filter(|p| p.parent() == Some(library_dir)) {
+                queue.push_back(path.clone());
+            }
+            resolved.push(ResolvedDependency {
+                provided_by_system: is_provided_by_system(&name),
+                name,
+                needed_by: object_path.clone(),
+                path,
+            });
+        }
+    }
+    Some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_binaries::pe_dll;
+
+    fn write_file(path: &Path, data: &[u8]) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    fn dependency<'a>(resolved: &'a [ResolvedDependency], name: &str) -> &'a ResolvedDependency {
+        resolved.iter().find(|dep| dep.name == name).unwrap()
+    }
+
+    #[test]
+    fn imports() {
+        for is_64 in [false, true] {
+            let data = pe_dll(is_64, &["KERNEL32.dll", "helper.dll"], &[]);
+            assert_eq!(
+                binary_architecture(&data),
+                Some(if is_64 {
+                    Architecture::X86_64
+                } else {
+                    Architecture::X86
+                })
+            );
+            assert_eq!(
+                parse_imports(&data),
+                Some(vec!["KERNEL32.dll".to_owned(), "helper.dll".to_owned()])
+            );
+        }
+        assert_eq!(parse_imports(b"MZ not really"), None);
+    }
+
+    #[test]
+    fn dll_kinds() {
+        assert!(is_api_set("api-ms-win-crt-runtime-l1-1-0.dll"));
+        assert!(is_api_set("API-MS-WIN-CORE-SYNCH-L1-2-0.DLL"));
+        assert!(is_api_set("ext-ms-win-ntuser-window-l1-1-0.dll"));
+        assert!(!is_api_set("kernel32.dll"));
+        assert!(is_provided_by_system("Kernel32.DLL"));
+        assert!(!is_provided_by_system("helper.dll"));
+
+        for name in [
+            "vcruntime140.dll",
+            "VCRUNTIME140_1.dll",
+            "msvcp140.dll",
+            "msvcp140_atomic_wait.dll",
+            "concrt140.dll",
+        ] {
+            assert!(is_vc_runtime(name), "{}", name);
+            assert!(!is_provided_by_system(name), "{}", name);
+        }
+        // Debug builds, Windows' own old C runtime, and not DLLs at all
+        for name in [
+            "vcruntime140d.dll",
+            "msvcp140d.dll",
+            "msvcrt.dll",
+            "msvcp140",
+        ] {
+            assert!(!is_vc_runtime(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn resolve() {
+        let root = tempfile::tempdir().unwrap();
+        let runtime_dir = root.path().join("Runtime");
+        let library = runtime_dir.join("runtime.dll");
+        write_file(
+            &library,
+            &pe_dll(
+                true,
+                &[
+                    "KERNEL32.dll",
+                    "api-ms-win-crt-runtime-l1-1-0.dll",
+                    "helper.dll",
+                    "VCRUNTIME140.dll",
+                    "missing.dll",
+                ],
+                &[],
+            ),
+        );
+        write_file(
+            &runtime_dir.join("helper.dll"),
+            &pe_dll(true, &["kernel32.dll", "other.dll", "MSVCP140.dll"], &[]),
+        );
+        // Found ignoring case, and only in the system directory for the right architecture
+        let windows = root.path().join("Windows");
+        let other_64 = windows.join("System32").join("Other.DLL");
+        write_file(&other_64, &pe_dll(true, &[], &[]));
+        write_file(
+            &windows.join("SysWOW64").join("other.dll"),
+            &pe_dll(false, &[], &[]),
+        );
+        write_file(
+            &windows.join("System32").join("msvcp140.dll"),
+            &pe_dll(true, &[], &[]),
+        );
+
+        let search = DllSearch::new(&Sysroot::new(root.path()));
+        let resolved = resolve_dll_dependencies(&library, &search).unwrap();
+        // Each name once, in the order the loader would come to them
+        let names: Vec<&str> = resolved.iter().map(|dep| dep.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "KERNEL32.dll",
+                "api-ms-win-crt-runtime-l1-1-0.dll",
+                "helper.dll",
+                "VCRUNTIME140.dll",
+                "missing.dll",
+                "other.dll",
+                "MSVCP140.dll",
+            ]
+        );
+
+        // Part of Windows, even with no DLL to be found
+        for name in ["KERNEL32.dll", "api-ms-win-crt-runtime-l1-1-0.dll"] {
+            let dep = dependency(&resolved, name);
+            assert!(dep.provided_by_system && !dep.is_missing(), "{}", name);
+            assert_eq!(dep.path, None);
+        }
+        assert_eq!(
+            dependency(&resolved, "helper.dll").path,
+            Some(runtime_dir.join("helper.dll"))
+        );
+        let other = dependency(&resolved, "other.dll");
+        assert_eq!(other.path, Some(other_64));
+        assert_eq!(other.needed_by, runtime_dir.join("helper.dll"));
+        assert!(!dependency(&resolved, "MSVCP140.dll").is_missing());
+
+        let missing: Vec<&str> = resolved
+            .iter()
+            .filter(|dep| dep.is_missing())
+            .map(|dep| dep.name.as_str())
+            .collect();
+        assert_eq!(missing, ["VCRUNTIME140.dll", "missing.dll"]);
+        assert!(dependency(&resolved, "VCRUNTIME140.dll").is_vc_runtime());
+    }
+
+    #[test]
+    fn resolve_32_bit() {
+        let root = tempfile::tempdir().unwrap();
+        let library = root.path().join("Runtime").join("runtime32.dll");
+        write_file(&library, &pe_dll(false, &["other.dll"], &[]));
+        let windows = root.path().join("Windows");
+        write_file(
+            &windows.join("System32").join("other.dll"),
+            &pe_dll(true, &[], &[]),
+        );
+        let other_32 = windows.join("SysWOW64").join("other.dll");
+        write_file(&other_32, &pe_dll(false, &[], &[]));
+
+        let search = DllSearch::new(&Sysroot::new(root.path()));
+        let resolved = resolve_dll_dependencies(&library, &search).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].path, Some(other_32));
+    }
+
+    #[test]
+    fn not_a_dll() {
+        let root = tempfile::tempdir().unwrap();
+        let library = root.path().join("runtime.dll");
+        write_file(&library, b"MZ not really");
+        let search = DllSearch::new(&Sysroot::new(root.path()));
+        assert_eq!(resolve_dll_dependencies(&library, &search), None);
+    }
+}
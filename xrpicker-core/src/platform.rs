@@ -3,13 +3,58 @@
 
 use std::path::{Path, PathBuf};
 
-use crate::{ActiveState, Error, ManifestError};
+use crate::{
+    arch_detect::{Architecture, LibraryVersion},
+    diagnostics::Diagnosis,
+    history::ActivationRecord,
+    known_runtimes::KnownRuntime,
+    layer::ApiLayer,
+    library::{ResolvedDependency, RuntimeProblem},
+    ActivationStrategy, ActiveState, Error, ManifestError,
+};
 
 /// Trait for platform-specific interaction with a runtime.
 pub trait PlatformRuntime {
     /// Attempt to make this runtime active.
     fn make_active(&self) -> Result<(), Error>;
 
+    /// Attempt to make this runtime active, in the given way.
+    fn make_active_with_strategy(&self, strategy: ActivationStrategy) -> Result<(), Error> {
+        if strategy != ActivationStrategy::default() {
+            return Err(unsupported_strategy(strategy));
+        }
+        self.make_active()
+    }
+
+    /// Attempt to make this runtime active for every user of the system, in the given way, which
+    /// usually needs administrator privileges. Checks first that every user could use it.
+    ///
+    /// With `dry_run`, only checks, changing nothing. Returns a description of each change made,
+    /// or that would be made.
+    fn make_active_system_wide(
+        &self,
+        strategy: ActivationStrategy,
+        _dry_run: bool,
+    ) -> Result<Vec<String>, Error> {
+        Err(Error::SetActiveError(format!(
+            "Cannot make a runtime active for all users with the '{}' strategy on this platform",
+            strategy.name()
+        )))
+    }
+
+    /// Attempt to make this runtime active only for applications of the given architecture,
+    /// like 32-bit games on a 64-bit system.
+    fn make_active_for_architecture(
+        &self,
+        architecture: Architecture,
+        _strategy: ActivationStrategy,
+    ) -> Result<(), Error> {
+        Err(Error::SetActiveError(format!(
+            "Cannot set an active runtime for {} alone on this platform",
+            architecture
+        )))
+    }
+
     /// Get a name for the runtime, preferably the self-declared one.
     ///
     /// Not promised to be unique, though!
@@ -20,6 +65,47 @@ pub trait PlatformRuntime {
 
     /// Describe this specific instance of a runtime: usually using the manifest(s) and library
     fn describe(&self) -> String;
+
+    /// Inspect the runtime library (or libraries) for problems that would keep applications
+    /// from using this runtime, including missing dependencies. Empty if no problems were found.
+    fn get_problems(&self) -> Vec<RuntimeProblem>;
+
+    /// Get the architecture of each runtime library we could identify.
+    fn get_architectures(&self) -> Vec<Architecture>;
+
+    /// Get the version information in the runtime library (or the first of them that has any),
+    /// like its file version and product name.
+    fn get_version(&self) -> Option<LibraryVersion>;
+
+    /// Get what we know about this runtime, like its vendor and homepage, if we recognize its
+    /// library.
+    fn get_known_runtime(&self) -> Option<&KnownRuntime>;
+
+    /// Get the shared libraries the runtime library (or libraries) need, directly or indirectly,
+    /// and where the dynamic linker (or Windows loader) would find them. Only available for ELF
+    /// and PE libraries. They are looked for the first time they (or the problems) are needed,
+    /// reading the whole tree of libraries.
+    fn get_dependencies(&self) -> Vec<ResolvedDependency>;
+
+    /// Is this runtime enabled in the platform's list of available runtimes?
+    /// `None` if it is not listed there, or the platform has no such list.
+    fn get_enabled(&self) -> Option<bool> {
+        None
+    }
+}
+
+fn no_available_runtimes_list() -> Error {
+    Error::AvailableRuntimesError(
+        "There is no list of available runtimes on this platform".to_owned(),
+    )
+}
+
+/// The error for an activation strategy a platform does not offer
+pub(crate) fn unsupported_strategy(strategy: ActivationStrategy) -> Error {
+    Error::SetActiveError(format!(
+        "The '{}' activation strategy is not supported on this platform",
+        strategy.name()
+    ))
 }
 
 /// Trait abstracting over the underlying system/platform type.
@@ -57,4 +143,92 @@ pub trait Platform {
         runtime: &Self::PlatformRuntimeType,
         active_data: &Self::PlatformActiveData,
     ) -> ActiveState;
+
+    /// Walk the configuration the way the OpenXR loader does, checking each step,
+    /// to explain why the loader might not find a (working) runtime.
+    fn diagnose(&self) -> Diagnosis;
+
+    /// The ways this platform can make a runtime active, the default first.
+    fn activation_strategies(&self) -> Vec<ActivationStrategy> {
+        vec![ActivationStrategy::default()]
+    }
+
+    /// Enable or disable a runtime in the list of available runtimes that installers register in.
+    /// A disabled runtime can still be made active.
+    fn set_runtime_enabled(
+        &self,
+        _runtime: &Self::PlatformRuntimeType,
+        _enabled: bool,
+    ) -> Result<(), Error> {
+        Err(no_available_runtimes_list())
+    }
+
+    /// Add a runtime manifest to the list of available runtimes, enabled.
+    fn register_runtime(&self, _manifest: &Path) -> Result<(), Error> {
+        Err(no_available_runtimes_list())
+    }
+
+    /// Remove a runtime from the list of available runtimes.
+    fn unregister_runtime(&self, _runtime: &Self::PlatformRuntimeType) -> Result<(), Error> {
+        Err(no_available_runtimes_list())
+    }
+
+    /// Enumerate all implicit and explicit API layers we can find, including disabled implicit ones.
+    fn find_api_layers(&self) -> Result<(Vec<ApiLayer>, Vec<ManifestError>), Error>;
+
+    /// Enable or disable an implicit API layer for all applications.
+    ///
+    /// Explicit layers are only loaded on request, so they cannot be enabled or disabled.
+    fn set_api_layer_enabled(&self, layer: &ApiLayer, enabled: bool) -> Result<(), Error>;
+
+    /// Is there a per-user active runtime setting that overrides the system-wide one?
+    fn has_user_active_runtime(&self, _active_data: &Self::PlatformActiveData) -> bool {
+        false
+    }
+
+    /// Remove the per-user active runtime setting (backing it up), so the system-wide one
+    /// takes effect again.
+    ///
+    /// Returns the active runtime file that now takes effect for applications like this one,
+    /// and the manifest it selects, if any.
+    fn clear_user_active_runtime(&self) -> Result<Option<(PathBuf, PathBuf)>, Error> {
+        Err(Error::SetActiveError(
+            "There is no per-user active runtime setting on this platform".to_owned(),
+        ))
+    }
+
+    /// Get the changes we have made to the active runtime setting, oldest first.
+    fn get_activation_history(&self) -> Result<Vec<ActivationRecord>, Error> {
+        Ok(vec![])
+    }
+
+    /// Undo the most recent change to the active runtime setting.
+    ///
+    /// This is itself recorded as a change, so undoing twice gets back where we started.
+    fn undo_last_activation(&self) -> Result<(), Error> {
+        Err(Error::HistoryError(
+            "Activation history is not supported on this platform".to_owned(),
+        ))
+    }
+
+    /// Set an active runtime file back to what the change at `index` in the history set it to.
+    fn restore_activation(&self, _index: usize) -> Result<(), Error> {
+        Err(Error::HistoryError(
+            "Activation history is not supported on this platform".to_owned(),
+        ))
+    }
+
+    /// Forget all but the `keep` most recent changes, and remove the backups of active runtime
+    /// files that only the forgotten changes needed. With `remove_unrecorded`, also remove
+    /// backups that no change in the history needs, like those older versions saved without
+    /// recording them. Returns how many backups were removed.
+    fn clean_activation_history(
+        &self,
+        _keep: usize,
+        _remove_unrecorded: bool,
+    ) -> Result<usize, Error> {
+        Err(Error::HistoryError(
+            "Activation history is not supported on this platform".to_owned(),
+        ))
+    }
 }
@@ -0,0 +1,863 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! The parts of the Windows registry the OpenXR loader and runtime installers use, behind an
+//! interface, so the logic does not need Windows (or a real registry) to run.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+
+use crate::{layer::ApiLayerKind, Error, OPENXR, OPENXR_MAJOR_VERSION};
+
+/// Key under the OpenXR key listing the installed runtimes: the value names are manifest paths,
+/// and a DWORD value of 0 means enabled.
+pub const AVAILABLE_RUNTIMES: &str = "AvailableRuntimes";
+
+/// String value in the OpenXR key with the path to the active runtime manifest
+pub const ACTIVE_RUNTIME: &str = "ActiveRuntime";
+
+/// Key under the OpenXR key with a subkey per kind of API layer, each listing layer manifests
+/// like [`AVAILABLE_RUNTIMES`] lists runtime manifests
+pub const API_LAYERS: &str = "ApiLayers";
+
+/// The registry hives we use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Hive {
+    LocalMachine,
+    CurrentUser,
+}
+
+/// Which view of the registry: on 64-bit Windows, 32-bit applications see their own copy of
+/// our keys (under `WOW6432Node`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RegistryView {
+    Bits64,
+    Bits32,
+}
+
+/// The registry operations we need
+pub trait Registry {
+    /// The views that exist: a 32-bit Windows has no 64-bit view.
+    fn views(&self) -> Vec<RegistryView>;
+
+    /// The names and values of all the DWORD values in a key, which has none if it does not exist.
+    fn dword_values(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+    ) -> io::Result<Vec<(String, u32)>>;
+
+    /// A string value, or `None` if it (or its key) does not exist.
+    fn string_value(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+    ) -> io::Result<Option<String>>;
+
+    /// Set a string value, creating the key if needed.
+    fn set_string(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+        value: &str,
+    ) -> io::Result<()>;
+
+    /// Set a DWORD value, creating the key if needed.
+    fn set_dword(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+        value: u32,
+    ) -> io::Result<()>;
+
+    /// Remove a value, which must exist.
+    fn delete_value(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+    ) -> io::Result<()>;
+}
+
+/// Where a key is: which hive, which view, and the path within those
+type KeyLocation = (Hive, RegistryView, PathBuf);
+
+/// The kinds of values we use
+#[derive(Debug, Clone)]
+enum Value {
+    Dword(u32),
+    String(String),
+}
+
+/// A registry kept in memory, for trying out registry logic anywhere
+#[derive(Debug, Clone)]
+pub struct MemoryRegistry {
+    views: Vec<RegistryView>,
+    values: RefCell<BTreeMap<KeyLocation, BTreeMap<String, Value>>>,
+}
+
+impl Default for MemoryRegistry {
+    /// An empty registry, like on 64-bit Windows
+    fn default() -> Self {
+        Self::with_views(vec![RegistryView::Bits64, RegistryView::Bits32])
+    }
+}
+
+impl MemoryRegistry {
+    /// An empty registry with only the given views, like `[RegistryView::Bits32]` for 32-bit Windows
+    pub fn with_views(views: Vec<RegistryView>) -> Self {
+        Self {
+            views,
+            values: RefCell::default(),
+        }
+    }
+
+    fn check_view(&self, view: RegistryView) -> io::Result<()> {
+        if self.views.contains(&view) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("There is no {:?} registry view", view),
+            ))
+        }
+    }
+
+    fn set_value(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+        value: Value,
+    ) -> io::Result<()> {
+        self.check_view(view)?;
+        self.values
+            .borrow_mut()
+            .entry((hive, view, key.to_owned()))
+            .or_default()
+            .insert(name.to_owned(), value);
+        Ok(())
+    }
+}
+
+impl Registry for MemoryRegistry {
+    fn views(&self) -> Vec<RegistryView> {
+        self.views.clone()
+    }
+
+    fn dword_values(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+    ) -> io::Result<Vec<(String, u32)>> {
+        self.check_view(view)?;
+        Ok(self
+            .values
+            .borrow()
+            .get(&(hive, view, key.to_owned()))
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|(k, v)| match v {
+                        Value::Dword(v) => Some((k.clone(), *v)),
+                        Value::String(_) => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn string_value(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+    ) -> io::Result<Option<String>> {
+        self.check_view(view)?;
+        Ok(self
+            .values
+            .borrow()
+            .get(&(hive, view, key.to_owned()))
+            .and_then(|values| match values.get(name) {
+                Some(Value::String(v)) => Some(v.clone()),
+                _ => None,
+            }))
+    }
+
+    fn set_string(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+        value: &str,
+    ) -> io::Result<()> {
+        self.set_value(hive, view, key, name, Value::String(value.to_owned()))
+    }
+
+    fn set_dword(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+        value: u32,
+    ) -> io::Result<()> {
+        self.set_value(hive, view, key, name, Value::Dword(value))
+    }
+
+    fn delete_value(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+    ) -> io::Result<()> {
+        self.check_view(view)?;
+        self.values
+            .borrow_mut()
+            .get_mut(&(hive, view, key.to_owned()))
+            .and_then(|values| values.remove(name))
+            .map(|_| ())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No such registry value"))
+    }
+}
+
+/// The OpenXR key, relative to a hive
+pub fn make_prefix_key() -> PathBuf {
+    Path::new("Software")
+        .join("Khronos")
+        .join(OPENXR)
+        .join(OPENXR_MAJOR_VERSION.to_string())
+}
+
+fn available_runtimes_key() -> PathBuf {
+    make_prefix_key().join(AVAILABLE_RUNTIMES)
+}
+
+/// The runtime manifests listed as available in a view, with whether each is enabled
+pub fn available_runtimes(registry: &dyn Registry, view: RegistryView) -> Vec<(PathBuf, bool)> {
+    registry
+        .dword_values(Hive::LocalMachine, view, &available_runtimes_key())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| (PathBuf::from(name), value == 0))
+        .collect()
+}
+
+/// The active runtime manifest in a view, if any
+pub fn active_runtime(registry: &dyn Registry, view: RegistryView) -> Option<PathBuf> {
+    registry
+        .string_value(Hive::LocalMachine, view, &make_prefix_key(), ACTIVE_RUNTIME)
+        .ok()
+        .flatten()
+        .map(PathBuf::from)
+}
+
+/// Make a runtime manifest the active one in a view.
+pub fn set_active_runtime(
+    registry: &dyn Registry,
+    view: RegistryView,
+    manifest: &Path,
+) -> Result<(), Error> {
+    let value = manifest.to_str().ok_or_else(|| {
+        Error::SetActiveError(format!(
+            "The manifest path {} cannot be put in the registry",
+            manifest.display()
+        ))
+    })?;
+    registry.set_string(
+        Hive::LocalMachine,
+        view,
+        &make_prefix_key(),
+        ACTIVE_RUNTIME,
+        value,
+    )?;
+    Ok(())
+}
+
+/// The runtime manifests the registry knows of in a view, with whether each is enabled.
+///
+/// These are the ones listed as available, then the active one if it is not listed (with `None`
+/// for whether it is enabled), as when a runtime makes itself active without registering.
+/// Like Windows, this ignores case when comparing paths.
+pub fn known_runtimes(registry: &dyn Registry, view: RegistryView) -> Vec<(PathBuf, Option<bool>)> {
+    let mut runtimes: Vec<(PathBuf, Option<bool>)> = available_runtimes(registry, view)
+        .into_iter()
+        .map(|(path, enabled)| (path, Some(enabled)))
+        .collect();
+    if let Some(active) = active_runtime(registry, view) {
+        let active_name = active.to_string_lossy();
+        if !runtimes
+            .iter()
+            .any(|(path, _)| path.to_string_lossy().eq_ignore_ascii_case(&active_name))
+        {
+            runtimes.push((active, None));
+        }
+    }
+    runtimes
+}
+
+/// The views listing a runtime manifest as available
+fn views_listing(registry: &dyn Registry, manifest: &Path) -> Vec<RegistryView> {
+    registry
+        .views()
+        .into_iter()
+        .filter(|view| {
+            available_runtimes(registry, *view)
+                .iter()
+                .any(|(path, _)| path == manifest)
+        })
+        .collect()
+}
+
+fn value_name(manifest: &Path) -> Result<&str, Error> {
+    manifest.to_str().ok_or_else(|| {
+        Error::AvailableRuntimesError(format!(
+            "The manifest path {} cannot be put in the registry",
+            manifest.display()
+        ))
+    })
+}
+
+fn write_failed(manifest: &Path, e: io::Error) -> Error {
+    Error::AvailableRuntimesError(format!(
+        "Could not update {} in the registry (administrator access may be required): {}",
+        manifest.display(),
+        e
+    ))
+}
+
+/// Enable or disable runtime manifests in every view that lists them.
+///
+/// Fails if none of them is listed anywhere.
+pub fn set_runtimes_enabled(
+    registry: &dyn Registry,
+    manifests: &[&Path],
+    enabled: bool,
+) -> Result<(), Error> {
+    let value: u32 = if enabled { 0 } else { 1 };
+    let mut found = false;
+    for manifest in manifests {
+        for view in views_listing(registry, manifest) {
+            found = true;
+            registry
+                .set_dword(
+                    Hive::LocalMachine,
+                    view,
+                    &available_runtimes_key(),
+                    value_name(manifest)?,
+                    value,
+                )
+                .map_err(|e| write_failed(manifest, e))?;
+        }
+    }
+    if found {
+        Ok(())
+    } else {
+        Err(not_listed(manifests))
+    }
+}
+
+/// List a runtime manifest as available (and enabled) in the given views.
+pub fn register_runtime(
+    registry: &dyn Registry,
+    manifest: &Path,
+    views: &[RegistryView],
+) -> Result<(), Error> {
+    for view in views {
+        registry
+            .set_dword(
+                Hive::LocalMachine,
+                *view,
+                &available_runtimes_key(),
+                value_name(manifest)?,
+                0,
+            )
+            .map_err(|e| write_failed(manifest, e))?;
+    }
+    Ok(())
+}
+
+/// Remove runtime manifests from the list of available runtimes in every view.
+///
+/// Fails if none of them is listed anywhere.
+pub fn unregister_runtimes(registry: &dyn Registry, manifests: &[&Path]) -> Result<(), Error> {
+    let mut found = false;
+    for manifest in manifests {
+        for view in views_listing(registry, manifest) {
+            found = true;
+            registry
+                .delete_value(
+                    Hive::LocalMachine,
+                    view,
+                    &available_runtimes_key(),
+                    value_name(manifest)?,
+                )
+                .map_err(|e| write_failed(manifest, e))?;
+        }
+    }
+    if found {
+        Ok(())
+    } else {
+        Err(not_listed(manifests))
+    }
+}
+
+fn not_listed(manifests: &[&Path]) -> Error {
+    Error::AvailableRuntimesError(format!(
+        "{} is not listed in the registry",
+        manifests.iter().map(|p| p.display()).join(" or ")
+    ))
+}
+
+fn api_layers_key(kind: ApiLayerKind) -> PathBuf {
+    let kind_key = match kind {
+        ApiLayerKind::Implicit => "Implicit",
+        ApiLayerKind::Explicit => "Explicit",
+    };
+    make_prefix_key().join(API_LAYERS).join(kind_key)
+}
+
+/// Where the loader looks for API layers: every view of the machine-wide hive, then the
+/// per-user hive, which is not split by view.
+fn api_layer_locations(registry: &dyn Registry) -> Vec<(Hive, RegistryView)> {
+    let views = registry.views();
+    let user = views.first().map(|view| (Hive::CurrentUser, *view));
+    views
+        .into_iter()
+        .map(|view| (Hive::LocalMachine, view))
+        .chain(user)
+        .collect()
+}
+
+/// The API layer manifests of a kind listed anywhere, with whether each is enabled.
+///
+/// Like for runtimes, the value name is the manifest path, and a DWORD value of 0 means enabled.
+pub fn api_layers(registry: &dyn Registry, kind: ApiLayerKind) -> Vec<(PathBuf, bool)> {
+    let key = api_layers_key(kind);
+    api_layer_locations(registry)
+        .into_iter()
+        .flat_map(|(hive, view)| registry.dword_values(hive, view, &key).unwrap_or_default())
+        .map(|(name, value)| (PathBuf::from(name), value == 0))
+        .collect()
+}
+
+/// Enable or disable an implicit API layer everywhere it is listed, since the loader might look
+/// at any of those places.
+///
+/// Fails if it is not listed anywhere.
+pub fn set_api_layer_enabled(
+    registry: &dyn Registry,
+    manifest: &Path,
+    enabled: bool,
+) -> Result<(), Error> {
+    let key = api_layers_key(ApiLayerKind::Implicit);
+    let value: u32 = if enabled { 0 } else { 1 };
+    let mut found = false;
+    for (hive, view) in api_layer_locations(registry) {
+        let listed = registry
+            .dword_values(hive, view, &key)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|(name, _)| Path::new(name) == manifest);
+        let name = match listed {
+            Some((name, _)) => name,
+            None => continue,
+        };
+        found = true;
+        registry
+            .set_dword(hive, view, &key, &name, value)
+            .map_err(|e| {
+                Error::ApiLayerError(format!(
+                    "Could not update {} in the registry (administrator access may be required): {}",
+                    manifest.display(),
+                    e
+                ))
+            })?;
+    }
+    if found {
+        Ok(())
+    } else {
+        Err(Error::ApiLayerError(format!(
+            "{} is not listed in the registry",
+            manifest.display()
+        )))
+    }
+}
+
+/// Quote a string for a `.reg` file
+fn reg_file_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A `.reg` file that makes a runtime active, and lists it as available, on the 64-bit Windows
+/// machines it is imported on: for deploying with tools like group policy instead of running
+/// this one everywhere.
+///
+/// The manifest paths are as on those machines. The 64-bit manifest goes in the 64-bit view of
+/// the registry, and the 32-bit one in the 32-bit view (`WOW6432Node`). Either may be left out.
+/// The text uses Windows line endings, and regedit expects it saved as UTF-16 with a byte order
+/// mark if any path is not ASCII.
+pub fn active_runtime_reg_file(manifest_64: Option<&Path>, manifest_32: Option<&Path>) -> String {
+    let prefix: Vec<String> = make_prefix_key()
+        .iter()
+        .map(|c| c.to_string_lossy().into_owned())
+        .collect();
+    let mut lines = vec!["Windows Registry Editor Version 5.00".to_owned()];
+    let views = [(manifest_64, None), (manifest_32, Some("WOW6432Node"))];
+    for (manifest, view_node) in views
+        .into_iter()
+        .filter_map(|(manifest, node)| Some((manifest?, node)))
+    {
+        // The 32-bit view is the same key, just after the first component (`Software`)
+        let mut key = prefix.clone();
+        if let Some(node) = view_node {
+            key.insert(1, node.to_owned());
+        }
+        let key = format!("HKEY_LOCAL_MACHINE\\{}", key.join("\\"));
+        let manifest = reg_file_string(&manifest.to_string_lossy());
+        lines.extend([
+            String::new(),
+            format!("[{}]", key),
+            format!("{}={}", reg_file_string(ACTIVE_RUNTIME), manifest),
+            String::new(),
+            format!("[{}\\{}]", key, AVAILABLE_RUNTIMES),
+            format!("{}=dword:00000000", manifest),
+        ]);
+    }
+    lines.push(String::new());
+    lines.join("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list_layer(registry: &MemoryRegistry, hive: Hive, view: RegistryView, manifest: &str) {
+        registry
+            .set_dword(
+                hive,
+                view,
+                &api_layers_key(ApiLayerKind::Implicit),
+                manifest,
+                0,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn api_layers_from_every_location() {
+        let registry = MemoryRegistry::default();
+        list_layer(
+            &registry,
+            Hive::LocalMachine,
+            RegistryView::Bits64,
+            "C:\\a.json",
+        );
+        list_layer(
+            &registry,
+            Hive::LocalMachine,
+            RegistryView::Bits32,
+            "C:\\b.json",
+        );
+        list_layer(
+            &registry,
+            Hive::CurrentUser,
+            RegistryView::Bits64,
+            "C:\\c.json",
+        );
+        // Not a place the loader looks
+        list_layer(
+            &registry,
+            Hive::CurrentUser,
+            RegistryView::Bits32,
+            "C:\\d.json",
+        );
+
+        let layers = api_layers(&registry, ApiLayerKind::Implicit);
+        assert_eq!(
+            layers,
+            vec![
+                (PathBuf::from("C:\\a.json"), true),
+                (PathBuf::from("C:\\b.json"), true),
+                (PathBuf::from("C:\\c.json"), true),
+            ]
+        );
+        assert!(api_layers(&registry, ApiLayerKind::Explicit).is_empty());
+    }
+
+    #[test]
+    fn api_layer_enable_disable_round_trip() {
+        let registry = MemoryRegistry::default();
+        let manifest = Path::new("C:\\layer.json");
+        list_layer(
+            &registry,
+            Hive::LocalMachine,
+            RegistryView::Bits64,
+            "C:\\layer.json",
+        );
+        list_layer(
+            &registry,
+            Hive::LocalMachine,
+            RegistryView::Bits32,
+            "C:\\layer.json",
+        );
+        list_layer(
+            &registry,
+            Hive::LocalMachine,
+            RegistryView::Bits64,
+            "C:\\other.json",
+        );
+
+        set_api_layer_enabled(&registry, manifest, false).unwrap();
+        let key = api_layers_key(ApiLayerKind::Implicit);
+        for view in [RegistryView::Bits64, RegistryView::Bits32] {
+            let values = registry
+                .dword_values(Hive::LocalMachine, view, &key)
+                .unwrap();
+            assert!(values.contains(&("C:\\layer.json".to_owned(), 1)));
+        }
+        assert!(api_layers(&registry, ApiLayerKind::Implicit)
+            .contains(&(PathBuf::from("C:\\other.json"), true)));
+
+        set_api_layer_enabled(&registry, manifest, true).unwrap();
+        assert!(api_layers(&registry, ApiLayerKind::Implicit)
+            .iter()
+            .all(|(_, enabled)| *enabled));
+        // Nothing listed twice, or anywhere new
+        assert_eq!(api_layers(&registry, ApiLayerKind::Implicit).len(), 3);
+    }
+
+    #[test]
+    fn unlisted_api_layer_is_an_error() {
+        let registry = MemoryRegistry::default();
+        let result = set_api_layer_enabled(&registry, Path::new("C:\\missing.json"), false);
+        assert!(matches!(result, Err(Error::ApiLayerError(_))));
+        assert!(api_layers(&registry, ApiLayerKind::Implicit).is_empty());
+    }
+
+    fn list_runtime(registry: &MemoryRegistry, view: RegistryView, manifest: &str, value: u32) {
+        registry
+            .set_dword(
+                Hive::LocalMachine,
+                view,
+                &available_runtimes_key(),
+                manifest,
+                value,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn nonzero_dword_is_disabled() {
+        let registry = MemoryRegistry::default();
+        list_runtime(&registry, RegistryView::Bits64, "C:\\a.json", 0);
+        list_runtime(&registry, RegistryView::Bits64, "C:\\b.json", 1);
+        list_runtime(&registry, RegistryView::Bits64, "C:\\c.json", 42);
+        assert_eq!(
+            available_runtimes(&registry, RegistryView::Bits64),
+            vec![
+                (PathBuf::from("C:\\a.json"), true),
+                (PathBuf::from("C:\\b.json"), false),
+                (PathBuf::from("C:\\c.json"), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn runtime_enable_disable_round_trip() {
+        let registry = MemoryRegistry::default();
+        let manifest = Path::new("C:\\runtime.json");
+        register_runtime(
+            &registry,
+            manifest,
+            &[RegistryView::Bits64, RegistryView::Bits32],
+        )
+        .unwrap();
+
+        set_runtimes_enabled(&registry, &[manifest], false).unwrap();
+        for view in [RegistryView::Bits64, RegistryView::Bits32] {
+            assert_eq!(
+                available_runtimes(&registry, view),
+                vec![(manifest.to_owned(), false)]
+            );
+        }
+        set_runtimes_enabled(&registry, &[manifest], true).unwrap();
+        for view in [RegistryView::Bits64, RegistryView::Bits32] {
+            assert_eq!(
+                available_runtimes(&registry, view),
+                vec![(manifest.to_owned(), true)]
+            );
+        }
+
+        unregister_runtimes(&registry, &[manifest]).unwrap();
+        assert!(available_runtimes(&registry, RegistryView::Bits64).is_empty());
+        assert!(matches!(
+            set_runtimes_enabled(&registry, &[manifest], false),
+            Err(Error::AvailableRuntimesError(_))
+        ));
+    }
+
+    #[test]
+    fn views_are_separate() {
+        let registry = MemoryRegistry::default();
+        let manifest_64 = Path::new("C:\\runtime_64.json");
+        let manifest_32 = Path::new("C:\\runtime_32.json");
+        register_runtime(&registry, manifest_64, &[RegistryView::Bits64]).unwrap();
+        register_runtime(&registry, manifest_32, &[RegistryView::Bits32]).unwrap();
+        set_active_runtime(&registry, RegistryView::Bits32, manifest_32).unwrap();
+
+        assert_eq!(
+            available_runtimes(&registry, RegistryView::Bits64),
+            vec![(manifest_64.to_owned(), true)]
+        );
+        assert_eq!(
+            available_runtimes(&registry, RegistryView::Bits32),
+            vec![(manifest_32.to_owned(), true)]
+        );
+        assert_eq!(active_runtime(&registry, RegistryView::Bits64), None);
+        assert_eq!(
+            active_runtime(&registry, RegistryView::Bits32),
+            Some(manifest_32.to_owned())
+        );
+
+        // Disabling one leaves the other view alone
+        set_runtimes_enabled(&registry, &[manifest_32], false).unwrap();
+        assert_eq!(
+            available_runtimes(&registry, RegistryView::Bits64),
+            vec![(manifest_64.to_owned(), true)]
+        );
+    }
+
+    #[test]
+    fn no_64_bit_view_on_32_bit_windows() {
+        let registry = MemoryRegistry::with_views(vec![RegistryView::Bits32]);
+        let manifest = Path::new("C:\\runtime.json");
+        assert!(register_runtime(&registry, manifest, &[RegistryView::Bits64]).is_err());
+        assert!(available_runtimes(&registry, RegistryView::Bits64).is_empty());
+    }
+
+    #[test]
+    fn unregistered_active_runtime_is_known() {
+        let registry = MemoryRegistry::default();
+        let listed = Path::new("C:\\listed.json");
+        let unlisted = Path::new("C:\\unlisted.json");
+        list_runtime(&registry, RegistryView::Bits64, "C:\\listed.json", 1);
+        set_active_runtime(&registry, RegistryView::Bits64, unlisted).unwrap();
+        assert_eq!(
+            known_runtimes(&registry, RegistryView::Bits64),
+            vec![
+                (listed.to_owned(), Some(false)),
+                (unlisted.to_owned(), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn registered_active_runtime_is_known_once() {
+        let registry = MemoryRegistry::default();
+        let manifest = Path::new("C:\\runtime.json");
+        register_runtime(&registry, manifest, &[RegistryView::Bits64]).unwrap();
+        set_active_runtime(
+            &registry,
+            RegistryView::Bits64,
+            Path::new("c:\\RUNTIME.json"),
+        )
+        .unwrap();
+        assert_eq!(
+            known_runtimes(&registry, RegistryView::Bits64),
+            vec![(manifest.to_owned(), Some(true))]
+        );
+        assert!(known_runtimes(&registry, RegistryView::Bits32).is_empty());
+    }
+
+    #[test]
+    fn reg_file_for_64_bit_runtime() {
+        let contents = active_runtime_reg_file(Some(Path::new("C:\\Runtime\\runtime.json")), None);
+        assert_eq!(
+            contents,
+            [
+                "Windows Registry Editor Version 5.00",
+                "",
+                "[HKEY_LOCAL_MACHINE\\Software\\Khronos\\openxr\\1]",
+                "\"ActiveRuntime\"=\"C:\\\\Runtime\\\\runtime.json\"",
+                "",
+                "[HKEY_LOCAL_MACHINE\\Software\\Khronos\\openxr\\1\\AvailableRuntimes]",
+                "\"C:\\\\Runtime\\\\runtime.json\"=dword:00000000",
+                "",
+            ]
+            .join("\r\n")
+        );
+        assert!(!contents.contains("WOW6432Node"));
+    }
+
+    #[test]
+    fn reg_file_for_32_bit_runtime() {
+        let contents =
+            active_runtime_reg_file(None, Some(Path::new("C:\\Runtime\\runtime32.json")));
+        assert_eq!(
+            contents,
+            [
+                "Windows Registry Editor Version 5.00",
+                "",
+                "[HKEY_LOCAL_MACHINE\\Software\\WOW6432Node\\Khronos\\openxr\\1]",
+                "\"ActiveRuntime\"=\"C:\\\\Runtime\\\\runtime32.json\"",
+                "",
+                "[HKEY_LOCAL_MACHINE\\Software\\WOW6432Node\\Khronos\\openxr\\1\\AvailableRuntimes]",
+                "\"C:\\\\Runtime\\\\runtime32.json\"=dword:00000000",
+                "",
+            ]
+            .join("\r\n")
+        );
+    }
+
+    #[test]
+    fn reg_file_for_both_views() {
+        let contents = active_runtime_reg_file(
+            Some(Path::new("C:\\Runtime\\runtime64.json")),
+            Some(Path::new("C:\\Runtime\\runtime32.json")),
+        );
+        assert!(contents.starts_with("Windows Registry Editor Version 5.00\r\n"));
+        // Every line ends with CRLF, with no bare line feeds
+        assert!(contents.ends_with("\r\n"));
+        assert_eq!(
+            contents.matches('\n').count(),
+            contents.matches("\r\n").count()
+        );
+        let key_64 = contents
+            .find("[HKEY_LOCAL_MACHINE\\Software\\Khronos\\openxr\\1]\r\n\"ActiveRuntime\"=\"C:\\\\Runtime\\\\runtime64.json\"")
+            .unwrap();
+        let key_32 = contents
+            .find("[HKEY_LOCAL_MACHINE\\Software\\WOW6432Node\\Khronos\\openxr\\1]\r\n\"ActiveRuntime\"=\"C:\\\\Runtime\\\\runtime32.json\"")
+            .unwrap();
+        assert!(key_64 < key_32);
+    }
+
+    #[test]
+    fn reg_file_escapes_strings() {
+        let contents =
+            active_runtime_reg_file(Some(Path::new("C:\\Odd \"Runtime\"\\runtime.json")), None);
+        assert!(contents
+            .contains("\"ActiveRuntime\"=\"C:\\\\Odd \\\"Runtime\\\"\\\\runtime.json\"\r\n"));
+        assert!(contents
+            .contains("\r\n\"C:\\\\Odd \\\"Runtime\\\"\\\\runtime.json\"=dword:00000000\r\n"));
+    }
+}
@@ -2,11 +2,30 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use std::{
-    fs,
+    env, fs,
     path::{Path, PathBuf},
 };
 
-use crate::{manifest::GenericManifest, Error, RuntimeManifest};
+use crate::{
+    arch_detect::{Architecture, LibraryVersion},
+    known_runtimes::{KnownRuntime, KnownRuntimes},
+    ld_so::find_library_by_name,
+    library::{
+        inspect_runtime_library, resolve_library_dependencies, LibraryInspection,
+        ResolvedDependency, RuntimeProblem, NEGOTIATE_FUNCTION,
+    },
+    manifest::GenericManifest,
+    sysroot::Sysroot,
+    Error, RuntimeManifest, RUNTIME_JSON_ENV_VAR,
+};
+
+/// Get the runtime manifest path selected by the environment, if any, which takes precedence
+/// over the active runtime setting in the loader.
+pub(crate) fn environment_override_manifest() -> Option<PathBuf> {
+    env::var_os(RUNTIME_JSON_ENV_VAR)
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
 
 /// The path and parsed data of a runtime manifest.
 ///
@@ -15,6 +34,10 @@ use crate::{manifest::GenericManifest, Error, RuntimeManifest};
 pub(crate) struct BaseRuntime {
     manifest_path: PathBuf,
     manifest: RuntimeManifest,
+    /// From the version information of the library, once inspected
+    library_product_name: Option<String>,
+    /// What we know about this runtime, going by its library
+    known_runtime: Option<KnownRuntime>,
 }
 
 impl BaseRuntime {
@@ -28,17 +51,44 @@ impl BaseRuntime {
         if !manifest.is_file_format_version_ok() {
             return Err(Error::ManifestVersionMismatch);
         }
+        let known_runtime = KnownRuntimes::embedded()
+            .identify(manifest.library_path())
+            .cloned();
         Ok(BaseRuntime {
             manifest_path: manifest_path.to_owned(),
             manifest,
+            library_product_name: None,
+            known_runtime,
         })
     }
 
+    /// Identify the runtime using `known` rather than just the built-in known runtimes.
+    pub(crate) fn with_known_runtimes(self, known: &KnownRuntimes) -> Self {
+        Self {
+            known_runtime: known.identify(self.manifest.library_path()).cloned(),
+            ..self
+        }
+    }
+
+    /// Use the product name in the library's version information, if any, when the manifest
+    /// does not name the runtime.
+    pub(crate) fn with_library_version(self, version: Option<&LibraryVersion>) -> Self {
+        Self {
+            library_product_name: version.and_then(|v| v.product_name.clone()),
+            ..self
+        }
+    }
+
     /// Get the path to our manifest
     pub(crate) fn get_manifest_path(&self) -> &Path {
         &self.manifest_path
     }
 
+    /// Get what we know about this runtime, if we recognize it
+    pub(crate) fn get_known_runtime(&self) -> Option<&KnownRuntime> {
+        self.known_runtime.as_ref()
+    }
+
     /// Get a name for the runtime, preferably the self-declared one.
     ///
     /// Not promised to be unique, though!
@@ -48,15 +98,12 @@ impl BaseRuntime {
             return s.clone();
         }
 
-        // Heuristics go here, for manifests that lack the name
-        if self.manifest.library_path().contains("MixedRealityRuntime") {
-            return "Windows Mixed Reality".to_owned();
-        }
-        if self.manifest.library_path().contains("monado") {
-            return "Monado".to_owned();
+        // Then what we know about it, for manifests that lack the name
+        if let Some(known) = &self.known_runtime {
+            return known.name.clone();
         }
-        if self.manifest.library_path().contains("VarjoOpenXR") {
-            return "Varjo".to_owned();
+        if let Some(product_name) = &self.library_product_name {
+            return product_name.clone();
         }
 
         // Fallback to manifest path or library path
@@ -66,14 +113,73 @@ impl BaseRuntime {
             .to_owned()
     }
 
+    /// The name of the function the loader calls first, which may be renamed in the manifest
+    pub(crate) fn negotiate_function_name(&self) -> &str {
+        self.manifest
+            .runtime
+            .functions
+            .as_ref()
+            .and_then(|f| f.xr_negotiate_loader_runtime_interface.as_deref())
+            .unwrap_or(NEGOTIATE_FUNCTION)
+    }
+
+    /// Inspect the library this manifest points to, in the system in `sysroot`.
+    ///
+    /// Libraries found using the dynamic library search path are only checked on Linux.
+    /// The architecture is only checked against ours when inspecting the live system.
+    pub(crate) fn inspect_library_in(&self, sysroot: &Sysroot) -> LibraryInspection {
+        let library_path = self.resolve_library_path_in(sysroot);
+        if self.manifest.uses_search_path() && !library_path.is_absolute() {
+            if cfg!(unix) {
+                return LibraryInspection::with_problem(RuntimeProblem::LibraryNotInSearchPath(
+                    self.manifest.library_path().to_owned(),
+                ));
+            }
+            return LibraryInspection::default();
+        }
+        let host = Some(Architecture::host()).filter(|_| sysroot.is_host());
+        inspect_runtime_library(library_path, self.negotiate_function_name(), host)
+    }
+
+    /// Find the shared libraries the library this manifest points to needs, in the system in
+    /// `sysroot`: none if the library itself cannot be found.
+    pub(crate) fn resolve_dependencies_in(&self, sysroot: &Sysroot) -> Vec<ResolvedDependency> {
+        let library_path = self.resolve_library_path_in(sysroot);
+        if self.manifest.uses_search_path() && !library_path.is_absolute() {
+            return vec![];
+        }
+        resolve_library_dependencies(&library_path, sysroot)
+    }
+
     /// Get the fully resolved, canonical path to the library in this manifest/runtime, if possible
+    #[cfg(windows)]
     pub(crate) fn resolve_library_path(&self) -> PathBuf {
-        let notcanon = self
-            .manifest_path
-            .parent()
-            .expect("files always have parents")
-            .join(self.manifest.library_path());
-        notcanon.canonicalize().unwrap_or(notcanon)
+        self.resolve_library_path_in(&Sysroot::host())
+    }
+
+    /// Like `resolve_library_path()`, for a manifest that is part of the system in `sysroot`.
+    ///
+    /// On Linux, a library named without a path is looked up the way `dlopen` would.
+    /// Otherwise, or if it is not found, just the name is returned.
+    pub(crate) fn resolve_library_path_in(&self, sysroot: &Sysroot) -> PathBuf {
+        let library_path = Path::new(self.manifest.library_path());
+        if self.manifest.uses_search_path() {
+            let found = if cfg!(unix) {
+                find_library_by_name(self.manifest.library_path(), sysroot)
+            } else {
+                None
+            };
+            return found.unwrap_or_else(|| library_path.to_owned());
+        }
+        let notcanon = if library_path.has_root() {
+            sysroot.map(library_path)
+        } else {
+            self.manifest_path
+                .parent()
+                .expect("files always have parents")
+                .join(library_path)
+        };
+        sysroot.canonicalize(&notcanon).unwrap_or(notcanon)
     }
 }
 
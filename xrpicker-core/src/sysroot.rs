@@ -0,0 +1,223 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Map paths of the system being inspected to paths we can access, so we can work on an alternate
+//! root directory (a mounted disk image, chroot, or container rootfs) as well as the live system.
+
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Maximum number of symlinks to follow while resolving a single path, like `MAXSYMLINKS` on Linux
+const MAX_SYMLINKS: usize = 40;
+
+/// The root directory of the system being inspected.
+///
+/// Paths "in the target" are as the inspected system would see them, e.g. `/etc/xdg`.
+/// Paths "on the host" are where we can actually find them, e.g. `/mnt/image/etc/xdg`.
+/// For the live system, these are the same.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Sysroot {
+    root: PathBuf,
+}
+
+impl Default for Sysroot {
+    fn default() -> Self {
+        Self::host()
+    }
+}
+
+impl Sysroot {
+    /// The live system
+    pub(crate) fn host() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+        }
+    }
+
+    /// An alternate root directory on the host
+    pub(crate) fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+        }
+    }
+
+    /// Are we inspecting the live system?
+    pub(crate) fn is_host(&self) -> bool {
+        self.root == Path::new("/")
+    }
+
+    /// Turn a path in the target into a path on the host
+    pub(crate) fn map(&self, path: &Path) -> PathBuf {
+        if self.is_host() || !path.has_root() {
+            return path.to_owned();
+        }
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// Turn a path on the host into a path in the target, if it is inside our root
+    pub(crate) fn unmap(&self, path: &Path) -> Option<PathBuf> {
+        if self.is_host() {
+            return Some(path.to_owned());
+        }
+        path.strip_prefix(&self.root)
+            .ok()
+            .map(|relative| Path::new("/").join(relative))
+    }
+
+    /// Canonicalize a path on the host, interpreting absolute symlinks relative to our root,
+    /// the way they would be interpreted in the target.
+    pub(crate) fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        if self.is_host() {
+            return path.canonicalize();
+        }
+        let target_path = self.unmap(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not inside {}", path.display(), self.root.display()),
+            )
+        })?;
+
+        // Components of the target path still to process, in reverse order so we can pop them
+        let mut pending: Vec<PathBuf> = components_reversed(&target_path);
+        let mut resolved = PathBuf::from("/");
+        let mut symlinks_followed = 0;
+        while let Some(component) = pending.pop() {
+            match component.to_str() {
+                Some("..") => {
+                    resolved.pop();
+                    continue;
+                }
+                Some(".") => continue,
+                _ => {}
+            }
+            let candidate = resolved.join(&component);
+            let host_candidate = self.map(&candidate);
+            let metadata = host_candidate.symlink_metadata()?;
+            if metadata.is_symlink() {
+                symlinks_followed += 1;
+                if symlinks_followed > MAX_SYMLINKS {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("Too many levels of symbolic links at {}", path.display()),
+                    ));
+                }
+                let link_target = host_candidate.read_link()?;
+                if link_target.has_root() {
+                    resolved = PathBuf::from("/");
+                }
+                pending.extend(components_reversed(&link_target));
+            } else {
+                resolved = candidate;
+            }
+        }
+        Ok(self.map(&resolved))
+    }
+}
+
+/// The normal components of a path, along with `.` and `..`, in reverse order.
+fn components_reversed(path: &Path) -> Vec<PathBuf> {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(PathBuf::from(s)),
+            Component::ParentDir => Some(PathBuf::from("..")),
+            Component::CurDir => Some(PathBuf::from(".")),
+            Component::RootDir | Component::Prefix(_) => None,
+        })
+        .rev()
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{fs, os::unix::fs::symlink};
+
+    use super::*;
+
+    /// A fake root with a runtime manifest, and an active runtime file pointing at it with an
+    /// absolute symlink, as seen inside the root
+    fn fake_root() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let share = root.path().join("usr/share/openxr/1");
+        fs::create_dir_all(&share).unwrap();
+        fs::write(share.join("openxr_test.json"), "{}").unwrap();
+        let etc = root.path().join("etc/xdg/openxr/1");
+        fs::create_dir_all(&etc).unwrap();
+        symlink(
+            "/usr/share/openxr/1/openxr_test.json",
+            etc.join("active_runtime.json"),
+        )
+        .unwrap();
+        root
+    }
+
+    #[test]
+    fn map_and_unmap() {
+        let sysroot = Sysroot::new(Path::new("/mnt/image"));
+        assert!(!sysroot.is_host());
+        assert_eq!(
+            sysroot.map(Path::new("/etc/xdg")),
+            Path::new("/mnt/image/etc/xdg")
+        );
+        assert_eq!(sysroot.map(Path::new("relative")), Path::new("relative"));
+        assert_eq!(
+            sysroot.unmap(Path::new("/mnt/image/etc/xdg")),
+            Some(PathBuf::from("/etc/xdg"))
+        );
+        assert_eq!(sysroot.unmap(Path::new("/etc/xdg")), None);
+        assert_eq!(
+            Sysroot::host().map(Path::new("/etc/xdg")),
+            Path::new("/etc/xdg")
+        );
+    }
+
+    #[test]
+    fn absolute_symlink_resolves_inside_root() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        let sysroot = Sysroot::new(&root_path);
+        let active = root_path.join("etc/xdg/openxr/1/active_runtime.json");
+        assert_eq!(
+            sysroot.canonicalize(&active).unwrap(),
+            root_path.join("usr/share/openxr/1/openxr_test.json")
+        );
+    }
+
+    #[test]
+    fn resolution_never_leaves_root() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        let sysroot = Sysroot::new(&root_path);
+        let dir = root_path.join("etc/xdg/openxr/1");
+        // Like `/..` in the target, going up from the root stays at the root
+        symlink("../../../../../../..", dir.join("up")).unwrap();
+        assert_eq!(sysroot.canonicalize(&dir.join("up")).unwrap(), root_path);
+        symlink(
+            "../../../../../../../usr/share/openxr/1/openxr_test.json",
+            dir.join("up_and_down.json"),
+        )
+        .unwrap();
+        assert_eq!(
+            sysroot.canonicalize(&dir.join("up_and_down.json")).unwrap(),
+            root_path.join("usr/share/openxr/1/openxr_test.json")
+        );
+        // An absolute symlink to a file that only exists on the host is not followed there
+        let host_file = tempfile::NamedTempFile::new().unwrap();
+        symlink(host_file.path(), dir.join("host.json")).unwrap();
+        assert!(sysroot.canonicalize(&dir.join("host.json")).is_err());
+        // Paths outside the root are refused outright
+        assert!(sysroot.canonicalize(host_file.path()).is_err());
+    }
+
+    #[test]
+    fn symlink_loops_are_errors() {
+        let root = fake_root();
+        let root_path = root.path().canonicalize().unwrap();
+        let sysroot = Sysroot::new(&root_path);
+        let dir = root_path.join("etc/xdg/openxr/1");
+        symlink("/etc/xdg/openxr/1/b", dir.join("a")).unwrap();
+        symlink("/etc/xdg/openxr/1/a", dir.join("b")).unwrap();
+        assert!(sysroot.canonicalize(&dir.join("a")).is_err());
+    }
+}
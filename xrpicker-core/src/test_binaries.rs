@@ -0,0 +1,209 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Minimal shared libraries for tests, with just the parts we read.
+
+use object::{elf, pe};
+
+fn pad_to(data: &mut Vec<u8>, alignment: usize) {
+    data.resize((data.len() + alignment - 1) / alignment * alignment, 0);
+}
+
+/// A string table holding `strings`, and the offset of each in it
+fn string_table(strings: &[&str]) -> (Vec<u8>, Vec<u32>) {
+    let mut table = vec![0];
+    let offsets = strings
+        .iter()
+        .map(|s| {
+            let offset = table.len() as u32;
+            table.extend(s.as_bytes());
+            table.push(0);
+            offset
+        })
+        .collect();
+    (table, offsets)
+}
+
+/// A 64-bit x86 ELF shared library needing each of `needed`, and exporting a function named
+/// each of `exports`
+pub(crate) fn elf_library(needed: &[&str], exports: &[&str]) -> Vec<u8> {
+    let (dynstr, offsets) = string_table(&[needed, exports].concat());
+    let (needed_names, export_names) = offsets.split_at(needed.len());
+
+    let mut dynsym = vec![0u8; 24];
+    for name in export_names {
+        dynsym.extend(name.to_le_bytes());
+        dynsym.push((elf::STB_GLOBAL << 4) | elf::STT_FUNC);
+        dynsym.push(0);
+        // Defined in .text
+        dynsym.extend(4u16.to_le_bytes());
+        dynsym.extend([0; 16]);
+    }
+    let mut dynamic = vec![];
+    for name in needed_names {
+        dynamic.extend(u64::from(elf::DT_NEEDED).to_le_bytes());
+        dynamic.extend(u64::from(*name).to_le_bytes());
+    }
+    dynamic.extend([0; 16]);
+    let (shstrtab, section_names) =
+        string_table(&[".dynstr", ".dynsym", ".dynamic", ".text", ".shstrtab"]);
+
+    // Name, type, contents, link, and entry size of each section after the null one
+    let sections = [
+        (section_names[0], elf::SHT_STRTAB, dynstr, 0u32, 0u64),
+        (section_names[1], elf::SHT_DYNSYM, dynsym, 1, 24),
+        (section_names[2], elf::SHT_DYNAMIC, dynamic, 1, 16),
+        (section_names[3], elf::SHT_PROGBITS, vec![0xc3], 0, 0),
+        (section_names[4], elf::SHT_STRTAB, shstrtab, 0, 0),
+    ];
+    let mut data = vec![0u8; 64];
+    let mut headers = vec![0u8; 64];
+    for (name, section_type, contents, link, entry_size) in sections {
+        pad_to(&mut data, 8);
+        headers.extend(name.to_le_bytes());
+        headers.extend(section_type.to_le_bytes());
+        headers.extend([0; 16]);
+        headers.extend((data.len() as u64).to_le_bytes());
+        headers.extend((contents.len() as u64).to_le_bytes());
+        headers.extend(link.to_le_bytes());
+        headers.extend(0u32.to_le_bytes());
+        headers.extend(8u64.to_le_bytes());
+        headers.extend(entry_size.to_le_bytes());
+        data.extend(contents);
+    }
+    pad_to(&mut data, 8);
+    let section_headers_offset = data.len() as u64;
+    data.extend(headers);
+
+    let mut header = elf::ELFMAG.to_vec();
+    header.extend([elf::ELFCLASS64, elf::ELFDATA2LSB, elf::EV_CURRENT]);
+    header.resize(16, 0);
+    header.extend(elf::ET_DYN.to_le_bytes());
+    header.extend(elf::EM_X86_64.to_le_bytes());
+    header.extend(u32::from(elf::EV_CURRENT).to_le_bytes());
+    header.extend([0; 16]);
+    header.extend(section_headers_offset.to_le_bytes());
+    header.extend(0u32.to_le_bytes());
+    // Header sizes, no program headers, then 6 sections, the last of which has their names
+    for value in [64u16, 56, 0, 64, 6, 5] {
+        header.extend(value.to_le_bytes());
+    }
+    data[..64].copy_from_slice(&header);
+    data
+}
+
+/// File offset and address of the only section, which holds the imports and exports
+const SECTION_OFFSET: u32 = 0x200;
+const SECTION_ADDRESS: u32 = 0x1000;
+
+/// A minimal PE DLL importing (nothing from) each of `imports`, and exporting a function named
+/// each of `exports`
+pub(crate) fn pe_dll(is_64: bool, imports: &[&str], exports: &[&str]) -> Vec<u8> {
+    // Import descriptors, a null one to end them, then the names they point to
+    let mut section = vec![0u8; (imports.len() + 1) * 20];
+    for (i, import) in imports.iter().enumerate() {
+        let name_address = SECTION_ADDRESS + section.len() as u32;
+        section[i * 20 + 12..i * 20 + 16].copy_from_slice(&name_address.to_le_bytes());
+        section.extend(import.as_bytes());
+        section.push(0);
+    }
+    let imports_size = section.len() as u32;
+
+    // Export directory, then the tables of addresses, names, and ordinals it points to
+    pad_to(&mut section, 4);
+    let exports_start = section.len() as u32;
+    let count = exports.len() as u32;
+    let addresses = SECTION_ADDRESS + exports_start + 40;
+    let names = addresses + 4 * count;
+    let ordinals = names + 4 * count;
+    let mut name_address = ordinals + 2 * count;
+    // Characteristics, time stamp, version and name are left empty, and ordinals start at 1
+    section.extend([0; 16]);
+    section.extend(1u32.to_le_bytes());
+    for value in [count, count, addresses, names, ordinals] {
+        section.extend(value.to_le_bytes());
+    }
+    for _ in exports {
+        // All in the same place, outside of the export data, so they are not forwarders
+        section.extend(0x1800u32.to_le_bytes());
+    }
+    for export in exports {
+        section.extend(name_address.to_le_bytes());
+        name_address += export.len() as u32 + 1;
+    }
+    for ordinal in 0..exports.len() as u16 {
+        section.extend(ordinal.to_le_bytes());
+    }
+    for export in exports {
+        section.extend(export.as_bytes());
+        section.push(0);
+    }
+    let exports_size = section.len() as u32 - exports_start;
+
+    let mut data = vec![0u8; SECTION_OFFSET as usize];
+    data[..2].copy_from_slice(b"MZ");
+    data[0x3c..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+    let mut headers = b"PE\0\0".to_vec();
+    let (machine, magic, optional_size) = if is_64 {
+        (
+            pe::IMAGE_FILE_MACHINE_AMD64,
+            pe::IMAGE_NT_OPTIONAL_HDR64_MAGIC,
+            240u16,
+        )
+    } else {
+        (
+            pe::IMAGE_FILE_MACHINE_I386,
+            pe::IMAGE_NT_OPTIONAL_HDR32_MAGIC,
+            224u16,
+        )
+    };
+    // File header: one section, and an optional header with all 16 data directories
+    headers.extend(machine.to_le_bytes());
+    headers.extend(1u16.to_le_bytes());
+    headers.extend([0; 12]);
+    headers.extend(optional_size.to_le_bytes());
+    headers.extend((pe::IMAGE_FILE_EXECUTABLE_IMAGE | pe::IMAGE_FILE_DLL).to_le_bytes());
+    // Optional header, up to the image base
+    let optional_start = headers.len();
+    headers.extend(magic.to_le_bytes());
+    headers.extend([0; 22]);
+    if is_64 {
+        headers.extend(0x1_8000_0000u64.to_le_bytes());
+    } else {
+        headers.extend(0x1000_0000u32.to_le_bytes());
+    }
+    // Section and file alignment, versions, image and header sizes
+    headers.extend(0x1000u32.to_le_bytes());
+    headers.extend(0x200u32.to_le_bytes());
+    headers.extend([0; 16]);
+    headers.extend(0x2000u32.to_le_bytes());
+    headers.extend(SECTION_OFFSET.to_le_bytes());
+    // The rest, to the count of data directories, is stack and heap sizes and flags
+    headers.resize(optional_start + optional_size as usize - 16 * 8 - 4, 0);
+    headers.extend(16u32.to_le_bytes());
+    for directory in 0..16 {
+        let (offset, size) = match directory {
+            pe::IMAGE_DIRECTORY_ENTRY_EXPORT => (exports_start, exports_size),
+            pe::IMAGE_DIRECTORY_ENTRY_IMPORT => (0, imports_size),
+            _ => {
+                headers.extend([0; 8]);
+                continue;
+            }
+        };
+        headers.extend((SECTION_ADDRESS + offset).to_le_bytes());
+        headers.extend(size.to_le_bytes());
+    }
+    // Section header
+    pad_to(&mut section, 0x200);
+    headers.extend(b".rdata\0\0");
+    headers.extend((section.len() as u32).to_le_bytes());
+    headers.extend(SECTION_ADDRESS.to_le_bytes());
+    headers.extend((section.len() as u32).to_le_bytes());
+    headers.extend(SECTION_OFFSET.to_le_bytes());
+    headers.extend([0; 12]);
+    headers.extend((pe::IMAGE_SCN_CNT_INITIALIZED_DATA | pe::IMAGE_SCN_MEM_READ).to_le_bytes());
+    data[0x40..0x40 + headers.len()].copy_from_slice(&headers);
+
+    data.extend(section);
+    data
+}
@@ -0,0 +1,813 @@
+// Copyright 2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Lint a runtime manifest, producing a list of findings rather than stopping at the first problem.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs,
+    path::{Component, Path},
+};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    library::NEGOTIATE_FUNCTION, manifest::GenericManifest, sysroot::Sysroot, Error,
+    RuntimeManifest,
+};
+
+/// The only manifest file format version we understand
+const SUPPORTED_FILE_FORMAT_VERSION: &str = "1.0.0";
+
+const TOP_LEVEL_KEYS: [&str; 2] = ["file_format_version", "runtime"];
+const RUNTIME_KEYS: [&str; 3] = ["library_path", "name", "functions"];
+
+/// How bad is a finding?
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Worth knowing, but not a problem
+    Info,
+    /// Probably a mistake, though the loader might still accept the manifest
+    Warning,
+    /// The loader will reject this manifest, or fail to load the runtime
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem (or observation) about a manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+    /// 1-based line in the manifest, if the finding relates to a specific location
+    pub line: Option<usize>,
+    /// 1-based column in the manifest, if the finding relates to a specific location
+    pub column: Option<usize>,
+}
+
+impl Finding {
+    fn new(severity: Severity, message: impl Into<String>, position: Option<Position>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            line: position.map(|p| p.line),
+            column: position.map(|p| p.column),
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}: ", line, column)?,
+            (Some(line), None) => write!(f, "{}: ", line)?,
+            _ => {}
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Line and column (both 1-based) in the source text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+/// Positions of the keys in a (known-valid) JSON document, by their path from the root.
+///
+/// Only object keys are recorded: array elements are not relevant for manifests.
+#[derive(Default)]
+struct KeyPositions {
+    positions: HashMap<Vec<String>, Position>,
+    duplicates: Vec<(Vec<String>, Position)>,
+}
+
+impl KeyPositions {
+    /// Scan the text. Assumes it has already been parsed successfully as JSON.
+    fn scan(text: &str) -> Self {
+        let mut result = Self::default();
+        let mut scanner = Scanner {
+            chars: text.chars().peekable(),
+            position: Position { line: 1, column: 1 },
+        };
+        scanner.value(&mut vec![], &mut result);
+        result
+    }
+
+    fn get(&self, path: &[&str]) -> Option<Position> {
+        let path: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+        self.positions.get(&path).copied()
+    }
+}
+
+struct Scanner<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    position: Position,
+}
+
+impl<'a> Scanner<'a> {
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    /// Consume the four hex digits of a `\\u` escape
+    fn hex_escape(&mut self) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..4 {
+            value = value * 16 + self.next()?.to_digit(16)?;
+        }
+        Some(value)
+    }
+
+    /// Consume a string, assuming we are at the opening quote, decoding escapes the way
+    /// parsing does so keys can be looked up by their parsed names.
+    fn string(&mut self) -> String {
+        let mut s = String::new();
+        self.next();
+        while let Some(c) = self.next() {
+            match c {
+                '"' => break,
+                '\\' => match self.next() {
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => {
+                        let code = self.hex_escape().unwrap_or(0xFFFD);
+                        let code = if (0xD800..0xDC00).contains(&code) {
+                            // A surrogate pair, if valid: the low half follows as another escape
+                            let low = match (self.next(), self.next()) {
+                                (Some('\\'), Some('u')) => self.hex_escape(),
+                                _ => None,
+                            };
+                            match low {
+                                Some(low) if (0xDC00..0xE000).contains(&low) => {
+                                    0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)
+                                }
+                                _ => 0xFFFD,
+                            }
+                        } else {
+                            code
+                        };
+                        s.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                    // quote, backslash, and slash stand for themselves
+                    Some(escaped) => s.push(escaped),
+                    None => break,
+                },
+                c => s.push(c),
+            }
+        }
+        s
+    }
+
+    fn value(&mut self, path: &mut Vec<String>, out: &mut KeyPositions) {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => {
+                self.next();
+                let mut keys_in_object = HashSet::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.chars.peek() {
+                        Some('"') => {
+                            let position = self.position;
+                            let key = self.string();
+                            path.push(key.clone());
+                            if !keys_in_object.insert(key) {
+                                out.duplicates.push((path.clone(), position));
+                            }
+                            // Later duplicates win, like when parsing
+                            out.positions.insert(path.clone(), position);
+                            self.skip_whitespace();
+                            // the colon
+                            self.next();
+                            self.value(path, out);
+                            path.pop();
+                        }
+                        Some(',') => {
+                            self.next();
+                        }
+                        _ => {
+                            // closing brace, or end of input
+                            self.next();
+                            break;
+                        }
+                    }
+                }
+            }
+            Some('[') => {
+                self.next();
+                loop {
+                    self.skip_whitespace();
+                    match self.chars.peek() {
+                        Some(']') | None => {
+                            self.next();
+                            break;
+                        }
+                        Some(',') => {
+                            self.next();
+                        }
+                        _ => self.value(path, out),
+                    }
+                }
+            }
+            Some('"') => {
+                self.string();
+            }
+            _ => {
+                // number, true, false, null
+                while self
+                    .chars
+                    .peek()
+                    .is_some_and(|c| !matches!(c, ',' | '}' | ']') && !c.is_whitespace())
+                {
+                    self.next();
+                }
+            }
+        }
+    }
+}
+
+/// Does this relative path, once normalized, point outside the directory it is relative to?
+fn escapes_directory(relative: &Path) -> bool {
+    let mut depth: usize = 0;
+    for component in relative.components() {
+        match component {
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return true,
+            },
+            Component::Normal(_) => depth += 1,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Accumulates findings along with what we need to locate them.
+struct Linter<'a> {
+    keys: KeyPositions,
+    manifest_path: &'a Path,
+    sysroot: &'a Sysroot,
+    findings: Vec<Finding>,
+}
+
+impl<'a> Linter<'a> {
+    fn push(&mut self, severity: Severity, message: impl Into<String>, key_path: &[&str]) {
+        let position = self.keys.get(key_path);
+        self.findings
+            .push(Finding::new(severity, message, position));
+    }
+
+    fn check_unknown_keys(&mut self, object: &Map<String, Value>, known: &[&str], parent: &[&str]) {
+        for key in object.keys().filter(|k| !known.contains(&k.as_str())) {
+            let mut key_path = parent.to_vec();
+            key_path.push(key);
+            let location = if parent.is_empty() {
+                "at the top level".to_owned()
+            } else {
+                format!("in \"{}\"", parent.join("."))
+            };
+            self.push(
+                Severity::Warning,
+                format!("Unknown key \"{}\" {}", key, location),
+                &key_path,
+            );
+        }
+    }
+
+    fn check_file_format_version(&mut self, root: &Map<String, Value>) {
+        match root.get("file_format_version") {
+            None => self.push(
+                Severity::Error,
+                "Missing required \"file_format_version\"",
+                &[],
+            ),
+            Some(Value::String(v)) if v == SUPPORTED_FILE_FORMAT_VERSION => {}
+            Some(Value::String(v)) => self.push(
+                Severity::Error,
+                format!(
+                    "Unsupported \"file_format_version\" \"{}\": expected \"{}\"",
+                    v, SUPPORTED_FILE_FORMAT_VERSION
+                ),
+                &["file_format_version"],
+            ),
+            Some(_) => self.push(
+                Severity::Error,
+                "\"file_format_version\" must be a string",
+                &["file_format_version"],
+            ),
+        }
+    }
+
+    fn check_functions(&mut self, functions: &Value) {
+        let functions = match functions {
+            Value::Object(o) => o,
+            _ => {
+                self.push(
+                    Severity::Error,
+                    "\"runtime.functions\" must be an object",
+                    &["runtime", "functions"],
+                );
+                return;
+            }
+        };
+        self.check_unknown_keys(functions, &[NEGOTIATE_FUNCTION], &["runtime", "functions"]);
+        match functions.get(NEGOTIATE_FUNCTION) {
+            None => {}
+            Some(Value::String(symbol)) if symbol == NEGOTIATE_FUNCTION => self.push(
+                Severity::Info,
+                format!(
+                    "\"{}\" is \"renamed\" to its default name, which is unnecessary",
+                    NEGOTIATE_FUNCTION
+                ),
+                &["runtime", "functions", NEGOTIATE_FUNCTION],
+            ),
+            Some(Value::String(symbol)) => self.push(
+                Severity::Info,
+                format!(
+                    "\"{}\" is renamed: the library must export \"{}\" instead",
+                    NEGOTIATE_FUNCTION, symbol
+                ),
+                &["runtime", "functions", NEGOTIATE_FUNCTION],
+            ),
+            Some(_) => self.push(
+                Severity::Error,
+                format!(
+                    "The renamed symbol for \"{}\" must be a string",
+                    NEGOTIATE_FUNCTION
+                ),
+                &["runtime", "functions", NEGOTIATE_FUNCTION],
+            ),
+        }
+    }
+
+    fn check_runtime_object(&mut self, runtime: &Map<String, Value>) {
+        self.check_unknown_keys(runtime, &RUNTIME_KEYS, &["runtime"]);
+        match runtime.get("library_path") {
+            None => self.push(
+                Severity::Error,
+                "Missing required \"runtime.library_path\"",
+                &["runtime"],
+            ),
+            Some(Value::String(s)) if s.is_empty() => self.push(
+                Severity::Error,
+                "\"runtime.library_path\" is empty",
+                &["runtime", "library_path"],
+            ),
+            Some(Value::String(_)) => {}
+            Some(_) => self.push(
+                Severity::Error,
+                "\"runtime.library_path\" must be a string",
+                &["runtime", "library_path"],
+            ),
+        }
+        match runtime.get("name") {
+            None => self.push(
+                Severity::Warning,
+                "Missing \"runtime.name\": tools will have to guess a name for this runtime",
+                &["runtime"],
+            ),
+            Some(Value::String(s)) if s.trim().is_empty() => self.push(
+                Severity::Warning,
+                "\"runtime.name\" is empty",
+                &["runtime", "name"],
+            ),
+            Some(Value::String(_)) => {}
+            Some(_) => self.push(
+                Severity::Error,
+                "\"runtime.name\" must be a string",
+                &["runtime", "name"],
+            ),
+        }
+        if let Some(functions) = runtime.get("functions") {
+            self.check_functions(functions);
+        }
+    }
+
+    /// Check the library referred to by a manifest that otherwise parsed fine.
+    fn check_library(&mut self, manifest: &RuntimeManifest) {
+        let key_path = ["runtime", "library_path"];
+        let library_path = manifest.library_path();
+        if manifest.uses_search_path() {
+            self.push(
+                Severity::Info,
+                format!(
+                    "\"{}\" will be found using the dynamic library search path, so it was not checked",
+                    library_path
+                ),
+                &key_path,
+            );
+            return;
+        }
+        let manifest_dir = self
+            .manifest_path
+            .parent()
+            .expect("files always have parents");
+        if manifest.library_relative_to_manifest() && escapes_directory(Path::new(library_path)) {
+            self.push(
+                Severity::Warning,
+                format!(
+                    "Relative library path \"{}\" goes outside the manifest directory, so moving the manifest will break it",
+                    library_path
+                ),
+                &key_path,
+            );
+        }
+        // Absolute paths are in the inspected system, which might not be the one we run on
+        let resolved = if Path::new(library_path).has_root() {
+            self.sysroot.map(Path::new(library_path))
+        } else {
+            manifest_dir.join(library_path)
+        };
+        let resolved = self.sysroot.canonicalize(&resolved).unwrap_or(resolved);
+        match fs::metadata(&resolved) {
+            Ok(m) if m.is_file() => {}
+            Ok(_) => self.push(
+                Severity::Error,
+                format!(
+                    "Library path {} exists but is not a regular file",
+                    resolved.display()
+                ),
+                &key_path,
+            ),
+            Err(_) => self.push(
+                Severity::Error,
+                format!("Library {} does not exist", resolved.display()),
+                &key_path,
+            ),
+        }
+    }
+
+    fn check_duplicates(&mut self) {
+        let duplicates = std::mem::take(&mut self.keys.duplicates);
+        for (path, position) in duplicates {
+            self.findings.push(Finding::new(
+                Severity::Warning,
+                format!(
+                    "Duplicate key \"{}\": only the last one will be used",
+                    path.join(".")
+                ),
+                Some(position),
+            ));
+        }
+    }
+}
+
+/// Lint the contents of a runtime manifest.
+///
+/// The manifest path is used for resolving the library path and is not read.
+pub fn validate_runtime_manifest_str(contents: &str, manifest_path: &Path) -> Vec<Finding> {
+    validate_runtime_manifest_str_in(contents, manifest_path, &Sysroot::host())
+}
+
+/// Lint the contents of a runtime manifest that is part of the system in `sysroot`.
+pub(crate) fn validate_runtime_manifest_str_in(
+    contents: &str,
+    manifest_path: &Path,
+    sysroot: &Sysroot,
+) -> Vec<Finding> {
+    let root: Value = match serde_json::from_str(contents) {
+        Ok(v) => v,
+        Err(e) => {
+            // The position is reported separately, so leave it out of the message
+            let message = e.to_string();
+            let suffix = format!(" at line {} column {}", e.line(), e.column());
+            let message = message.strip_suffix(&suffix).unwrap_or(&message);
+            return vec![Finding::new(
+                Severity::Error,
+                format!("Invalid JSON: {}", message),
+                Some(Position {
+                    line: e.line(),
+                    column: e.column(),
+                }),
+            )];
+        }
+    };
+    let mut linter = Linter {
+        keys: KeyPositions::scan(contents),
+        manifest_path,
+        sysroot,
+        findings: vec![],
+    };
+    linter.check_duplicates();
+
+    let root_object = match &root {
+        Value::Object(o) => o,
+        _ => {
+            linter.push(
+                Severity::Error,
+                "The manifest must contain a JSON object",
+                &[],
+            );
+            return linter.findings;
+        }
+    };
+    linter.check_unknown_keys(root_object, &TOP_LEVEL_KEYS, &[]);
+    linter.check_file_format_version(root_object);
+
+    match root_object.get("runtime") {
+        None => linter.push(Severity::Error, "Missing required \"runtime\" object", &[]),
+        Some(Value::Object(runtime)) => linter.check_runtime_object(runtime),
+        Some(_) => linter.push(
+            Severity::Error,
+            "\"runtime\" must be an object",
+            &["runtime"],
+        ),
+    }
+
+    // Only check the library if we can read the manifest like the rest of the crate does
+    if let Ok(manifest) = serde_json::from_value::<RuntimeManifest>(root) {
+        linter.check_library(&manifest);
+    }
+    linter
+        .findings
+        .sort_by_key(|f| (f.line.unwrap_or_default(), f.column.unwrap_or_default()));
+    linter.findings
+}
+
+/// Lint a runtime manifest file.
+///
+/// Returns an error only if the file could not be read at all:
+/// all problems with its contents are reported as findings.
+pub fn validate_runtime_manifest(manifest_path: &Path) -> Result<Vec<Finding>, Error> {
+    validate_runtime_manifest_in(manifest_path, &Sysroot::host())
+}
+
+/// Lint a runtime manifest file that is part of the system in `sysroot`.
+pub(crate) fn validate_runtime_manifest_in(
+    manifest_path: &Path,
+    sysroot: &Sysroot,
+) -> Result<Vec<Finding>, Error> {
+    let contents = fs::read_to_string(manifest_path)?;
+    Ok(validate_runtime_manifest_str_in(
+        &contents,
+        manifest_path,
+        sysroot,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lint a manifest with the given contents, in a temporary directory where it has
+    /// `lib/libtest.so` and `lib/dir` next to it, and `../outside/libtest.so`
+    fn lint(contents: &str) -> Vec<(Severity, String)> {
+        let root = tempfile::tempdir().unwrap();
+        let manifest_dir = root.path().join("manifests");
+        fs::create_dir_all(manifest_dir.join("lib/dir")).unwrap();
+        fs::create_dir_all(root.path().join("outside")).unwrap();
+        fs::write(manifest_dir.join("lib/libtest.so"), "").unwrap();
+        fs::write(root.path().join("outside/libtest.so"), "").unwrap();
+        let manifest = manifest_dir.join("openxr_test.json");
+        fs::write(&manifest, contents).unwrap();
+        validate_runtime_manifest(&manifest)
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.severity, f.message))
+            .collect()
+    }
+
+    /// A manifest with the given contents of the runtime object
+    fn manifest(runtime: &str) -> String {
+        format!(
+            r#"{{"file_format_version": "1.0.0", "runtime": {{{}}}}}"#,
+            runtime
+        )
+    }
+
+    fn position(line: usize, column: usize) -> Option<Position> {
+        Some(Position { line, column })
+    }
+
+    #[test]
+    fn key_positions_in_nested_objects() {
+        let text = r#"{
+  "file_format_version": "1.0.0",
+  "runtime": {
+    "library_path": "libx.so",
+    "functions": { "xrNegotiateLoaderRuntimeInterface": "renamed" }
+  }
+}"#;
+        let keys = KeyPositions::scan(text);
+        assert_eq!(keys.get(&["file_format_version"]), position(2, 3));
+        assert_eq!(keys.get(&["runtime"]), position(3, 3));
+        assert_eq!(keys.get(&["runtime", "library_path"]), position(4, 5));
+        assert_eq!(
+            keys.get(&["runtime", "functions", NEGOTIATE_FUNCTION]),
+            position(5, 20)
+        );
+        // Only the full path finds a nested key
+        assert_eq!(keys.get(&["library_path"]), None);
+        assert!(keys.duplicates.is_empty());
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let text = "{\"runtime\": {\"name\": \"a\", \"name\": \"b\"}, \"runtime2\": {\"name\": 1}}";
+        let keys = KeyPositions::scan(text);
+        assert_eq!(
+            keys.duplicates,
+            vec![(
+                vec!["runtime".to_owned(), "name".to_owned()],
+                position(1, 27).unwrap()
+            )]
+        );
+        // The last one wins, as when parsing
+        assert_eq!(keys.get(&["runtime", "name"]), position(1, 27));
+        // The same name in another object is not a duplicate
+        assert_eq!(keys.get(&["runtime2", "name"]), position(1, 54));
+    }
+
+    #[test]
+    fn escaped_strings() {
+        let text = r#"{"a\"b": "}{,\"", "é\n": [{"x": "\\"}], "😀": 1, "c": 2}"#;
+        let keys = KeyPositions::scan(text);
+        assert_eq!(keys.get(&["a\"b"]), position(1, 2));
+        assert_eq!(keys.get(&["é\n"]), position(1, 19));
+        // Keys of objects in arrays belong to the array's key
+        assert_eq!(keys.get(&["é\n", "x"]), position(1, 28));
+        assert_eq!(keys.get(&["😀"]), position(1, 41));
+        // Braces and escaped quotes inside strings are not structure
+        assert_eq!(keys.get(&["c"]), position(1, 49));
+        assert!(keys.duplicates.is_empty());
+
+        let keys = KeyPositions::scan(r#"{"\u0041\ud83d\ude00\/": {"\t": null}}"#);
+        assert_eq!(keys.get(&["A😀/"]), position(1, 2));
+        assert_eq!(keys.get(&["A😀/", "\t"]), position(1, 27));
+    }
+
+    #[test]
+    fn duplicate_key_finding() {
+        let text = "{\n\"file_format_version\": \"1.0.0\",\n\"file_format_version\": \"1.0.0\"\n}";
+        let findings = validate_runtime_manifest_str(text, Path::new("/nonexistent/x.json"));
+        assert!(findings.iter().any(|f| f.severity == Severity::Warning
+            && f.message.contains("Duplicate key \"file_format_version\"")
+            && f.line == Some(3)
+            && f.column == Some(1)));
+    }
+
+    #[test]
+    fn valid_manifest() {
+        let findings = lint(&manifest(
+            r#""name": "Test", "library_path": "./lib/libtest.so""#,
+        ));
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn missing_name() {
+        let findings = lint(&manifest(r#""library_path": "./lib/libtest.so""#));
+        assert_eq!(
+            findings,
+            vec![(
+                Severity::Warning,
+                "Missing \"runtime.name\": tools will have to guess a name for this runtime"
+                    .to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn unsupported_file_format_version() {
+        let findings = lint(
+            r#"{"file_format_version": "2.0.0", "runtime": {"name": "Test", "library_path": "./lib/libtest.so"}}"#,
+        );
+        assert_eq!(
+            findings,
+            vec![(
+                Severity::Error,
+                "Unsupported \"file_format_version\" \"2.0.0\": expected \"1.0.0\"".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn missing_library() {
+        let findings = lint(&manifest(
+            r#""name": "Test", "library_path": "./lib/libmissing.so""#,
+        ));
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].0, Severity::Error);
+        assert!(findings[0].1.starts_with("Library "));
+        assert!(findings[0].1.ends_with("libmissing.so does not exist"));
+    }
+
+    #[test]
+    fn library_not_a_file() {
+        let findings = lint(&manifest(r#""name": "Test", "library_path": "./lib/dir""#));
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].0, Severity::Error);
+        assert!(findings[0]
+            .1
+            .ends_with("dir exists but is not a regular file"));
+    }
+
+    #[test]
+    fn library_outside_manifest_directory() {
+        let findings = lint(&manifest(
+            r#""name": "Test", "library_path": "../outside/libtest.so""#,
+        ));
+        assert_eq!(
+            findings,
+            vec![(
+                Severity::Warning,
+                "Relative library path \"../outside/libtest.so\" goes outside the manifest directory, so moving the manifest will break it"
+                    .to_owned()
+            )]
+        );
+        // Going up and back down again stays inside
+        let findings = lint(&manifest(
+            r#""name": "Test", "library_path": "lib/../lib/libtest.so""#,
+        ));
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let findings = lint(
+            r#"{"file_format_version": "1.0.0", "extra": 1, "runtime": {"name": "Test", "library_path": "./lib/libtest.so", "api_version": "1.0", "functions": {"xrGetInstanceProcAddr": "a"}}}"#,
+        );
+        assert_eq!(
+            findings,
+            vec![
+                (
+                    Severity::Warning,
+                    "Unknown key \"extra\" at the top level".to_owned()
+                ),
+                (
+                    Severity::Warning,
+                    "Unknown key \"api_version\" in \"runtime\"".to_owned()
+                ),
+                (
+                    Severity::Warning,
+                    "Unknown key \"xrGetInstanceProcAddr\" in \"runtime.functions\"".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_negotiate_function() {
+        let findings = lint(&manifest(
+            r#""name": "Test", "library_path": "./lib/libtest.so", "functions": {"xrNegotiateLoaderRuntimeInterface": "xrRenamed"}"#,
+        ));
+        assert_eq!(
+            findings,
+            vec![(
+                Severity::Info,
+                "\"xrNegotiateLoaderRuntimeInterface\" is renamed: the library must export \"xrRenamed\" instead"
+                    .to_owned()
+            )]
+        );
+        let findings = lint(&manifest(
+            r#""name": "Test", "library_path": "./lib/libtest.so", "functions": {"xrNegotiateLoaderRuntimeInterface": "xrNegotiateLoaderRuntimeInterface"}"#,
+        ));
+        assert_eq!(
+            findings,
+            vec![(
+                Severity::Info,
+                "\"xrNegotiateLoaderRuntimeInterface\" is \"renamed\" to its default name, which is unnecessary"
+                    .to_owned()
+            )]
+        );
+        let findings = lint(&manifest(
+            r#""name": "Test", "library_path": "./lib/libtest.so", "functions": {"xrNegotiateLoaderRuntimeInterface": 1}"#,
+        ));
+        assert_eq!(
+            findings,
+            vec![(
+                Severity::Error,
+                "The renamed symbol for \"xrNegotiateLoaderRuntimeInterface\" must be a string"
+                    .to_owned()
+            )]
+        );
+    }
+}
@@ -2,34 +2,58 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use crate::{
-    arch_detect::{get_runtime_bitness, PushUnique, RuntimeBitness},
+    arch_detect::{get_runtime_architecture, Architecture, LibraryVersion, PushUnique},
+    diagnostics::{
+        check_active_manifest, check_environment, check_override_manifest, Check, CheckStatus,
+        Diagnosis,
+    },
+    known_runtimes::{expand_location, KnownRuntime, KnownRuntimes, KNOWN_RUNTIMES_FILENAME},
+    layer::{
+        environment_explicit_layer_dirs, layer_manifests_in_dir, ApiLayer, ApiLayerCollection,
+        ApiLayerKind,
+    },
+    library::{missing_dependency_problems, LibraryInspection, ResolvedDependency, RuntimeProblem},
     manifest::GenericManifest,
-    platform::{Platform, PlatformRuntime},
-    runtime::BaseRuntime,
-    ActiveState, Error, ManifestError, OPENXR, OPENXR_MAJOR_VERSION,
+    pairing::{pair_manifests, ManifestSummary},
+    platform::{unsupported_strategy, Platform, PlatformRuntime},
+    registry::{
+        self, active_runtime, known_runtimes, make_prefix_key, register_runtime,
+        set_active_runtime, set_runtimes_enabled, unregister_runtimes, Hive, Registry,
+        RegistryView, ACTIVE_RUNTIME,
+    },
+    runtime::{environment_override_manifest, BaseRuntime},
+    sysroot::Sysroot,
+    ActivationStrategy, ActiveState, Error, ManifestError, RUNTIME_JSON_ENV_VAR,
 };
 use itertools::Itertools;
 use special_folder::SpecialFolder;
 use std::{
-    collections::{hash_map::RandomState, HashMap, HashSet},
+    collections::{HashMap, HashSet},
+    env, io,
     path::{Path, PathBuf},
+    sync::OnceLock,
 };
 use winreg::{
-    enums::{HKEY_LOCAL_MACHINE, KEY_QUERY_VALUE, KEY_READ, KEY_WRITE, KEY_CREATE_SUB_KEY},
-    RegKey, RegValue,
+    enums::{
+        HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, KEY_CREATE_SUB_KEY, KEY_QUERY_VALUE, KEY_READ,
+        KEY_WRITE,
+    },
+    RegKey,
 };
 
 #[derive(Debug, Clone)]
 pub struct WindowsRuntime {
     base64: Option<BaseRuntime>,
     base32: Option<BaseRuntime>,
+    /// Inspected once up front, since reading the libraries is slow
+    libraries: Vec<LibraryInspection>,
+    /// Whether it is enabled in `AvailableRuntimes`, if listed there
+    enabled: Option<bool>,
+    /// Each library, with the DLLs it needs: resolved the first time they are needed, since
+    /// that reads the whole tree of libraries
+    dependencies: OnceLock<Vec<(PathBuf, Vec<ResolvedDependency>)>>,
 }
 
-const WINMR_JSON_NAME: &str = "MixedRealityRuntime.json";
-
-const AVAILABLE_RUNTIMES: &str = "AvailableRuntimes";
-const ACTIVE_RUNTIME: &str = "ActiveRuntime";
-
 #[cfg(target_pointer_width = "64")]
 fn system_dir_64() -> Option<PathBuf> {
     SpecialFolder::System.get()
@@ -60,11 +84,37 @@ fn system_dir_32() -> Option<PathBuf> {
     SpecialFolder::System.get()
 }
 
-fn make_prefix_key() -> PathBuf {
-    Path::new("Software")
-        .join("Khronos")
-        .join(OPENXR)
-        .join(&OPENXR_MAJOR_VERSION.to_string())
+/// The 64-bit Program Files directory, even from a 32-bit process
+fn program_files_64() -> Option<PathBuf> {
+    env::var_os("ProgramW6432").map(PathBuf::from).or_else(|| {
+        SpecialFolder::ProgramFiles
+            .get()
+            .filter(|_| cfg!(target_pointer_width = "64"))
+    })
+}
+
+/// The folder a placeholder in a known runtime's install location stands for
+fn known_location_folder(placeholder: &str) -> Option<PathBuf> {
+    match placeholder {
+        "program_files" => program_files_64(),
+        "program_files_x86" => env::var_os("ProgramFiles(x86)").map(PathBuf::from),
+        "system_64" => system_dir_64(),
+        "system_32" => system_dir_32(),
+        _ => None,
+    }
+}
+
+/// The user's additions to the known runtimes
+fn known_runtimes_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| {
+        dir.join(env!("CARGO_PKG_NAME"))
+            .join(KNOWN_RUNTIMES_FILENAME)
+    })
+}
+
+/// The system directories DLLs are loaded from, for 64-bit and 32-bit libraries
+pub(crate) fn system_dll_dirs() -> (Option<PathBuf>, Option<PathBuf>) {
+    (system_dir_64(), system_dir_32())
 }
 
 #[cfg(target_pointer_width = "64")]
@@ -98,49 +148,235 @@ fn make_prefix_key_flags_32() -> Option<u32> {
     Some(KEY_WOW64_32KEY)
 }
 
-fn get_active_runtime_manifest_path(prefix: &Path, reg_flags: Option<u32>) -> Option<PathBuf> {
-    let reg_flags = reg_flags?;
-    let base = RegKey::predef(HKEY_LOCAL_MACHINE)
-        .open_subkey_with_flags(prefix, reg_flags | KEY_READ | KEY_QUERY_VALUE)
-        .ok()?;
-    let val: String = base.get_value(ACTIVE_RUNTIME).ok()?;
-    Some(Path::new(&val).to_path_buf())
+/// The registry flags to open our keys in a view, if it exists
+fn view_flags(view: RegistryView) -> Option<u32> {
+    match view {
+        RegistryView::Bits64 => make_prefix_key_flags_64(),
+        RegistryView::Bits32 => make_prefix_key_flags_32(),
+    }
+}
+
+/// The real registry
+struct WindowsRegistry;
+
+impl WindowsRegistry {
+    fn flags(view: RegistryView) -> io::Result<u32> {
+        view_flags(view).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "There is no 64-bit registry view on 32-bit Windows",
+            )
+        })
+    }
+
+    fn open(hive: Hive, view: RegistryView, key: &Path, access: u32) -> io::Result<RegKey> {
+        hive_key(hive).open_subkey_with_flags(key, Self::flags(view)? | access)
+    }
+
+    fn create(hive: Hive, view: RegistryView, key: &Path) -> io::Result<RegKey> {
+        let (key, _disp) = hive_key(hive).create_subkey_with_flags(
+            key,
+            Self::flags(view)? | KEY_WRITE | KEY_READ | KEY_QUERY_VALUE | KEY_CREATE_SUB_KEY,
+        )?;
+        Ok(key)
+    }
+}
+
+fn hive_key(hive: Hive) -> RegKey {
+    match hive {
+        Hive::LocalMachine => RegKey::predef(HKEY_LOCAL_MACHINE),
+        Hive::CurrentUser => RegKey::predef(HKEY_CURRENT_USER),
+    }
+}
+
+impl Registry for WindowsRegistry {
+    fn views(&self) -> Vec<RegistryView> {
+        [RegistryView::Bits64, RegistryView::Bits32]
+            .into_iter()
+            .filter(|view| view_flags(*view).is_some())
+            .collect()
+    }
+
+    fn dword_values(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+    ) -> io::Result<Vec<(String, u32)>> {
+        let key = match Self::open(hive, view, key, KEY_READ | KEY_QUERY_VALUE) {
+            Ok(key) => key,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        Ok(key
+            .enum_values()
+            .filter_map(|x| {
+                let (val_name, _) = x.ok()?;
+                let v: u32 = key.get_value(&val_name).ok()?;
+                Some((val_name, v))
+            })
+            .collect())
+    }
+
+    fn string_value(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+    ) -> io::Result<Option<String>> {
+        let value = Self::open(hive, view, key, KEY_READ | KEY_QUERY_VALUE)
+            .and_then(|key| key.get_value::<String, _>(name));
+        match value {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn set_string(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+        value: &str,
+    ) -> io::Result<()> {
+        Self::create(hive, view, key)?.set_value(name, &value)
+    }
+
+    fn set_dword(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+        value: u32,
+    ) -> io::Result<()> {
+        Self::create(hive, view, key)?.set_value(name, &value)
+    }
+
+    fn delete_value(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &Path,
+        name: &str,
+    ) -> io::Result<()> {
+        Self::open(hive, view, key, KEY_READ | KEY_WRITE)?.delete_value(name)
+    }
 }
 
 impl WindowsRuntime {
-    fn new(path64: Option<&Path>, path32: Option<&Path>) -> Result<Self, Error> {
-        let base64 = path64.map(BaseRuntime::new).transpose()?;
-        let base32 = path32.map(BaseRuntime::new).transpose()?;
-        Ok(WindowsRuntime { base64, base32 })
+    fn new(
+        path64: Option<&Path>,
+        path32: Option<&Path>,
+        known: &KnownRuntimes,
+    ) -> Result<Self, Error> {
+        let mut libraries = vec![];
+        let mut inspect = |base: BaseRuntime| {
+            let base = base.with_known_runtimes(known);
+            let library = base.inspect_library_in(&Sysroot::host());
+            let base = base.with_library_version(library.version.as_ref());
+            libraries.push(library);
+            base
+        };
+        let base64 = path64.map(BaseRuntime::new).transpose()?.map(&mut inspect);
+        let base32 = path32.map(BaseRuntime::new).transpose()?.map(&mut inspect);
+        Ok(WindowsRuntime {
+            base64,
+            base32,
+            libraries,
+            enabled: None,
+            dependencies: OnceLock::new(),
+        })
     }
 
     fn runtimes(&self) -> impl Iterator<Item = &BaseRuntime> {
         self.base64.iter().chain(self.base32.iter())
     }
+
+    fn resolved_dependencies(&self) -> &[(PathBuf, Vec<ResolvedDependency>)] {
+        self.dependencies.get_or_init(|| {
+            self.runtimes()
+                .map(|r| {
+                    (
+                        r.resolve_library_path(),
+                        r.resolve_dependencies_in(&Sysroot::host()),
+                    )
+                })
+                .collect()
+        })
+    }
 }
 
 impl PlatformRuntime for WindowsRuntime {
     fn make_active(&self) -> Result<(), Error> {
-        fn try_set_active(
-            reg_path: &Path,
-            runtime: &Option<BaseRuntime>,
-            flags: Option<u32>,
-        ) -> Result<(), Error> {
-            if let (Some(runtime), Some(flags)) = (runtime, flags) {
-                let (key, _disp) = RegKey::predef(HKEY_LOCAL_MACHINE).create_subkey_with_flags(
-                    reg_path,
-                    flags | KEY_WRITE | KEY_READ | KEY_QUERY_VALUE | KEY_CREATE_SUB_KEY,
-                )?;
-                key.set_value(ACTIVE_RUNTIME, &runtime.get_manifest_path().as_os_str())?;
+        let views = WindowsRegistry.views();
+        for (runtime, view) in [
+            (&self.base64, RegistryView::Bits64),
+            (&self.base32, RegistryView::Bits32),
+        ] {
+            if let Some(runtime) = runtime.as_ref().filter(|_| views.contains(&view)) {
+                set_active_runtime(&WindowsRegistry, view, runtime.get_manifest_path())?;
             }
-            Ok(())
         }
-        let key = make_prefix_key();
-        try_set_active(&key, &self.base64, make_prefix_key_flags_64())?;
-        try_set_active(&key, &self.base32, make_prefix_key_flags_32())?;
         Ok(())
     }
 
+    fn make_active_system_wide(
+        &self,
+        strategy: ActivationStrategy,
+        dry_run: bool,
+    ) -> Result<Vec<String>, Error> {
+        // The active runtime is always set for all users here, in HKEY_LOCAL_MACHINE
+        if strategy != ActivationStrategy::default() {
+            return Err(unsupported_strategy(strategy));
+        }
+        if !dry_run {
+            self.make_active()?;
+        }
+        Ok(self
+            .get_manifests()
+            .into_iter()
+            .map(|manifest| {
+                format!(
+                    "HKEY_LOCAL_MACHINE\\{}\\{}: {}",
+                    make_prefix_key().display(),
+                    ACTIVE_RUNTIME,
+                    manifest.display()
+                )
+            })
+            .collect())
+    }
+
+    fn make_active_for_architecture(
+        &self,
+        architecture: Architecture,
+        strategy: ActivationStrategy,
+    ) -> Result<(), Error> {
+        if strategy != ActivationStrategy::default() {
+            return Err(unsupported_strategy(strategy));
+        }
+        // The registry has one setting for 64-bit applications and one for 32-bit ones
+        let (base, view) = if architecture.is_64() {
+            (&self.base64, RegistryView::Bits64)
+        } else {
+            (&self.base32, RegistryView::Bits32)
+        };
+        let runtime = match base {
+            Some(runtime) if WindowsRegistry.views().contains(&view) => runtime,
+            _ => {
+                return Err(Error::SetActiveError(format!(
+                    "{} has no manifest for {} applications",
+                    self.get_runtime_name(),
+                    architecture
+                )))
+            }
+        };
+        set_active_runtime(&WindowsRegistry, view, runtime.get_manifest_path())
+    }
+
     fn get_runtime_name(&self) -> String {
         self.runtimes()
             .map(|r| r.get_runtime_name())
@@ -156,32 +392,81 @@ impl PlatformRuntime for WindowsRuntime {
         self.runtimes().map(|r| r.resolve_library_path()).collect()
     }
 
+    fn get_problems(&self) -> Vec<RuntimeProblem> {
+        let dependency_problems = self
+            .resolved_dependencies()
+            .iter()
+            .flat_map(|(library, dependencies)| missing_dependency_problems(library, dependencies));
+        self.libraries
+            .iter()
+            .flat_map(|l| l.problems.iter().cloned())
+            .chain(dependency_problems)
+            .collect()
+    }
+
+    fn get_architectures(&self) -> Vec<Architecture> {
+        self.libraries
+            .iter()
+            .filter_map(|l| l.architecture)
+            .collect()
+    }
+
+    fn get_version(&self) -> Option<LibraryVersion> {
+        self.libraries.iter().find_map(|l| l.version.clone())
+    }
+
+    fn get_known_runtime(&self) -> Option<&KnownRuntime> {
+        self.runtimes().find_map(|r| r.get_known_runtime())
+    }
+
+    fn get_dependencies(&self) -> Vec<ResolvedDependency> {
+        self.resolved_dependencies()
+            .iter()
+            .flat_map(|(_, dependencies)| dependencies.iter().cloned())
+            .collect()
+    }
+
     fn describe(&self) -> String {
         self.runtimes()
             .map(|r| r.describe_manifest(r.get_manifest_path()))
             .join("\n")
     }
+
+    fn get_enabled(&self) -> Option<bool> {
+        self.enabled
+    }
 }
 
 /// Little helper for accumulating runtimes and coalescing their different bitnesses.
-#[derive(Default)]
 struct RuntimeCollection {
     runtimes: Vec<WindowsRuntime>,
     used_manifests: HashSet<PathBuf>,
+    known: KnownRuntimes,
 }
 
 impl RuntimeCollection {
+    fn new(known: KnownRuntimes) -> Self {
+        Self {
+            runtimes: vec![],
+            used_manifests: HashSet::new(),
+            known,
+        }
+    }
+
+    /// Has this manifest been added already? Ignores case, since paths we build may differ
+    /// from registered ones only in that.
+    fn is_used(&self, manifest: &Path) -> bool {
+        let manifest = manifest.to_string_lossy();
+        self.used_manifests
+            .iter()
+            .any(|p| p.to_string_lossy().eq_ignore_ascii_case(&manifest))
+    }
+
     fn try_add(&mut self, path64: Option<&Path>, path32: Option<&Path>) -> Result<(), Error> {
         let mut has_path = false;
-        if let Some(p) = path64 {
+        for p in path64.into_iter().chain(path32) {
             has_path = true;
-            if self.used_manifests.contains(p) {
-                return Ok(());
-            }
-        }
-        if let Some(p) = path32 {
-            has_path = true;
-            if self.used_manifests.contains(p) {
+            if self.is_used(p) {
                 return Ok(());
             }
         }
@@ -190,7 +475,7 @@ impl RuntimeCollection {
                 "Tried to add a runtime with no manifest paths!".to_string(),
             ));
         }
-        let runtime = WindowsRuntime::new(path64, path32)?;
+        let runtime = WindowsRuntime::new(path64, path32, &self.known)?;
         self.runtimes.push(runtime);
         if let Some(p) = path64 {
             self.used_manifests.insert(p.to_owned());
@@ -201,44 +486,37 @@ impl RuntimeCollection {
         Ok(())
     }
 
-    fn try_add_varjo(&mut self) -> Result<(), ManifestError> {
-        if !cfg!(target_pointer_width = "64") {
-            return Ok(());
-        }
-        let path = SpecialFolder::ProgramFiles.get().map(|p| {
-            p.join("Varjo")
-                .join("varjo-openxr")
-                .join("VarjoOpenXR.json")
-        });
-        let path = path.as_deref().filter(|&p| p.exists());
-        if let Some(path) = path {
-            self.try_add(Some(path), None)
-                .map_err(|e| ManifestError(path.to_owned(), e))
-        } else {
-            Ok(())
-        }
-    }
-
-    fn try_add_winmr(&mut self) -> Result<(), ManifestError> {
-        // Manually add winmr because it will be some revisions of windows before they can put it in AvailableRuntimes
-        let (winmr64, winmr32) = (
-            system_dir_64().map(|d| d.join(WINMR_JSON_NAME)),
-            system_dir_32().map(|d| d.join(WINMR_JSON_NAME)),
-        );
-
-        // Only use paths that exist
-        let (winmr64, winmr32) = (
-            winmr64.as_deref().filter(|&p| p.exists()),
-            winmr32.as_deref().filter(|&p| p.exists()),
-        );
-
-        if winmr64.is_some() || winmr32.is_some() {
-            self.try_add(winmr64, winmr32).map_err(|e| {
-                ManifestError(winmr64.unwrap_or_else(|| winmr32.unwrap()).to_owned(), e)
-            })
-        } else {
-            Ok(())
+    /// Add the known runtimes installed where we expect them, which might not be registered
+    /// (like Windows Mixed Reality, which is not in `AvailableRuntimes` in some versions of
+    /// Windows). Returns any non-fatal errors.
+    fn try_add_known_locations(&mut self) -> Vec<ManifestError> {
+        let expand = |location: &Option<String>| {
+            location
+                .as_deref()
+                .and_then(|l| expand_location(l, known_location_folder))
+                .filter(|p| p.exists())
+        };
+        let locations: Vec<(Option<PathBuf>, Option<PathBuf>)> = self
+            .known
+            .runtimes()
+            .iter()
+            .flat_map(|runtime| &runtime.locations.windows)
+            .map(|location| (expand(&location.manifest_64), expand(&location.manifest_32)))
+            .collect();
+        let mut nonfatal_errors = vec![];
+        for (path64, path32) in locations {
+            let path = match path64.as_ref().or(path32.as_ref()) {
+                Some(path) => path.clone(),
+                None => continue,
+            };
+            if path64.iter().chain(path32.iter()).any(|p| self.is_used(p)) {
+                continue;
+            }
+            if let Err(e) = self.try_add(path64.as_deref(), path32.as_deref()) {
+                nonfatal_errors.push(ManifestError(path, e));
+            }
         }
+        nonfatal_errors
     }
 }
 
@@ -251,6 +529,8 @@ impl From<RuntimeCollection> for Vec<WindowsRuntime> {
 pub struct WindowsActiveRuntimeData {
     active_64: Option<PathBuf>,
     active_32: Option<PathBuf>,
+    /// Manifest from `XR_RUNTIME_JSON`, if set
+    environment_override: Option<PathBuf>,
 }
 
 fn check_active(active_runtime_manifest: &Option<PathBuf>, runtime: &Option<BaseRuntime>) -> bool {
@@ -262,12 +542,12 @@ fn check_active(active_runtime_manifest: &Option<PathBuf>, runtime: &Option<Base
 
 impl WindowsActiveRuntimeData {
     fn new() -> Self {
-        let reg_prefix = make_prefix_key();
-        let active_64 = get_active_runtime_manifest_path(&reg_prefix, make_prefix_key_flags_64());
-        let active_32 = get_active_runtime_manifest_path(&reg_prefix, make_prefix_key_flags_32());
+        let active_64 = active_runtime(&WindowsRegistry, RegistryView::Bits64);
+        let active_32 = active_runtime(&WindowsRegistry, RegistryView::Bits32);
         Self {
             active_64,
             active_32,
+            environment_override: environment_override_manifest(),
         }
     }
 
@@ -275,7 +555,16 @@ impl WindowsActiveRuntimeData {
         let active_64 = check_active(&self.active_64, &runtime.base64);
         let active_32 = check_active(&self.active_32, &runtime.base32);
 
-        ActiveState::from_active_64_and_32(active_64, active_32)
+        let state = ActiveState::from_active_64_and_32(active_64, active_32)
+            .with_environment_override(
+                self.environment_override
+                    .as_deref()
+                    .map(|p| runtime.get_manifests().contains(&p)),
+            );
+        match (state, runtime.enabled) {
+            (ActiveState::NotActive, Some(false)) => ActiveState::Disabled,
+            _ => state,
+        }
     }
 }
 
@@ -287,42 +576,17 @@ impl WindowsPlatform {
     }
 }
 
-fn maybe_runtime(regkey: &RegKey, kv: (String, RegValue)) -> Option<PathBuf> {
-    let (val_name, _) = kv;
-    let v: u32 = regkey.get_value(&val_name).ok()?;
-    if v == 0 {
-        return Some(Path::new(&val_name).to_owned());
-    }
-    None
-}
-
-fn enumerate_reg_runtimes(base_key: &Path, reg_flags: u32) -> Vec<PathBuf> {
-    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    hklm.open_subkey_with_flags(
-        base_key.to_str().unwrap(),
-        reg_flags | KEY_READ | KEY_QUERY_VALUE,
-    )
-    .map(|avail| {
-        let manifest_files = avail.enum_values().filter_map(|x| {
-            let x = x.ok()?;
-            maybe_runtime(&avail, x)
-        });
-        manifest_files.collect()
-    })
-    .unwrap_or_default()
-}
-
-/// Returns any non-fatal errors
-fn manually_add_runtimes(collection: &mut RuntimeCollection) -> Vec<ManifestError> {
-    let mut nonfatal_errors = vec![];
-
-    if let Err(e) = collection.try_add_varjo() {
-        nonfatal_errors.push(e);
-    }
-    if let Err(e) = collection.try_add_winmr() {
-        nonfatal_errors.push(e);
-    }
-    nonfatal_errors
+/// Describe parsed manifests for pairing, given their runtime names
+fn summarize<'a>(runtimes: &'a [BaseRuntime], names: &'a [String]) -> Vec<ManifestSummary<'a>> {
+    runtimes
+        .iter()
+        .zip(names)
+        .map(|(runtime, name)| ManifestSummary {
+            manifest_path: runtime.get_manifest_path(),
+            runtime_name: Some(name.as_str()),
+            library_path: runtime.library_path(),
+        })
+        .collect()
 }
 
 fn process_extra_manifests(
@@ -332,14 +596,15 @@ fn process_extra_manifests(
     let mut paths64 = vec![];
     let mut nonfatal_errors = vec![];
     for path in extra_paths {
-        match get_runtime_bitness(&path) {
-            Ok(bitness) => match bitness {
-                RuntimeBitness::Universal => {
+        match get_runtime_architecture(&path) {
+            Ok(architecture) => match architecture {
+                // Universal: uses the search path to find the right binary per arch
+                None => {
                     paths32.push(path.clone());
                     paths64.push(path);
                 }
-                RuntimeBitness::BitWidth32 => paths32.push(path),
-                RuntimeBitness::BitWidth64 => paths64.push(path),
+                Some(arch) if arch.is_64() => paths64.push(path),
+                Some(_) => paths32.push(path),
             },
             Err(e) => nonfatal_errors.push(e),
         }
@@ -354,26 +619,39 @@ impl Platform for WindowsPlatform {
         &self,
         extra_paths: Box<dyn '_ + Iterator<Item = PathBuf>>,
     ) -> Result<(Vec<Self::PlatformRuntimeType>, Vec<ManifestError>), Error> {
-        let mut collection = RuntimeCollection::default();
-
         let mut nonfatal_errors = vec![];
 
-        let avail_runtimes_key_path = make_prefix_key().join(AVAILABLE_RUNTIMES);
-
-        let mut manifests64 = match make_prefix_key_flags_64() {
-            Some(flags) => enumerate_reg_runtimes(&avail_runtimes_key_path, flags),
-            None => Default::default(),
+        let database = match known_runtimes_path() {
+            Some(path) => KnownRuntimes::load(&path).unwrap_or_else(|e| {
+                nonfatal_errors.push(ManifestError(path, e));
+                KnownRuntimes::embedded().clone()
+            }),
+            None => KnownRuntimes::embedded().clone(),
         };
 
-        let mut manifests32 = match make_prefix_key_flags_32() {
-            Some(flags) => enumerate_reg_runtimes(&avail_runtimes_key_path, flags),
-            None => Default::default(),
+        // Including disabled ones, which are shown as such, and active ones that were never
+        // listed as available
+        let mut enabled: HashMap<PathBuf, bool> = HashMap::new();
+        let mut known = |view| -> Vec<PathBuf> {
+            known_runtimes(&WindowsRegistry, view)
+                .into_iter()
+                .map(|(path, is_enabled)| {
+                    if let Some(is_enabled) = is_enabled {
+                        *enabled.entry(path.clone()).or_default() |= is_enabled;
+                    }
+                    path
+                })
+                .collect()
         };
+        let mut manifests64 = known(RegistryView::Bits64);
+        let mut manifests32 = known(RegistryView::Bits32);
 
         {
             // handle extra paths
 
-            let (extra32, extra64, mut errs) = process_extra_manifests(extra_paths);
+            // including the one from the environment, if any
+            let (extra32, extra64, mut errs) =
+                process_extra_manifests(extra_paths.chain(environment_override_manifest()));
             for path in extra32 {
                 manifests32.push_unique(path);
             }
@@ -383,12 +661,6 @@ impl Platform for WindowsPlatform {
             nonfatal_errors.append(&mut errs);
         }
 
-        let manifest_32_by_parent_dir: HashMap<&Path, &Path, RandomState> = HashMap::from_iter(
-            manifests32
-                .iter()
-                .filter_map(|p| p.parent().map(|parent| (parent, p.as_path()))),
-        );
-
         let mut push_err = |e: Error, path: &Path| {
             eprintln!(
                 "Error creating runtime object for runtime with manifest {}: {}",
@@ -398,32 +670,52 @@ impl Platform for WindowsPlatform {
             nonfatal_errors.push(ManifestError(path.to_owned(), e));
         };
 
-        // Handle all 64-bit runtimes, matching with a 32-bit one if applicable
-        for path in manifests64.iter() {
-            let parent = path.parent().expect("every file has a parent");
-            let counterpart_32 = manifest_32_by_parent_dir.get(parent);
-            if let Err(e) = collection.try_add(Some(path), counterpart_32.map(|p| p.as_ref())) {
-                push_err(e, &path);
-            }
-        }
-        // Handle remaining 32-bit ones
-        for path in manifests32.iter() {
-            // we don't care about errors right now
-            if let Err(e) = collection.try_add(None, Some(path)) {
-                push_err(e, &path);
+        // Parse them all first, to match up the 64-bit and 32-bit manifests of each runtime
+        let mut parse = |paths: &[PathBuf]| -> Vec<BaseRuntime> {
+            paths
+                .iter()
+                .filter_map(|path| BaseRuntime::new(path).map_err(|e| push_err(e, path)).ok())
+                .map(|base| base.with_known_runtimes(&database))
+                .collect()
+        };
+        let (parsed64, parsed32) = (parse(&manifests64), parse(&manifests32));
+        let names64: Vec<String> = parsed64.iter().map(BaseRuntime::get_runtime_name).collect();
+        let names32: Vec<String> = parsed32.iter().map(BaseRuntime::get_runtime_name).collect();
+        let mut collection = RuntimeCollection::new(database);
+        let groups = pair_manifests(
+            &summarize(&parsed64, &names64),
+            &summarize(&parsed32, &names32),
+        );
+        for group in groups {
+            if let Err(e) = collection.try_add(group.manifest_64, group.manifest_32) {
+                let path = group.manifest_64.or(group.manifest_32);
+                push_err(e, path.expect("every group has a manifest"));
             }
         }
 
         // Finally, try adding ones we might not see otherwise
-        nonfatal_errors.extend(manually_add_runtimes(&mut collection));
-
-        Ok((collection.into(), nonfatal_errors))
+        nonfatal_errors.extend(collection.try_add_known_locations());
+
+        let mut runtimes: Vec<WindowsRuntime> = collection.into();
+        for runtime in &mut runtimes {
+            // Enabled if any of its manifests is
+            runtime.enabled = runtime
+                .get_manifests()
+                .into_iter()
+                .filter_map(|p| enabled.get(p).copied())
+                .reduce(|a, b| a || b);
+        }
+        Ok((runtimes, nonfatal_errors))
     }
 
     type PlatformActiveData = WindowsActiveRuntimeData;
 
     fn get_active_runtime_manifests(&self) -> Vec<PathBuf> {
         let data = WindowsActiveRuntimeData::new();
+        if let Some(environment_override) = data.environment_override {
+            // The loader will use only this
+            return vec![environment_override];
+        }
         // OK to move out of data because we just created it for this purpose
         data.active_64
             .into_iter()
@@ -442,6 +734,159 @@ impl Platform for WindowsPlatform {
     ) -> ActiveState {
         active_data.check_runtime(runtime)
     }
+
+    fn diagnose(&self) -> Diagnosis {
+        let mut diagnosis = Diagnosis::default();
+        for check in check_environment() {
+            diagnosis.push(check);
+        }
+
+        let data = WindowsActiveRuntimeData::new();
+        let reg_prefix = make_prefix_key();
+        let arch_64 = if cfg!(target_pointer_width = "64") {
+            Architecture::host()
+        } else {
+            Architecture::X86_64
+        };
+        let views = [
+            (
+                "64-bit",
+                make_prefix_key_flags_64(),
+                &data.active_64,
+                arch_64,
+            ),
+            (
+                "32-bit",
+                make_prefix_key_flags_32(),
+                &data.active_32,
+                Architecture::X86,
+            ),
+        ];
+        for (label, flags, active, arch) in views {
+            if flags.is_none() {
+                // e.g. the 64-bit view on 32-bit Windows
+                continue;
+            }
+            let value_desc = format!(
+                "HKLM\\{}\\{} ({} registry view)",
+                reg_prefix.display(),
+                ACTIVE_RUNTIME,
+                label
+            );
+            match active {
+                // The loader does not look at it at all
+                Some(manifest) if data.environment_override.is_some() => diagnosis.push(
+                    Check::new(
+                        CheckStatus::Info,
+                        format!(
+                            "The {} active runtime is set, but is overridden by {}",
+                            label, RUNTIME_JSON_ENV_VAR
+                        ),
+                    )
+                    .with_detail(format!(
+                        "{} = {}",
+                        value_desc,
+                        manifest.display()
+                    )),
+                ),
+                Some(manifest) => {
+                    diagnosis.push(
+                        Check::new(
+                            CheckStatus::Ok,
+                            format!("The {} active runtime is set", label),
+                        )
+                        .with_detail(format!(
+                            "{} = {}",
+                            value_desc,
+                            manifest.display()
+                        )),
+                    );
+                    for check in check_active_manifest(manifest, &Sysroot::host(), Some(arch)) {
+                        diagnosis.push(check);
+                    }
+                }
+                None if data.environment_override.is_some() => {}
+                None => {
+                    // Only 32-bit apps need a 32-bit runtime, and those are rare.
+                    let status = if arch == Architecture::X86 {
+                        CheckStatus::Warning
+                    } else {
+                        CheckStatus::Failure
+                    };
+                    diagnosis.push(
+                        Check::new(status, format!("No {} active runtime is set", label))
+                            .with_detail(value_desc)
+                            .with_suggestion(
+                                "Choose a runtime with `xrpicker list` and `xrpicker set`",
+                            ),
+                    );
+                }
+            }
+        }
+        if let Some(manifest) = &data.environment_override {
+            // It applies to applications of every architecture: check it for native ones
+            let native_arch = if make_prefix_key_flags_64().is_some() {
+                arch_64
+            } else {
+                Architecture::X86
+            };
+            for check in check_override_manifest(manifest, Some(native_arch)) {
+                diagnosis.push(check);
+            }
+        }
+        diagnosis
+    }
+
+    fn set_runtime_enabled(
+        &self,
+        runtime: &Self::PlatformRuntimeType,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        set_runtimes_enabled(&WindowsRegistry, &runtime.get_manifests(), enabled)
+    }
+
+    fn register_runtime(&self, manifest: &Path) -> Result<(), Error> {
+        // A runtime using the search path has a library for each architecture
+        let views = match get_runtime_architecture(manifest).map_err(|e| e.1)? {
+            None => WindowsRegistry.views(),
+            Some(arch) if arch.is_64() => vec![RegistryView::Bits64],
+            Some(_) => vec![RegistryView::Bits32],
+        };
+        register_runtime(&WindowsRegistry, manifest, &views)
+    }
+
+    fn unregister_runtime(&self, runtime: &Self::PlatformRuntimeType) -> Result<(), Error> {
+        unregister_runtimes(&WindowsRegistry, &runtime.get_manifests())
+    }
+
+    fn find_api_layers(&self) -> Result<(Vec<ApiLayer>, Vec<ManifestError>), Error> {
+        let mut collection = ApiLayerCollection::default();
+        for kind in [ApiLayerKind::Implicit, ApiLayerKind::Explicit] {
+            let manifests: Vec<(PathBuf, bool)> = match environment_explicit_layer_dirs() {
+                // The loader only searches these directories for explicit layers, if set
+                Some(dirs) if kind == ApiLayerKind::Explicit => dirs
+                    .iter()
+                    .flat_map(|dir| layer_manifests_in_dir(dir, kind))
+                    .collect(),
+                _ => registry::api_layers(&WindowsRegistry, kind),
+            };
+            for (path, enabled) in manifests {
+                let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+                collection.add(&path, &canonical, kind, enabled);
+            }
+        }
+        Ok(collection.finish())
+    }
+
+    fn set_api_layer_enabled(&self, layer: &ApiLayer, enabled: bool) -> Result<(), Error> {
+        if layer.kind() != ApiLayerKind::Implicit {
+            return Err(Error::ApiLayerError(format!(
+                "{} is an explicit layer: it is only loaded when requested",
+                layer.name()
+            )));
+        }
+        registry::set_api_layer_enabled(&WindowsRegistry, layer.manifest_path(), enabled)
+    }
 }
 
 pub fn make_platform() -> WindowsPlatform {
@@ -13,7 +13,8 @@ use eframe::{
 
 use itertools::Itertools;
 use xrpicker::{
-    make_platform, platform::PlatformRuntime, AppState, Error, PersistentAppState, Platform,
+    layer::ApiLayerKind, make_platform, platform::PlatformRuntime, ActivationStrategy, ActiveState,
+    AppState, Error, PersistentAppState, Platform, RUNTIME_JSON_ENV_VAR,
 };
 
 // const ICON_32: &[u8; 542] = include_bytes!("../assets/icon/icon32.png");
@@ -115,10 +116,47 @@ trait EguiAppState<T: Platform> {
     /// Add the non-fatal errors from manifest parsing to the UI
     fn add_non_fatal_errors_listing(&self, ui: &mut egui::Ui);
 
-    /// Adds a grid with the runtimes to the given `egui::Ui`, handling "make active" button presses.
+    /// Add a notice if the active runtime is overridden by the environment
+    fn add_environment_override_notice(&self, platform: &T, ui: &mut egui::Ui);
+
+    /// Add a choice of how to make runtimes active, if the platform has more than one way
+    fn add_activation_strategy_picker(
+        &self,
+        platform: &T,
+        ui: &mut egui::Ui,
+        strategy: &mut ActivationStrategy,
+    );
+
+    /// Add a button to remove the user's own active runtime setting, if they have one, so the
+    /// system-wide one takes effect again. What takes effect becomes the status.
+    ///
+    /// Returns an error (in which case that becomes the new state), or a boolean indicating whether to refresh.
+    fn add_reset_button(&mut self, platform: &T, ui: &mut egui::Ui) -> Result<bool, Error>;
+
+    /// Add the status left by the last change, if any
+    fn add_status(&self, ui: &mut egui::Ui);
+
+    /// Adds a grid with the runtimes to the given `egui::Ui`, handling "make active" button presses
+    /// by making the runtime active in the given way.
     ///
     /// Returns an error (in which case that becomes the new state), or a boolean indicating whether to refresh.
-    fn add_runtime_grid(&self, platform: &T, ui: &mut egui::Ui) -> Result<bool, Error>;
+    fn add_runtime_grid(
+        &self,
+        platform: &T,
+        ui: &mut egui::Ui,
+        strategy: ActivationStrategy,
+    ) -> Result<bool, Error>;
+
+    /// Adds a collapsible grid with the API layers, handling "enable"/"disable" button presses.
+    ///
+    /// Returns an error (in which case that becomes the new state), or a boolean indicating whether to refresh.
+    fn add_api_layer_grid(&self, platform: &T, ui: &mut egui::Ui) -> Result<bool, Error>;
+
+    /// Adds a collapsible list of changes to the active runtime, handling "undo" and "restore"
+    /// button presses.
+    ///
+    /// Returns an error (in which case that becomes the new state), or a boolean indicating whether to refresh.
+    fn add_activation_history(&self, platform: &T, ui: &mut egui::Ui) -> Result<bool, Error>;
 }
 
 impl<T: Platform> EguiAppState<T> for AppState<T> {
@@ -136,7 +174,92 @@ impl<T: Platform> EguiAppState<T> for AppState<T> {
         );
     }
 
-    fn add_runtime_grid(&self, platform: &T, ui: &mut egui::Ui) -> Result<bool, Error> {
+    fn add_environment_override_notice(&self, platform: &T, ui: &mut egui::Ui) {
+        let overridden = self.runtimes.iter().any(|r| {
+            matches!(
+                platform.get_runtime_active_state(r, &self.active_data),
+                ActiveState::ActiveByEnvironment
+            )
+        });
+        if overridden {
+            ui.label(format!(
+                "Note: the {} environment variable is set, so applications started from the same environment as this program will use the runtime it selects, no matter which runtime is active.",
+                RUNTIME_JSON_ENV_VAR
+            ));
+        }
+    }
+
+    fn add_activation_strategy_picker(
+        &self,
+        platform: &T,
+        ui: &mut egui::Ui,
+        strategy: &mut ActivationStrategy,
+    ) {
+        let strategies = platform.activation_strategies();
+        if strategies.len() < 2 {
+            return;
+        }
+        if !strategies.contains(strategy) {
+            *strategy = ActivationStrategy::default();
+        }
+        ui.horizontal(|ui| {
+            ui.label("Make runtimes active with:");
+            egui::ComboBox::from_id_source("activation_strategy")
+                .selected_text(strategy.to_string())
+                .show_ui(ui, |ui| {
+                    for choice in strategies {
+                        ui.selectable_value(strategy, choice, choice.to_string());
+                    }
+                });
+        });
+        if *strategy == ActivationStrategy::Environment {
+            ui.label(format!(
+                "Note: this sets {} for applications in your next login session, and overrides the active runtime there.",
+                RUNTIME_JSON_ENV_VAR
+            ));
+        }
+    }
+
+    fn add_reset_button(&mut self, platform: &T, ui: &mut egui::Ui) -> Result<bool, Error> {
+        if !platform.has_user_active_runtime(&self.active_data) {
+            return Ok(false);
+        }
+        let clicked = ui
+            .button("Reset to system default")
+            .on_hover_text("Remove your own active runtime setting (keeping a backup), so the system-wide one takes effect again")
+            .clicked();
+        if !clicked {
+            return Ok(false);
+        }
+        self.status = match platform.clear_user_active_runtime() {
+            Ok(Some((file, _))) => Some(format!(
+                "Reset to the system default: {} is now in effect.",
+                file.display()
+            )),
+            Ok(None) => Some(
+                "Reset to the system default: there is no system-wide active runtime, so none is active now."
+                    .to_owned(),
+            ),
+            Err(e) => {
+                eprintln!("error in clear_user_active_runtime: {:?}", e);
+                return Err(e);
+            }
+        };
+        Ok(true)
+    }
+
+    fn add_status(&self, ui: &mut egui::Ui) {
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+    }
+
+    fn add_runtime_grid(
+        &self,
+        platform: &T,
+        ui: &mut egui::Ui,
+        strategy: ActivationStrategy,
+    ) -> Result<bool, Error> {
         // The closure this calls returns true if we should refresh the list
         egui::containers::ScrollArea::horizontal()
             .show(ui, |ui| {
@@ -156,20 +279,64 @@ impl<T: Platform> EguiAppState<T> for AppState<T> {
                         for runtime in &self.runtimes {
                             let runtime_active_state =
                                 platform.get_runtime_active_state(runtime, &self.active_data);
-                            if runtime_active_state.should_provide_make_active_button() {
-                                if ui.button("Make active").clicked() {
-                                    if let Err(e) = runtime.make_active() {
+                            let enabled = runtime.get_enabled();
+                            ui.vertical(|ui| -> Result<(), Error> {
+                                if runtime_active_state.should_provide_make_active_button()
+                                    && ui.button("Make active").clicked()
+                                {
+                                    if let Err(e) = runtime.make_active_with_strategy(strategy) {
                                         eprintln!("error in make_active: {:?}", e);
                                         return Err(e);
                                     }
                                     repopulate = true;
                                 }
-                            } else {
-                                ui.label("");
-                            }
+                                if let Some(enabled) = enabled {
+                                    let label = if enabled { "Disable" } else { "Enable" };
+                                    if ui
+                                        .button(label)
+                                        .on_hover_text("Change whether it is enabled in the list of available runtimes")
+                                        .clicked()
+                                    {
+                                        if let Err(e) = platform.set_runtime_enabled(runtime, !enabled) {
+                                            eprintln!("error in set_runtime_enabled: {:?}", e);
+                                            return Err(e);
+                                        }
+                                        repopulate = true;
+                                    }
+                                }
+                                Ok(())
+                            })
+                            .inner?;
                             ui.label(runtime.get_runtime_name());
                             ui.label(format!("{}", runtime_active_state));
-                            ui.label(runtime.describe());
+                            let problems = runtime.get_problems();
+                            let architectures = runtime.get_architectures();
+                            let version = runtime.get_version();
+                            if problems.is_empty()
+                                && architectures.is_empty()
+                                && version.is_none()
+                            {
+                                ui.label(runtime.describe());
+                            } else {
+                                ui.vertical(|ui| {
+                                    ui.label(runtime.describe());
+                                    if !architectures.is_empty() {
+                                        ui.label(format!(
+                                            "Architecture: {}",
+                                            architectures.iter().join(", ")
+                                        ));
+                                    }
+                                    if let Some(version) = &version {
+                                        ui.label(format!("Version: {}", version));
+                                    }
+                                    for problem in &problems {
+                                        ui.colored_label(
+                                            ui.visuals().warn_fg_color,
+                                            format!("⚠ {}", problem),
+                                        );
+                                    }
+                                });
+                            }
                             ui.end_row();
                         }
                         Ok(repopulate)
@@ -178,6 +345,116 @@ impl<T: Platform> EguiAppState<T> for AppState<T> {
             })
             .inner
     }
+
+    fn add_api_layer_grid(&self, platform: &T, ui: &mut egui::Ui) -> Result<bool, Error> {
+        let num_implicit = self
+            .api_layers
+            .iter()
+            .filter(|layer| layer.kind() == ApiLayerKind::Implicit && layer.is_enabled())
+            .count();
+        egui::CollapsingHeader::new(format!(
+            "API layers ({} implicit layer(s) enabled)",
+            num_implicit
+        ))
+        .id_source("api_layers")
+        .show(ui, |ui| {
+            ui.label("Implicit layers are loaded by every application, so a broken one can break them all.");
+            #[cfg(unix)]
+            ui.label("System-wide layers are disabled for your user only, starting at your next login.");
+            egui::Grid::new("api_layers")
+                .striped(true)
+                .num_columns(4)
+                .show(ui, |ui| -> Result<bool, Error> {
+                    let mut repopulate = false;
+                    ui.label(""); // for button
+                    ui.label(egui::RichText::new("Layer Name").size(TABLE_HEADER_TEXT_SIZE));
+                    ui.label(egui::RichText::new("State").size(TABLE_HEADER_TEXT_SIZE));
+                    ui.label(egui::RichText::new("Details").size(TABLE_HEADER_TEXT_SIZE));
+                    ui.end_row();
+
+                    for layer in &self.api_layers {
+                        let state = match (layer.kind(), layer.is_enabled()) {
+                            (ApiLayerKind::Explicit, _) => {
+                                ui.label("");
+                                "Explicit"
+                            }
+                            (ApiLayerKind::Implicit, enabled) => {
+                                let label = if enabled { "Disable" } else { "Enable" };
+                                if ui.button(label).clicked() {
+                                    if let Err(e) = platform.set_api_layer_enabled(layer, !enabled)
+                                    {
+                                        eprintln!("error in set_api_layer_enabled: {:?}", e);
+                                        return Err(e);
+                                    }
+                                    repopulate = true;
+                                }
+                                if enabled {
+                                    "Implicit, enabled"
+                                } else {
+                                    "Implicit, disabled"
+                                }
+                            }
+                        };
+                        ui.label(layer.name());
+                        ui.label(state);
+                        match layer.description() {
+                            Some(description) => {
+                                ui.label(format!("{}\n{}", description, layer.describe()))
+                            }
+                            None => ui.label(layer.describe()),
+                        };
+                        ui.end_row();
+                    }
+                    Ok(repopulate)
+                })
+                .inner
+        })
+        .body_returned
+        .unwrap_or(Ok(false))
+    }
+
+    fn add_activation_history(&self, platform: &T, ui: &mut egui::Ui) -> Result<bool, Error> {
+        if self.activation_history.is_empty() {
+            return Ok(false);
+        }
+        egui::CollapsingHeader::new(format!(
+            "Activation history ({} change(s))",
+            self.activation_history.len()
+        ))
+        .id_source("activation_history")
+        .show(ui, |ui| -> Result<bool, Error> {
+            let mut repopulate = false;
+            if ui.button("Undo last switch").clicked() {
+                if let Err(e) = platform.undo_last_activation() {
+                    eprintln!("error in undo_last_activation: {:?}", e);
+                    return Err(e);
+                }
+                repopulate = true;
+            }
+            egui::Grid::new("activation_history")
+                .striped(true)
+                .num_columns(2)
+                .show(ui, |ui| -> Result<(), Error> {
+                    // Newest first, since those are the ones people want
+                    for (i, record) in self.activation_history.iter().enumerate().rev() {
+                        if ui.button("Restore").clicked() {
+                            if let Err(e) = platform.restore_activation(i) {
+                                eprintln!("error in restore_activation: {:?}", e);
+                                return Err(e);
+                            }
+                            repopulate = true;
+                        }
+                        ui.label(record.to_string());
+                        ui.end_row();
+                    }
+                    Ok(())
+                })
+                .inner?;
+            Ok(repopulate)
+        })
+        .body_returned
+        .unwrap_or(Ok(false))
+    }
 }
 
 /// The app-wide action to take, based on the options in the header.
@@ -296,7 +573,25 @@ impl<T: Platform> GuiView<T> for AppState<T> {
         // Central panel must come last
         let should_refresh = header_action.should_refresh(&new_extra_paths)
             || egui::CentralPanel::default()
-                .show(ctx, |ui| self.add_runtime_grid(platform, ui))
+                .show(ctx, |ui| -> Result<bool, Error> {
+                    self.add_environment_override_notice(platform, ui);
+                    self.add_activation_strategy_picker(
+                        platform,
+                        ui,
+                        &mut persistent_state.activation_strategy,
+                    );
+                    let reset = self.add_reset_button(platform, ui)?;
+                    self.add_status(ui);
+                    let repopulate =
+                        self.add_runtime_grid(platform, ui, persistent_state.activation_strategy)?;
+                    let repopulate = self.add_api_layer_grid(platform, ui)? || repopulate;
+                    let repopulate = self.add_activation_history(platform, ui)? || repopulate;
+                    if repopulate {
+                        // Some other change, which the status is not about
+                        self.status = None;
+                    }
+                    Ok(reset || repopulate)
+                })
                 .inner?; // get at the nested closure's return value (whether to repopulate), after handling errors.
 
         persistent_state.append_new_extra_paths(new_extra_paths);